@@ -0,0 +1,63 @@
+extern crate bio_utils;
+extern crate last_tiling;
+use bio_utils::fasta;
+use last_tiling::mapper::{Mapper, MapperConfig};
+use last_tiling::EncodedRead;
+use std::collections::{HashMap, HashSet};
+// Compare the built-in aligner with LAST.
+// Usage: compare_builtin_aligner <reads> <reference> <LAST alignments>
+// Output: Read ID, # of units encoded by LAST, # of units by the built-in aligner, Jaccard index.
+fn main() -> std::io::Result<()> {
+    let args: Vec<_> = std::env::args().collect();
+    let reads = fasta::parse_into_vec(&args[1])?;
+    let reference = fasta::parse_into_vec(&args[2])?;
+    let last_alns = last_tiling::parse_alignment_file(&args[3])?;
+    let builtin_alns = Mapper::new(&reference, MapperConfig::default()).map_reads(&reads);
+    eprintln!(
+        "LAST:{}\tBuiltin:{} alignments",
+        last_alns.len(),
+        builtin_alns.len()
+    );
+    let contigs = last_tiling::Contigs::new(reference);
+    let by_last = to_units(&last_tiling::encoding(&reads, &contigs, &last_alns));
+    let by_builtin = to_units(&last_tiling::encoding(&reads, &contigs, &builtin_alns));
+    let empty = HashSet::new();
+    let mut jaccards = vec![];
+    println!("ID\tLAST\tBuiltin\tJaccard");
+    for read in reads.iter() {
+        let last = by_last.get(read.id()).unwrap_or(&empty);
+        let builtin = by_builtin.get(read.id()).unwrap_or(&empty);
+        let union = last.union(builtin).count();
+        let jaccard = if union == 0 {
+            1.
+        } else {
+            last.intersection(builtin).count() as f64 / union as f64
+        };
+        println!(
+            "{}\t{}\t{}\t{:.3}",
+            read.id(),
+            last.len(),
+            builtin.len(),
+            jaccard
+        );
+        jaccards.push(jaccard);
+    }
+    let mean = jaccards.iter().sum::<f64>() / jaccards.len().max(1) as f64;
+    eprintln!("Mean Jaccard index:{:.3}", mean);
+    Ok(())
+}
+
+fn to_units(reads: &[EncodedRead]) -> HashMap<String, HashSet<(u16, u16, bool)>> {
+    reads
+        .iter()
+        .map(|read| {
+            let units: HashSet<_> = read
+                .seq()
+                .iter()
+                .filter_map(|unit| unit.encode())
+                .map(|e| (e.contig, e.unit, e.is_forward))
+                .collect();
+            (read.id().to_string(), units)
+        })
+        .collect()
+}
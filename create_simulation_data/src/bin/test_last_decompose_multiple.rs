@@ -97,7 +97,16 @@ fn benchmark(
     );
     let pred = {
         let answer = Some(answer.as_slice());
-        gibbs_sampling(&data, &label, answer, &forbidden, &DEFAULT_ALN, config)
+        let params = last_decompose::DecomposeParams::default();
+        gibbs_sampling(
+            &data,
+            &label,
+            answer,
+            &forbidden,
+            &DEFAULT_ALN,
+            config,
+            &params,
+        )
     };
     debug!("Index1\tIndex2\tDist");
     let dists: Vec<Vec<_>> = (0..clusters)
@@ -1,4 +1,4 @@
-use last_decompose::create_windows;
+use last_decompose::{create_windows, DecomposeParams};
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug")).init();
     let len = 4000;
@@ -8,7 +8,7 @@ fn main() {
             _ => 100,
         })
         .collect();
-    for (c, s, e) in create_windows(0, len, &covs) {
+    for (c, s, e) in create_windows(0, len, &covs, &DecomposeParams::default()) {
         println!("{}:{}-{}", c, s, e);
     }
 }
@@ -16,6 +16,16 @@ pub struct Node {
     pub cluster: u8,
     pub window_position: usize,
     pub is_forward: bool,
+    // The posterior probability on each cluster in the window.
+    #[serde(default)]
+    pub posterior: Vec<f64>,
+    // The stability of the cluster over the seeds, see `consensus`.
+    #[serde(default = "default_stability")]
+    pub stability: f64,
+}
+
+fn default_stability() -> f64 {
+    1.
 }
 
 impl ChunkedRead {
@@ -68,6 +78,8 @@ impl ChunkedRead {
                     cluster: entry.assignment,
                     window_position: entry.window,
                     is_forward,
+                    posterior: entry.posterior.clone(),
+                    stability: entry.stability,
                 }
             })
             .collect();
@@ -701,6 +701,40 @@ impl<'a> DitchGraph<'a, 'a> {
         (seg, edges)
     }
     pub fn simple_path(&self) -> (Vec<u8>, bool) {
+        let (path, is_circular) = self.walk_simple_path();
+        let mut seq = String::new();
+        for &(node, position, edge) in path.iter() {
+            if let Some((edge, is_forward)) = edge {
+                if is_forward {
+                    seq += edge;
+                } else {
+                    seq += &revcmp(edge);
+                }
+            }
+            match position {
+                Position::Head => seq += &self.nodes[node].nodes[0].seq,
+                Position::Tail => {
+                    let s = &self.nodes[node].nodes[0].seq;
+                    seq += &revcmp(s);
+                }
+            };
+        }
+        (seq.as_bytes().to_vec(), is_circular)
+    }
+    /// The nodes on the simple path, as (window position, cluster, length of the node).
+    pub fn simple_path_nodes(&self) -> Vec<(u64, u64, usize)> {
+        let (path, _) = self.walk_simple_path();
+        path.iter()
+            .map(|&(node, _, _)| {
+                let node = &self.nodes[node];
+                (node.window_position, node.cluster, node.nodes[0].seq.len())
+            })
+            .collect()
+    }
+    // Walk along the simple path. Return the nodes with the positions where we enter them,
+    // and the edges through which we enter them.
+    #[allow(clippy::type_complexity)]
+    fn walk_simple_path(&self) -> (Vec<(usize, Position, Option<(&'a str, bool)>)>, bool) {
         let (start, position, _max, is_circular) = self
             .nodes
             .iter()
@@ -737,17 +771,10 @@ impl<'a> DitchGraph<'a, 'a> {
         // debug!("{}-{}(Occ:{})", start, position, max);
         let mut node = start;
         let mut position = position;
-        let mut seq = String::new();
+        let mut path = vec![(node, position, None)];
         let mut arrived = vec![false; self.nodes.len()];
         loop {
             arrived[node] = true;
-            match position {
-                Position::Head => seq += &self.nodes[node].nodes[0].seq,
-                Position::Tail => {
-                    let s = &self.nodes[node].nodes[0].seq;
-                    seq += &revcmp(s);
-                }
-            };
             position = !position;
             let selected_edge = self.nodes[node]
                 .edges
@@ -761,15 +788,11 @@ impl<'a> DitchGraph<'a, 'a> {
             };
             assert_eq!(selected_edge.from, node);
             assert_eq!(selected_edge.from_position, position);
-            let &(ref edge, is_forward) = selected_edge.edges.first().unwrap();
-            if is_forward {
-                seq += &edge;
-            } else {
-                seq += &revcmp(&edge);
-            }
+            let &(edge, is_forward) = selected_edge.edges.first().unwrap();
             node = selected_edge.to;
             position = selected_edge.to_position;
+            path.push((node, position, Some((edge, is_forward))));
         }
-        (seq.as_bytes().to_vec(), is_circular)
+        (path, is_circular)
     }
 }
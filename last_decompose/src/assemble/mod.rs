@@ -1,6 +1,7 @@
 mod chunked_read;
 pub mod correct_reads;
 mod ditch_graph;
+use super::contacts::Contacts;
 use super::Entry;
 pub use chunked_read::ChunkedRead;
 use std::collections::{HashMap, HashSet};
@@ -66,11 +67,26 @@ type AssembleResult = (
     Vec<(String, Option<u8>)>,
     gfa::GFA,
     Vec<bio_utils::fasta::Record>,
+    Vec<Vec<f64>>,
 );
-pub fn assemble_reads(reads: &[ChunkedRead], k: usize, thr: usize) -> AssembleResult {
+fn corrected_reads(reads: &[ChunkedRead]) -> Vec<ChunkedRead> {
     let mut reads = reads.to_vec();
     correct_reads::correct_reads(&mut reads, 2);
     correct_reads::correct_reads(&mut reads, 2);
+    reads
+}
+
+/// Assemble the reads by the colour expansion on the de Bruijn graph of `k`-mers of the nodes.
+/// Nodes occurring less than `thr` are removed. The colours are expanded along `contacts` as well
+/// (see `contacts::expand_colors`).
+pub fn assemble_reads(
+    reads: &[ChunkedRead],
+    contacts: &Contacts,
+    k: usize,
+    thr: usize,
+    contact_thr: f64,
+) -> AssembleResult {
+    let mut reads = corrected_reads(reads);
     // Determine SVs which are merged into the backgrounds.
     let backgrounds = major_component(&reads);
     debug!("backgrounds:{:?}", backgrounds);
@@ -182,6 +198,23 @@ pub fn assemble_reads(reads: &[ChunkedRead], k: usize, thr: usize) -> AssembleRe
             (id, cluster)
         })
         .collect();
+    let mut clusters: Vec<_> = assignments.iter().map(|x| x.1).collect();
+    let moved = super::contacts::expand_colors(&reads, &mut clusters, contacts, contact_thr);
+    debug!("{} moves by {} contacts", moved, contacts.len());
+    let assignments: Vec<_> = assignments
+        .into_iter()
+        .zip(clusters)
+        .map(|((id, _), cluster)| (id, cluster))
+        .collect();
+    assemble_clusters(&reads, assignments)
+}
+
+/// Rename the clusters in `assignments` into 0, 1, 2, ..., and assemble the reads in each cluster.
+/// `assignments` should be in the same order as `reads`.
+pub fn assemble_clusters(
+    reads: &[ChunkedRead],
+    assignments: Vec<(String, Option<u8>)>,
+) -> AssembleResult {
     // Rename assignments.
     let map: HashMap<_, _> = {
         let clusters: HashSet<_> = assignments.iter().filter_map(|&(_, x)| x).collect();
@@ -256,7 +289,154 @@ pub fn assemble_reads(reads: &[ChunkedRead], k: usize, thr: usize) -> AssembleRe
         .collect();
     header.extend(records.into_iter().flat_map(|e| e.1));
     let gfa = gfa::GFA::from_records(header);
-    (assignments, gfa, contigs)
+    let posteriors = posteriors_on_clusters(reads, &assignments, map.len());
+    (assignments, gfa, contigs, posteriors)
+}
+
+/// Convert the posterior probabilities in each window into the posterior probabilities
+/// on the final clusters. Each cluster in a window is mapped to the final cluster
+/// which the majority of its reads are assigned to. Then, the posterior of a read is
+/// the average of the posterior mass on each final cluster over its nodes.
+/// Labeled reads and reads without any valid posterior have one-hot posteriors.
+fn posteriors_on_clusters(
+    reads: &[ChunkedRead],
+    assignments: &[(String, Option<u8>)],
+    cluster_num: usize,
+) -> Vec<Vec<f64>> {
+    let cluster_num = cluster_num.max(1);
+    let mut counts: HashMap<(usize, u8), HashMap<u8, u32>> = HashMap::new();
+    for (read, &(_, asn)) in reads.iter().zip(assignments.iter()) {
+        if let Some(asn) = asn {
+            for node in read.nodes.iter() {
+                let key = (node.window_position, node.cluster);
+                *counts.entry(key).or_default().entry(asn).or_default() += 1;
+            }
+        }
+    }
+    let to_final: HashMap<(usize, u8), u8> = counts
+        .into_iter()
+        .filter_map(|(key, count)| {
+            let (&cl, _) = count
+                .iter()
+                .max_by_key(|&(&cl, &c)| (c, std::cmp::Reverse(cl)))?;
+            Some((key, cl))
+        })
+        .collect();
+    let one_hot = |asn: u8| -> Vec<f64> {
+        let mut posterior = vec![0.; cluster_num];
+        posterior[asn as usize] = 1.;
+        posterior
+    };
+    reads
+        .iter()
+        .zip(assignments.iter())
+        .map(|(read, &(_, asn))| {
+            let asn = match asn {
+                Some(asn) => asn,
+                None => return vec![0.; cluster_num],
+            };
+            if read.label.is_some() {
+                return one_hot(asn);
+            }
+            let mut posterior = vec![0.; cluster_num];
+            for node in read.nodes.iter() {
+                let window = node.window_position;
+                for (cl, &p) in node.posterior.iter().enumerate() {
+                    if let Some(&to) = to_final.get(&(window, cl as u8)) {
+                        posterior[to as usize] += p;
+                    }
+                }
+            }
+            let sum = posterior.iter().sum::<f64>();
+            if sum > 0. {
+                posterior.iter_mut().for_each(|p| *p /= sum);
+                posterior
+            } else {
+                one_hot(asn)
+            }
+        })
+        .collect()
+}
+
+pub const CONTIG_PREFIX: &str = "tig_";
+/// Return the cluster id of a contig produced by `assemble_reads`.
+pub fn cluster_of_contig(id: &str) -> Option<u8> {
+    if id.starts_with(CONTIG_PREFIX) {
+        id[CONTIG_PREFIX.len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Return true if the contig is circular.
+pub fn is_circular_contig(contig: &bio_utils::fasta::Record) -> bool {
+    contig
+        .desc()
+        .map(|desc| desc.split_whitespace().any(|e| e == "is_circular=true"))
+        .unwrap_or(false)
+}
+
+/// The coverage of a reconstructed structure along its path on the ditch graph.
+#[derive(Debug, Clone)]
+pub struct PathCoverage {
+    pub cluster: u8,
+    /// The total length of the nodes on the path.
+    pub path_length: usize,
+    /// The bases of each read on the path, as (index of the read, bases).
+    pub read_bases: Vec<(usize, usize)>,
+}
+
+/// Compute the coverage of each final cluster along the path used to spell its contig.
+/// `assignments` should be the result of `assemble_reads` on the same reads.
+/// Clusters too small to be assembled are skipped.
+pub fn path_coverages(
+    reads: &[ChunkedRead],
+    assignments: &[(String, Option<u8>)],
+) -> Vec<PathCoverage> {
+    let reads = corrected_reads(reads);
+    let mut clusters: HashMap<u8, Vec<usize>> = HashMap::new();
+    for (idx, (_, asn)) in assignments.iter().enumerate() {
+        if let Some(asn) = asn {
+            clusters.entry(*asn).or_default().push(idx);
+        }
+    }
+    let mut clusters: Vec<_> = clusters.into_iter().collect();
+    clusters.sort_by_key(|x| x.0);
+    clusters
+        .into_iter()
+        .filter(|(_, members)| members.len() >= 10)
+        .filter_map(|(cluster, members)| {
+            let members_reads: Vec<_> = members.iter().map(|&i| &reads[i]).collect();
+            let mut graph = ditch_graph::DitchGraph::new(&members_reads);
+            graph.collapse_buddle();
+            let path: HashMap<_, _> = graph
+                .simple_path_nodes()
+                .into_iter()
+                .map(|(w, cl, len)| ((w as usize, cl as u8), len))
+                .collect();
+            let path_length = path.values().sum::<usize>();
+            if path_length == 0 {
+                return None;
+            }
+            let read_bases = members
+                .iter()
+                .map(|&i| {
+                    let bases = reads[i]
+                        .nodes
+                        .iter()
+                        .filter(|n| path.contains_key(&(n.window_position, n.cluster)))
+                        .map(|n| n.seq.len())
+                        .sum::<usize>();
+                    (i, bases)
+                })
+                .collect();
+            Some(PathCoverage {
+                cluster,
+                path_length,
+                read_bases,
+            })
+        })
+        .collect()
 }
 
 fn reads_to_contig(cl: usize, reads: &[&ChunkedRead]) -> Option<bio_utils::fasta::Record> {
@@ -279,7 +459,7 @@ fn reads_to_contig(cl: usize, reads: &[&ChunkedRead]) -> Option<bio_utils::fasta
     } else {
         None
     };
-    let id = format!("tig_{:04}", cl);
+    let id = format!("{}{:04}", CONTIG_PREFIX, cl);
     debug!("{}-{}len is_circular={}", id, seq.len(), is_circular);
     Some(bio_utils::fasta::Record::with_data(&id, &desc, &seq))
 }
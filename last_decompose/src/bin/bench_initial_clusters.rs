@@ -0,0 +1,43 @@
+extern crate last_decompose;
+extern crate rand;
+extern crate rand_xoshiro;
+use last_decompose::find_breakpoint::{merge_critical_regions, merge_critical_regions_naive};
+use last_decompose::simulate::repeat_genome;
+use last_decompose::{critical_regions, DecomposeParams, ERead};
+use rand::SeedableRng;
+use rand_xoshiro::Xoshiro256StarStar;
+use std::time::Instant;
+// Benchmark of merging critical regions into the initial clusters on a synthetic genome
+// with many repeats(see `last_decompose::simulate`).
+// Usage: cargo run --release --features simulate --bin bench_initial_clusters --
+//        [# of repeat pairs] [coverage] [seed]
+// Output: # of reads, # of critical regions, # of clusters,
+// elapsed time(ms) of the naive and the incremental merges.
+
+fn main() {
+    let args: Vec<_> = std::env::args().collect();
+    let num_repeats: usize = args.get(1).and_then(|x| x.parse().ok()).unwrap_or(100);
+    let coverage: usize = args.get(2).and_then(|x| x.parse().ok()).unwrap_or(60);
+    let seed: u64 = args.get(3).and_then(|x| x.parse().ok()).unwrap_or(3_214);
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(seed);
+    let (contigs, reads) = repeat_genome(&mut rng, num_repeats, coverage);
+    let params = DecomposeParams::default();
+    let ereads: Vec<_> = reads.iter().map(ERead::new_no_gapfill).collect();
+    let crs = critical_regions(&ereads, &contigs, &[], &params);
+    let start = Instant::now();
+    let naive = merge_critical_regions_naive(&ereads, crs.clone(), &params);
+    let naive_time = start.elapsed();
+    let start = Instant::now();
+    let incremental = merge_critical_regions(&ereads, crs.clone(), &params);
+    let incremental_time = start.elapsed();
+    assert_eq!(naive, incremental);
+    println!("reads\tcritical_regions\tclusters\tnaive\tincremental");
+    println!(
+        "{}\t{}\t{}\t{}\t{}",
+        reads.len(),
+        crs.len(),
+        incremental.len(),
+        naive_time.as_millis(),
+        incremental_time.as_millis()
+    );
+}
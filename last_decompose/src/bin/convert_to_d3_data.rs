@@ -12,7 +12,15 @@ fn main() -> std::io::Result<()> {
         serde_json::de::from_reader(std::fs::File::open(&args[1]).map(BufReader::new)?).unwrap();
     let reads: Vec<last_tiling::EncodedRead> =
         serde_json::de::from_reader(std::fs::File::open(&args[2]).map(BufReader::new)?).unwrap();
-    let clusters = last_decompose::initial_clusters(&reads, &contigs);
+    let params = match args.get(3) {
+        Some(file) => last_decompose::DecomposeParams::from_file(file)?,
+        None => last_decompose::DecomposeParams::default(),
+    };
+    let repeats = match args.get(4) {
+        Some(file) => last_tiling::repeat::open(file)?,
+        None => vec![],
+    };
+    let clusters = last_decompose::initial_clusters(&reads, &contigs, &repeats, &params);
 
     let summary = last_decompose::d3_data::convert_to_d3_data(&contigs, &reads, &clusters);
     let stdout = std::io::stdout();
@@ -1,7 +1,7 @@
-use last_decompose::create_windows;
+use last_decompose::{create_windows, DecomposeParams};
 fn main() {
     let covs = vec![10; 5000];
-    for range in create_windows(0, 5000, &covs) {
+    for range in create_windows(0, 5000, &covs, &DecomposeParams::default()) {
         eprintln!("{:?}", range);
     }
 }
@@ -1,3 +1,31 @@
+/// The labels of the clusters of a clustering, maximizing the number of items having the same
+/// labels in another clustering. `counts[a][b]` is the number of items in the `a`-th cluster of
+/// the other clustering and in the `b`-th cluster of this clustering, which gets the label
+/// `relabel(counts, k)[b]`, where `k` is the number of clusters of this clustering.
+/// Clusters without matches get the labels unused by the matching.
+pub fn relabel(counts: &[Vec<usize>], k: usize) -> Vec<usize> {
+    let graph: Vec<Vec<(usize, f64)>> = counts
+        .iter()
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .filter(|&(_, &c)| c > 0)
+                .map(|(b, &c)| (b, c as f64))
+                .collect()
+        })
+        .collect();
+    let matching = maximum_weight_matching(counts.len(), k, &graph);
+    let mut to: Vec<Option<usize>> = vec![None; k];
+    for (a, b) in matching {
+        to[b] = Some(a);
+    }
+    let used: std::collections::HashSet<usize> = to.iter().filter_map(|&x| x).collect();
+    let mut free = (0..).filter(|l| !used.contains(l));
+    to.into_iter()
+        .map(|x| x.unwrap_or_else(|| free.next().unwrap()))
+        .collect()
+}
+
 // Find maximum weight matching between the given bipartite matching.
 // First, it converts the given graph into flow graph,
 // where the const is the negative weight of each edges,
@@ -0,0 +1,215 @@
+//! Consensus of the clusterings of a window from several seeds.
+//! The labels of each run are aligned to the first run by the maximum weight matching of
+//! the clusters. The co-assignment of two reads is the fraction of the runs putting them into
+//! the same cluster. Each read is assigned to the cluster, in the majority vote of the aligned
+//! runs, with the largest mean co-assignment to the read. The stability of a read is its mean
+//! co-assignment with the other reads in its consensus cluster, and the agreement of a window
+//! is the mean adjusted Rand index between the runs.
+use super::bipartite_matching;
+
+/// The consensus of the runs on a window.
+#[derive(Debug, Clone)]
+pub struct Consensus {
+    pub assignments: Vec<u8>,
+    /// The stability of each read, in [0,1].
+    pub stability: Vec<f64>,
+    /// The mean adjusted Rand index between the runs.
+    pub agreement: f64,
+}
+
+/// The summary of the consensus on a window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowStability {
+    pub window: usize,
+    pub range: (u16, u16, u16),
+    pub seeds: usize,
+    pub agreement: f64,
+    pub mean_stability: f64,
+    /// The stability of each read.
+    pub read_stability: Vec<f64>,
+    /// The number of reads with stability less than `params.stability_thr`.
+    pub unstable_reads: usize,
+    /// Whether the agreement is less than `params.stability_thr`.
+    pub unstable: bool,
+}
+
+impl WindowStability {
+    pub const HEADER: &'static str =
+        "window\tcontig\tstart\tend\tseeds\tagreement\tmean_stability\tunstable_reads\tunstable";
+    /// Set `unstable_reads` and `unstable` by the threshold `thr`.
+    pub fn judge(&mut self, thr: f64) {
+        self.unstable_reads = self.read_stability.iter().filter(|&&s| s < thr).count();
+        self.unstable = self.agreement < thr;
+    }
+}
+
+impl std::fmt::Display for WindowStability {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (contig, start, end) = self.range;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}",
+            self.window,
+            contig,
+            start,
+            end,
+            self.seeds,
+            self.agreement,
+            self.mean_stability,
+            self.unstable_reads,
+            self.unstable
+        )
+    }
+}
+
+/// Relabel `run` to agree with `reference` as much as possible.
+pub fn align_to(reference: &[u8], run: &[u8]) -> Vec<u8> {
+    let k_ref = reference.iter().map(|&x| x as usize + 1).max().unwrap_or(0);
+    let k = run.iter().map(|&x| x as usize + 1).max().unwrap_or(0);
+    let mut counts = vec![vec![0; k]; k_ref];
+    for (&a, &b) in reference.iter().zip(run.iter()) {
+        counts[a as usize][b as usize] += 1;
+    }
+    let to = bipartite_matching::relabel(&counts, k);
+    run.iter().map(|&b| to[b as usize] as u8).collect()
+}
+
+/// The fraction of `runs` putting each pair of reads into the same cluster.
+pub fn co_assignment(runs: &[Vec<u8>]) -> Vec<Vec<f64>> {
+    let len = runs.first().map(|r| r.len()).unwrap_or(0);
+    let mut matrix = vec![vec![0.; len]; len];
+    for run in runs {
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x += (run[i] == run[j]) as u32 as f64;
+            }
+        }
+    }
+    let num = runs.len().max(1) as f64;
+    matrix
+        .iter_mut()
+        .for_each(|row| row.iter_mut().for_each(|x| *x /= num));
+    matrix
+}
+
+/// The adjusted Rand index between two clusterings. It is one if both are identical
+/// up to labels, and zero in expectation if they are independent.
+pub fn adjusted_rand_index(xs: &[u8], ys: &[u8]) -> f64 {
+    let comb2 = |n: usize| (n * n.saturating_sub(1)) as f64 / 2.;
+    let kx = xs.iter().map(|&x| x as usize + 1).max().unwrap_or(0);
+    let ky = ys.iter().map(|&y| y as usize + 1).max().unwrap_or(0);
+    let mut table = vec![vec![0; ky]; kx];
+    for (&x, &y) in xs.iter().zip(ys.iter()) {
+        table[x as usize][y as usize] += 1;
+    }
+    let index: f64 = table.iter().flatten().map(|&n| comb2(n)).sum();
+    let rows: f64 = table.iter().map(|row| comb2(row.iter().sum())).sum();
+    let columns: f64 = (0..ky)
+        .map(|y| comb2(table.iter().map(|row| row[y]).sum()))
+        .sum();
+    let expected = rows * columns / comb2(xs.len()).max(1.);
+    let max = (rows + columns) / 2.;
+    if (max - expected).abs() < f64::EPSILON {
+        1.
+    } else {
+        (index - expected) / (max - expected)
+    }
+}
+
+/// The consensus of `runs`. The first `labels.len()` reads keep their labels, and
+/// no read is assigned to the clusters forbidden for it.
+pub fn consensus(runs: &[Vec<u8>], labels: &[u8], forbidden: &[Vec<u8>]) -> Consensus {
+    let aligned: Vec<_> = runs.iter().map(|run| align_to(&runs[0], run)).collect();
+    let len = runs[0].len();
+    let cluster_num = aligned.iter().flatten().map(|&x| x as usize + 1).max();
+    let cluster_num = cluster_num.unwrap_or(1);
+    let vote: Vec<u8> = (0..len)
+        .map(|i| match labels.get(i) {
+            Some(&l) => l,
+            None => {
+                let mut counts = vec![0; cluster_num];
+                aligned.iter().for_each(|run| counts[run[i] as usize] += 1);
+                let max = counts.iter().max().unwrap();
+                counts.iter().position(|c| c == max).unwrap() as u8
+            }
+        })
+        .collect();
+    let matrix = co_assignment(&aligned);
+    // The mean co-assignment of the i-th read to the other reads in each cluster of `asn`.
+    let scores = |i: usize, asn: &[u8]| -> Vec<Option<f64>> {
+        let mut sums = vec![(0., 0); cluster_num];
+        for (j, &cl) in asn.iter().enumerate().filter(|&(j, _)| j != i) {
+            sums[cl as usize].0 += matrix[i][j];
+            sums[cl as usize].1 += 1;
+        }
+        sums.into_iter()
+            .map(|(sum, n)| if n > 0 { Some(sum / n as f64) } else { None })
+            .collect()
+    };
+    let assignments: Vec<u8> = (0..len)
+        .map(|i| {
+            if i < labels.len() {
+                return vote[i];
+            }
+            let forbid = forbidden.get(i).map(|f| f.as_slice()).unwrap_or(&[]);
+            scores(i, &vote)
+                .into_iter()
+                .enumerate()
+                .filter(|&(cl, _)| !forbid.contains(&(cl as u8)))
+                .filter_map(|(cl, s)| s.map(|s| (cl, s)))
+                .fold(None, |best: Option<(usize, f64)>, (cl, s)| match best {
+                    Some((_, t)) if s <= t => best,
+                    _ => Some((cl, s)),
+                })
+                .map(|(cl, _)| cl as u8)
+                .unwrap_or(vote[i])
+        })
+        .collect();
+    let stability: Vec<f64> = (0..len)
+        .map(|i| scores(i, &assignments)[assignments[i] as usize].unwrap_or(1.))
+        .collect();
+    let mut aris = vec![];
+    for (i, xs) in runs.iter().enumerate() {
+        for ys in runs.iter().skip(i + 1) {
+            aris.push(adjusted_rand_index(xs, ys));
+        }
+    }
+    let agreement = if aris.is_empty() {
+        1.
+    } else {
+        aris.iter().sum::<f64>() / aris.len() as f64
+    };
+    Consensus {
+        assignments,
+        stability,
+        agreement,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn consensus_test() {
+        let runs = vec![
+            vec![0, 0, 0, 1, 1, 1],
+            vec![1, 1, 1, 0, 0, 0],
+            vec![0, 0, 1, 1, 1, 1],
+        ];
+        assert_eq!(align_to(&runs[0], &runs[1]), runs[0]);
+        assert!((adjusted_rand_index(&runs[0], &runs[1]) - 1.).abs() < 0.0001);
+        let result = consensus(&runs, &[], &vec![vec![]; 6]);
+        assert_eq!(result.assignments, runs[0]);
+        assert!(result.stability[2] < result.stability[0]);
+        assert!(result.agreement < 1.);
+        let forbidden = vec![vec![], vec![], vec![0], vec![], vec![], vec![]];
+        let result = consensus(&runs, &[], &forbidden);
+        assert_eq!(result.assignments[2], 1);
+    }
+    #[test]
+    fn consensus_seeds_test() {
+        let mut params = crate::DecomposeParams::default();
+        params.set("consensus_seeds", "5").unwrap();
+        assert_eq!(params.consensus_seeds, 5);
+    }
+}
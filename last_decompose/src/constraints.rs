@@ -0,0 +1,296 @@
+//! Constraints on the clustering supplied by users, e.g., from a curated earlier run or
+//! from PCR-validated reads. A constraint file is a TSV with one constraint per line:
+//! `must-link <read> <read>`, `cannot-link <read> <read>`, or `label <read> <structure>`.
+//! Empty lines and lines starting with `#` are skipped.
+//! Each structure name becomes a new label after the labels of the initial clusters, and
+//! it overrides the label from the initial clusters. The label of a must-link component is
+//! given to all the reads in it, and a cannot-link pair forbids the label of a read for the
+//! other. Both kinds of pairs are also used as contacts with positive or negative weights.
+use super::find_union::FindUnion;
+use super::params::invalid_data;
+use super::ERead;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// User-supplied constraints.
+#[derive(Debug, Clone, Default)]
+pub struct Constraints {
+    pub must_links: Vec<(String, String)>,
+    pub cannot_links: Vec<(String, String)>,
+    /// Reads and the names of the structures they come from.
+    pub labels: Vec<(String, String)>,
+}
+
+impl Constraints {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+    pub fn parse(tsv: &str) -> std::io::Result<Self> {
+        let mut constraints = Self::default();
+        for (idx, line) in tsv.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split('\t').collect();
+            if fields.len() != 3 {
+                let why = format!("Line {}: expected 3 columns:{}", idx + 1, line);
+                return Err(invalid_data(why));
+            }
+            let pair = (fields[1].to_string(), fields[2].to_string());
+            match fields[0].to_lowercase().as_str() {
+                "must-link" => constraints.must_links.push(pair),
+                "cannot-link" => constraints.cannot_links.push(pair),
+                "label" => constraints.labels.push(pair),
+                x => {
+                    let why = format!("Line {}: Unknown constraint:{}", idx + 1, x);
+                    return Err(invalid_data(why));
+                }
+            }
+        }
+        Ok(constraints)
+    }
+    pub fn len(&self) -> usize {
+        self.must_links.len() + self.cannot_links.len() + self.labels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The names of the structures, in the order of their labels.
+    pub fn structures(&self) -> Vec<&str> {
+        let names: BTreeSet<_> = self.labels.iter().map(|(_, s)| s.as_str()).collect();
+        names.into_iter().collect()
+    }
+    /// The must-link pairs with weight `weight` and the cannot-link pairs with `-weight`,
+    /// to be added to the contacts.
+    pub fn links(&self, weight: f64) -> Vec<(String, String, f64)> {
+        let must = self.must_links.iter().map(|(r1, r2)| (r1, r2, weight));
+        let cannot = self.cannot_links.iter().map(|(r1, r2)| (r1, r2, -weight));
+        must.chain(cannot)
+            .map(|(r1, r2, w)| (r1.clone(), r2.clone(), w))
+            .collect()
+    }
+    /// Merge the constraints into the output of `initial_clustering`, where the reads
+    /// with labels come first in `dataset`. The labels of the structures start from `first_label`.
+    /// Return the reads with labels first, and their labels.
+    pub fn apply(
+        &self,
+        dataset: Vec<ERead>,
+        labels: Vec<u8>,
+        forbidden: &mut HashMap<String, Vec<u8>>,
+        first_label: u8,
+    ) -> (Vec<ERead>, Vec<u8>) {
+        let index: HashMap<&str, usize> = dataset
+            .iter()
+            .enumerate()
+            .map(|(idx, r)| (r.id(), idx))
+            .collect();
+        let mut current: Vec<Option<u8>> = vec![None; dataset.len()];
+        current
+            .iter_mut()
+            .zip(labels)
+            .for_each(|(c, l)| *c = Some(l));
+        // Labels of the structures.
+        let structures: HashMap<&str, u8> = self
+            .structures()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| (name, first_label + idx as u8))
+            .collect();
+        let (mut overridden, mut unknown) = (0, 0);
+        for (id, name) in self.labels.iter() {
+            match index.get(id.as_str()) {
+                Some(&idx) => {
+                    let label = structures[name.as_str()];
+                    overridden += current[idx].filter(|&l| l != label).is_some() as usize;
+                    current[idx] = Some(label);
+                }
+                None => unknown += 1,
+            }
+        }
+        // Labels of the must-link components.
+        let mut fu = FindUnion::new(dataset.len());
+        let pairs = |pairs: &[(String, String)]| -> Vec<(usize, usize)> {
+            let get = |id: &String| index.get(id.as_str()).copied();
+            pairs
+                .iter()
+                .filter_map(|(r1, r2)| Some((get(r1)?, get(r2)?)))
+                .collect()
+        };
+        let must_links = pairs(&self.must_links);
+        let cannot_links = pairs(&self.cannot_links);
+        unknown += self.must_links.len() + self.cannot_links.len();
+        unknown -= must_links.len() + cannot_links.len();
+        for &(i, j) in must_links.iter() {
+            fu.unite(i, j).unwrap();
+        }
+        let mut components: HashMap<usize, BTreeSet<u8>> = HashMap::new();
+        for (idx, label) in current.iter().enumerate() {
+            if let Some(l) = label {
+                let parent = fu.find(idx).unwrap();
+                components.entry(parent).or_default().insert(*l);
+            }
+        }
+        let (mut propagated, mut conflicts) = (0, 0);
+        for (idx, read) in dataset.iter().enumerate() {
+            let forbid = forbidden
+                .get(read.id())
+                .map(|f| f.as_slice())
+                .unwrap_or(&[]);
+            match components.get(&fu.find(idx).unwrap()) {
+                Some(ls) if ls.len() > 1 => conflicts += 1,
+                Some(ls) if current[idx].is_none() => {
+                    let label = *ls.iter().next().unwrap();
+                    if forbid.contains(&label) {
+                        conflicts += 1;
+                    } else {
+                        current[idx] = Some(label);
+                        propagated += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Forbid the label of the partner of a cannot-link pair.
+        let mut forbids = 0;
+        for &(i, j) in cannot_links.iter() {
+            if current[i].is_some() && current[i] == current[j] {
+                conflicts += 1;
+                continue;
+            }
+            for &(from, to) in &[(i, j), (j, i)] {
+                if let Some(l) = current[from] {
+                    let forb = forbidden.entry(dataset[to].id().to_string()).or_default();
+                    if !forb.contains(&l) {
+                        forb.push(l);
+                        forbids += 1;
+                    }
+                }
+            }
+        }
+        debug!(
+            "Constraints: {} overridden, {} propagated, {} forbidden, {} conflicts, {} unknown reads",
+            overridden, propagated, forbids, conflicts, unknown
+        );
+        let (labeled, unlabeled): (Vec<_>, Vec<_>) = dataset
+            .into_iter()
+            .zip(current)
+            .partition(|(_, l)| l.is_some());
+        let labels = labeled.iter().filter_map(|(_, l)| *l).collect();
+        let dataset = labeled
+            .into_iter()
+            .chain(unlabeled)
+            .map(|(r, _)| r)
+            .collect();
+        (dataset, labels)
+    }
+    /// The constraints violated by `assignments`. Reads without assignments are ignored.
+    /// A label constraint is violated if the read is not in the cluster having
+    /// the most reads of the structure.
+    pub fn violations(&self, assignments: &HashMap<String, u8>) -> Vec<Violation> {
+        let mut violations = vec![];
+        let get = |id: &String| assignments.get(id).copied();
+        for (r1, r2) in self.must_links.iter() {
+            if let (Some(c1), Some(c2)) = (get(r1), get(r2)) {
+                if c1 != c2 {
+                    violations.push(Violation::new("must-link", r1, r2, c1, c2));
+                }
+            }
+        }
+        for (r1, r2) in self.cannot_links.iter() {
+            if let (Some(c1), Some(c2)) = (get(r1), get(r2)) {
+                if c1 == c2 {
+                    violations.push(Violation::new("cannot-link", r1, r2, c1, c2));
+                }
+            }
+        }
+        let mut counts: HashMap<&str, HashMap<u8, usize>> = HashMap::new();
+        for (id, name) in self.labels.iter() {
+            if let Some(c) = get(id) {
+                *counts
+                    .entry(name.as_str())
+                    .or_default()
+                    .entry(c)
+                    .or_default() += 1;
+            }
+        }
+        let majority: HashMap<&str, u8> = counts
+            .into_iter()
+            .filter_map(|(name, counts)| {
+                let (&c, _) = counts
+                    .iter()
+                    .max_by_key(|&(&c, &n)| (n, std::cmp::Reverse(c)))?;
+                Some((name, c))
+            })
+            .collect();
+        for (id, name) in self.labels.iter() {
+            if let (Some(c), Some(&m)) = (get(id), majority.get(name.as_str())) {
+                if c != m {
+                    violations.push(Violation::new("label", id, name, c, m));
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// A constraint violated by the final clusters. For a label constraint,
+/// `read2` is the name of the structure and `cluster2` is its major cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub kind: &'static str,
+    pub read1: String,
+    pub read2: String,
+    pub cluster1: u8,
+    pub cluster2: u8,
+}
+
+impl Violation {
+    pub const HEADER: &'static str = "kind\tread1\tread2\tcluster1\tcluster2";
+    fn new(kind: &'static str, read1: &str, read2: &str, cluster1: u8, cluster2: u8) -> Self {
+        Self {
+            kind,
+            read1: read1.to_string(),
+            read2: read2.to_string(),
+            cluster1,
+            cluster2,
+        }
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.kind, self.read1, self.read2, self.cluster1, self.cluster2
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn constraints_test() {
+        let tsv = "# curated\nmust-link\tr1\tr2\ncannot-link\tr1\tr3\nlabel\tr4\tA\nlabel\tr5\tA\n";
+        let constraints = Constraints::parse(tsv).unwrap();
+        assert_eq!(constraints.len(), 4);
+        assert_eq!(constraints.structures(), vec!["A"]);
+        assert!(Constraints::parse("same\tr1\tr2").is_err());
+        assert!(Constraints::parse("must-link\tr1").is_err());
+        let assignments: HashMap<String, u8> = vec![("r1", 0), ("r2", 1), ("r3", 0), ("r4", 2)]
+            .into_iter()
+            .map(|(id, c)| (id.to_string(), c))
+            .collect();
+        let violations = constraints.violations(&assignments);
+        let kinds: Vec<_> = violations.iter().map(|v| v.kind).collect();
+        assert_eq!(kinds, vec!["must-link", "cannot-link"]);
+    }
+    #[test]
+    fn constraint_weight_test() {
+        let mut params = crate::DecomposeParams::default();
+        params.set("constraint_weight", "20").unwrap();
+        assert_eq!(params.constraint_weight, 20.);
+    }
+}
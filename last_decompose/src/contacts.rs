@@ -0,0 +1,311 @@
+//! Contacts between reads, from paired short reads or Hi-C pairs.
+//! The two mates of a pair are mapped onto the units of the contigs, and the pair links the
+//! reads covering the unit of one mate to the reads covering the unit of the other mate.
+//! Linked reads are likely, but not necessarily, to come from the same structure.
+//! Thus, the contacts are used as soft must-link terms: a prior favouring the clusters of the
+//! linked reads in the Gibbs sampling, and weights in the colour expansion on the de Bruijn
+//! graph.
+use super::assemble::ChunkedRead;
+use super::ERead;
+use definitions::{DataSet, HiCPair};
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// The length of k-mers to map mates onto the contigs.
+const K: usize = 15;
+/// The minimum number of k-mers shared by a mate and a unit to map the mate onto the unit.
+const MIN_HITS: usize = 3;
+
+/// Weighted contacts between reads.
+#[derive(Debug, Clone, Default)]
+pub struct Contacts {
+    partners: HashMap<String, Vec<(String, f64)>>,
+}
+
+impl Contacts {
+    /// Contacts from `(read, read, weight)`. The weights of the same pair are summed up.
+    pub fn new(links: &[(String, String, f64)]) -> Self {
+        let mut weights: HashMap<(&str, &str), f64> = HashMap::new();
+        for (r1, r2, w) in links.iter().filter(|(r1, r2, _)| r1 != r2) {
+            let key = if r1 < r2 { (r1, r2) } else { (r2, r1) };
+            *weights.entry((key.0.as_str(), key.1.as_str())).or_default() += w;
+        }
+        let mut partners: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+        for ((r1, r2), w) in weights {
+            let entry = partners.entry(r1.to_string()).or_default();
+            entry.push((r2.to_string(), w));
+            let entry = partners.entry(r2.to_string()).or_default();
+            entry.push((r1.to_string(), w));
+        }
+        partners
+            .values_mut()
+            .for_each(|ps| ps.sort_by(|a, b| a.0.cmp(&b.0)));
+        Self { partners }
+    }
+    /// The contacts with `links` added.
+    pub fn with_links(&self, links: &[(String, String, f64)]) -> Self {
+        let mut all: Vec<_> = self
+            .partners
+            .iter()
+            .flat_map(|(r1, ps)| {
+                ps.iter()
+                    .filter(move |(r2, _)| r1 < r2)
+                    .map(move |(r2, w)| (r1.clone(), r2.clone(), *w))
+            })
+            .collect();
+        all.extend_from_slice(links);
+        Self::new(&all)
+    }
+    /// The reads in contact with `id`, with the weights.
+    pub fn partners(&self, id: &str) -> &[(String, f64)] {
+        self.partners.get(id).map(|ps| ps.as_slice()).unwrap_or(&[])
+    }
+    /// The number of linked pairs of reads.
+    pub fn len(&self) -> usize {
+        self.partners.values().map(|ps| ps.len()).sum::<usize>() / 2
+    }
+    pub fn is_empty(&self) -> bool {
+        self.partners.is_empty()
+    }
+}
+
+/// Contacts from `dataset`. Each Hi-C edge links the raw reads `pair1` and `pair2` with
+/// weight one. Each Hi-C pair is mapped onto `contigs`, and links the reads covering the
+/// units of the two mates. A pair adds `1/sqrt(n1 * n2)` to each of the links, where `n1` and
+/// `n2` are the numbers of reads covering the units, so that each read gets about one from a pair.
+pub fn from_dataset(
+    dataset: &DataSet,
+    encoded_reads: &[EncodedRead],
+    contigs: &Contigs,
+) -> Contacts {
+    let names: HashMap<u64, &str> = dataset
+        .raw_reads
+        .iter()
+        .map(|r| (r.id, r.name.as_str()))
+        .collect();
+    let mut links: Vec<_> = dataset
+        .hic_edges
+        .iter()
+        .filter_map(|e| {
+            let (r1, r2) = (names.get(&e.pair1)?, names.get(&e.pair2)?);
+            Some((r1.to_string(), r2.to_string(), 1.))
+        })
+        .collect();
+    let mapped = map_pairs(&dataset.hic_pairs, contigs);
+    debug!(
+        "{} Hi-C edges, {} out of {} pairs mapped",
+        links.len(),
+        mapped.len(),
+        dataset.hic_pairs.len()
+    );
+    links.extend(link_reads(&mapped, encoded_reads));
+    Contacts::new(&links)
+}
+
+/// Map the mates of `pairs` onto the units of `contigs`. Pairs with an unmapped mate are discarded.
+pub fn map_pairs(pairs: &[HiCPair], contigs: &Contigs) -> Vec<((u16, u16), (u16, u16))> {
+    let index = kmer_index(contigs);
+    pairs
+        .par_iter()
+        .filter_map(|pair| {
+            let mate1 = map_mate(pair.seq1(), &index)?;
+            let mate2 = map_mate(pair.seq2(), &index)?;
+            Some((mate1, mate2))
+        })
+        .collect()
+}
+
+// The unit of each k-mer occurring on only one unit of the contigs, in either strand.
+fn kmer_index(contigs: &Contigs) -> HashMap<Vec<u8>, (u16, u16)> {
+    let mut index: HashMap<Vec<u8>, Option<(u16, u16)>> = HashMap::new();
+    for id in 0..contigs.get_num_of_contigs() as u16 {
+        let seq = contigs.get_by_id(id).unwrap();
+        let revcmp = contigs.get_by_id_revcmp(id).unwrap();
+        let forward = seq.windows(K).enumerate();
+        let reverse = revcmp
+            .windows(K)
+            .enumerate()
+            .map(|(pos, kmer)| (seq.len() - pos - K, kmer));
+        for (pos, kmer) in forward.chain(reverse) {
+            let unit = Some((id, (pos / UNIT_SIZE) as u16));
+            let entry = index.entry(kmer.to_ascii_uppercase()).or_insert(unit);
+            if *entry != unit {
+                *entry = None;
+            }
+        }
+    }
+    index
+        .into_iter()
+        .filter_map(|(kmer, unit)| unit.map(|u| (kmer, u)))
+        .collect()
+}
+
+// The unit sharing the most k-mers with `seq`, if it has at least `MIN_HITS` and
+// the majority of the hits.
+fn map_mate(seq: &[u8], index: &HashMap<Vec<u8>, (u16, u16)>) -> Option<(u16, u16)> {
+    let seq = seq.to_ascii_uppercase();
+    let mut hits: HashMap<(u16, u16), usize> = HashMap::new();
+    for kmer in seq.windows(K) {
+        if let Some(&unit) = index.get(kmer) {
+            *hits.entry(unit).or_default() += 1;
+        }
+    }
+    let total = hits.values().sum::<usize>();
+    let (&unit, &count) = hits.iter().max_by_key(|&(&unit, &count)| (count, unit))?;
+    if count >= MIN_HITS && 2 * count > total {
+        Some(unit)
+    } else {
+        None
+    }
+}
+
+/// Link the reads covering the units of the mates. See `from_dataset` for the weights.
+pub fn link_reads(
+    mapped: &[((u16, u16), (u16, u16))],
+    encoded_reads: &[EncodedRead],
+) -> Vec<(String, String, f64)> {
+    let ereads: Vec<_> = encoded_reads.iter().map(ERead::new_no_gapfill).collect();
+    let mut covering: HashMap<(u16, u16), Vec<usize>> = HashMap::new();
+    for (idx, read) in ereads.iter().enumerate() {
+        for unit in read.seq() {
+            let key = (unit.contig() as u16, unit.unit() as u16);
+            covering.entry(key).or_default().push(idx);
+        }
+    }
+    covering.values_mut().for_each(|reads| reads.dedup());
+    let mut counts: HashMap<((u16, u16), (u16, u16)), usize> = HashMap::new();
+    for &(a, b) in mapped.iter().filter(|(a, b)| a != b) {
+        *counts.entry((a.min(b), a.max(b))).or_default() += 1;
+    }
+    let mut weights: HashMap<(usize, usize), f64> = HashMap::new();
+    for ((a, b), count) in counts {
+        let (reads1, reads2) = match (covering.get(&a), covering.get(&b)) {
+            (Some(r1), Some(r2)) => (r1, r2),
+            _ => continue,
+        };
+        let weight = count as f64 / ((reads1.len() * reads2.len()) as f64).sqrt();
+        for &i in reads1.iter() {
+            for &j in reads2.iter().filter(|&&j| j != i) {
+                *weights.entry((i.min(j), i.max(j))).or_default() += weight;
+            }
+        }
+    }
+    weights
+        .into_iter()
+        .map(|((i, j), w)| (ereads[i].id().to_string(), ereads[j].id().to_string(), w))
+        .collect()
+}
+
+/// The maximum number of rounds of `expand_colors`.
+const EXPANSION_ROUNDS: usize = 10;
+
+/// Expand the colours of the de Bruijn graph along the contacts. `clusters` are the
+/// clusters of `reads` from the colour expansion on the graph, and are updated in place.
+/// In each round, a read without labels moves to the cluster with the largest total weight
+/// of contacts if the weight is at least `thr`, more than twice the weight with any other
+/// cluster, and the cluster is not forbidden for the read. The labels of the reads are
+/// not changed. Return the number of moves.
+pub fn expand_colors(
+    reads: &[ChunkedRead],
+    clusters: &mut [Option<u8>],
+    contacts: &Contacts,
+    thr: f64,
+) -> usize {
+    if contacts.is_empty() {
+        return 0;
+    }
+    let index: HashMap<&str, usize> = reads
+        .iter()
+        .enumerate()
+        .map(|(idx, r)| (r.id.as_str(), idx))
+        .collect();
+    let mut count = 0;
+    for _ in 0..EXPANSION_ROUNDS {
+        let moves: Vec<(usize, u8)> = reads
+            .iter()
+            .enumerate()
+            .filter(|(_, read)| read.label.is_none())
+            .filter_map(|(idx, read)| {
+                let mut weights: HashMap<u8, f64> = HashMap::new();
+                for (id, w) in contacts.partners(&read.id) {
+                    if let Some(cl) = index.get(id.as_str()).and_then(|&j| clusters[j]) {
+                        *weights.entry(cl).or_default() += w;
+                    }
+                }
+                let mut weights: Vec<_> = weights.into_iter().collect();
+                weights.sort_by(|a, b| (b.1).total_cmp(&a.1).then(a.0.cmp(&b.0)));
+                let &(cl, w) = weights.first()?;
+                let second = weights.get(1).map(|x| x.1).unwrap_or(0.);
+                let is_moved = clusters[idx] != Some(cl) && !read.forbidden.contains(&cl);
+                if w >= thr && w > 2. * second && is_moved {
+                    Some((idx, cl))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if moves.is_empty() {
+            break;
+        }
+        count += moves.len();
+        for (idx, cl) in moves {
+            clusters[idx] = Some(cl);
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn read(id: &str, label: Option<u8>, forbidden: Vec<u8>) -> ChunkedRead {
+        ChunkedRead {
+            id: id.to_string(),
+            desc: None,
+            nodes: vec![],
+            edges: vec![],
+            label,
+            forbidden,
+        }
+    }
+    #[test]
+    fn expand_colors_test() {
+        let reads = vec![
+            read("a", Some(0), vec![]),
+            read("b", Some(1), vec![]),
+            read("c", None, vec![]),
+            read("d", None, vec![0]),
+            read("e", None, vec![]),
+        ];
+        let link = |r1: &str, r2: &str, w: f64| (r1.to_string(), r2.to_string(), w);
+        let contacts = Contacts::new(&[
+            link("a", "c", 6.),
+            link("b", "c", 1.),
+            link("a", "d", 6.),
+            link("c", "e", 6.),
+            link("b", "e", 2.),
+        ]);
+        let mut clusters = vec![Some(0), Some(1), Some(1), None, None];
+        let moved = expand_colors(&reads, &mut clusters, &contacts, 5.);
+        // "c" moves to the cluster of "a". "e" moves to the cluster of "c" before the move,
+        // and then follows "c". The cluster of "a" is forbidden for "d".
+        assert_eq!(moved, 3);
+        assert_eq!(clusters, vec![Some(0), Some(1), Some(0), None, Some(0)]);
+        assert!(reads.iter().skip(2).all(|r| r.label.is_none()));
+        // Too weak contacts do not move reads.
+        let mut clusters = vec![Some(0), Some(1), Some(1), None, None];
+        assert_eq!(expand_colors(&reads, &mut clusters, &contacts, 10.), 0);
+    }
+    #[test]
+    fn contact_params_test() {
+        use crate::DecomposeParams;
+        let mut params = DecomposeParams::default();
+        params.set("contact_weight", "0.5").unwrap();
+        assert_eq!(params.contact_weight, 0.5);
+        // The threshold is used only in the assembly.
+        let key = params.key_of(DecomposeParams::CLUSTERING);
+        params.set("contact_thr", "2").unwrap();
+        assert_eq!(params.key_of(DecomposeParams::CLUSTERING), key);
+    }
+}
@@ -1,13 +1,12 @@
-use super::ERead;
-use last_tiling::{Contigs, EncodedRead};
+use super::params::BreakpointTest;
+use super::{refine_breakpoint, significance, DecomposeParams, ERead};
+use last_tiling::repeat::{Repeat, RepeatPairs};
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::cmp::{Ord, Ordering, PartialOrd};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
-const MERGE_THR: usize = 5;
-pub const COVERAGE_THR: usize = 15;
-pub const NGS_THR: usize = 10;
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cluster {
@@ -64,6 +63,126 @@ impl ReadClassify for Cluster {
 pub struct Member {
     pub cr: CriticalRegion,
     pub cluster: usize,
+    pub rearrangement: Rearrangement,
+    /// The repeat pair overlapping the critical region, if any.
+    pub repeat: Option<RepeatAnnotation>,
+    pub mechanism: Mechanism,
+}
+
+/// The minimum length of microhomology for `Mechanism::Microhomology`.
+pub const MIN_MICROHOMOLOGY: usize = 2;
+
+/// A repeat pair overlapping a critical region.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RepeatAnnotation {
+    /// The index of the repeat pair in `repeats.json`.
+    pub repeat: usize,
+    /// The mean length of the two copies.
+    pub length: usize,
+    /// The fraction of identical bases between the copies.
+    pub identity: f64,
+}
+
+/// The mechanism suggested by the sequence at a critical region.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum Mechanism {
+    /// The breakpoint sits inside a repeat pair. For a `ContigPair`, the two positions
+    /// should be in the different copies of the pair.
+    RepeatMediated,
+    /// A non-repeat junction with microhomology of at least `MIN_MICROHOMOLOGY` bases.
+    Microhomology,
+    /// A non-repeat junction without microhomology, blunt or with an insertion, as in
+    /// non-homologous end joining.
+    NonHomologous,
+    /// A non-repeat junction not refined to base-pair resolution.
+    Unknown,
+}
+
+impl Display for Mechanism {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Mechanism::RepeatMediated => "repeat_mediated",
+            Mechanism::Microhomology => "microhomology",
+            Mechanism::NonHomologous => "non_homologous",
+            Mechanism::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn annotate_repeat(idx: usize, pair: &RepeatPairs) -> RepeatAnnotation {
+    let length = pair
+        .inner()
+        .iter()
+        .map(|r| r.end() - r.start())
+        .sum::<usize>();
+    RepeatAnnotation {
+        repeat: idx,
+        length: length / pair.len().max(1),
+        identity: pair.identity(),
+    }
+}
+
+// The repeat pair overlapping `cr` and the mechanism of `cr`.
+fn annotate(cr: &CriticalRegion, repeats: &[RepeatPairs]) -> (Option<RepeatAnnotation>, Mechanism) {
+    let is_mediated = |pair: &RepeatPairs| match cr {
+        CriticalRegion::CP(cp) => {
+            let (a, b) = (&pair[0], &pair[1]);
+            let (p1, p2) = (&cp.contig1, &cp.contig2);
+            (p1.is_in(a) && p2.is_in(b)) || (p1.is_in(b) && p2.is_in(a))
+        }
+        CriticalRegion::CR(cr) => pair.inner().iter().any(|r| cr.pos.is_in(r)),
+    };
+    if let Some((idx, pair)) = repeats.iter().enumerate().find(|(_, p)| is_mediated(p)) {
+        return (Some(annotate_repeat(idx, pair)), Mechanism::RepeatMediated);
+    }
+    let positions = match cr {
+        CriticalRegion::CP(cp) => vec![&cp.contig1, &cp.contig2],
+        CriticalRegion::CR(cr) => vec![&cr.pos],
+    };
+    let overlapping = repeats.iter().enumerate().find(|(_, pair)| {
+        let is_in = |p: &&Position| pair.inner().iter().any(|r| p.is_in(r));
+        positions.iter().any(is_in)
+    });
+    let repeat = overlapping.map(|(idx, pair)| annotate_repeat(idx, pair));
+    let mechanism = match cr.contig_pair().and_then(|cp| cp.junction()) {
+        Some(j) if j.microhomology.len() >= MIN_MICROHOMOLOGY => Mechanism::Microhomology,
+        Some(_) => Mechanism::NonHomologous,
+        None => Mechanism::Unknown,
+    };
+    (repeat, mechanism)
+}
+
+/// The class of the rearrangement at a critical region, determined by
+/// the contigs and the directions of its positions.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum Rearrangement {
+    /// Both positions are on the same contig and retained in the same direction,
+    /// i.e., joined head-to-head or tail-to-tail. Typically mediated by an inverted repeat.
+    Inversion,
+    /// An upstream region is joined to a region further downstream,
+    /// looping out the region between them.
+    Deletion,
+    /// An upstream region is joined to a region before it,
+    /// duplicating the region between them in tandem.
+    TandemDuplication,
+    /// The positions are on different contigs.
+    Translocation,
+    /// A confluent region, where the other side of the junction is not resolved.
+    Confluence,
+}
+
+impl Display for Rearrangement {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        let name = match self {
+            Rearrangement::Inversion => "inversion",
+            Rearrangement::Deletion => "deletion",
+            Rearrangement::TandemDuplication => "tandem_duplication",
+            Rearrangement::Translocation => "translocation",
+            Rearrangement::Confluence => "confluence",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 fn get_forbids_cluster<'a>(
@@ -132,13 +251,18 @@ fn merge(
     crs[i].extend(j_clusters);
 }
 
-pub fn initial_clusters(reads: &[EncodedRead], contigs: &Contigs) -> Vec<Cluster> {
-    let reads: Vec<_> = reads.iter().map(ERead::new_no_gapfill).collect();
-    let crs: Vec<_> = critical_regions(&reads, contigs);
+/// Merge critical regions into clusters, the straightforward way. At each step,
+/// the reads spanning each cluster are collected again, and the first mergeable pair
+/// is searched from scratch. It is the reference of `merge_critical_regions`.
+pub fn merge_critical_regions_naive(
+    reads: &[ERead],
+    crs: Vec<CriticalRegion>,
+    params: &DecomposeParams,
+) -> Vec<Vec<CriticalRegion>> {
     let mut crs: Vec<_> = crs.into_iter().map(|e| vec![e]).collect();
     'merge: loop {
         let len = crs.len();
-        let forbiddens = get_forbids_cluster(&reads, &crs);
+        let forbiddens = get_forbids_cluster(reads, &crs);
         debug!("Current Cluster:{}", len);
         for i in 0..len {
             for j in (i + 1)..len {
@@ -146,7 +270,7 @@ pub fn initial_clusters(reads: &[EncodedRead], contigs: &Contigs) -> Vec<Cluster
                 let ngs = forbiddens_of(&crs, (i, j), &forbiddens);
                 let is_both = crs[i].iter().all(|cl| cl.confluent_region().is_some())
                     && crs[j].iter().all(|cl| cl.confluent_region().is_some());
-                if intersection > COVERAGE_THR && (ngs < NGS_THR || is_both) {
+                if intersection > params.coverage_thr && (ngs < params.ngs_thr || is_both) {
                     merge(&mut crs, (i, j), &forbiddens);
                     continue 'merge;
                 }
@@ -154,23 +278,184 @@ pub fn initial_clusters(reads: &[EncodedRead], contigs: &Contigs) -> Vec<Cluster
         }
         break;
     }
+    crs
+}
+
+// A cluster of critical regions being merged. Reads are indexed.
+struct MergingCluster {
+    // The indices of the critical regions.
+    members: Vec<usize>,
+    // The reads of the members.
+    reads: HashSet<usize>,
+    // The reads spanning any of the members.
+    forbidden: HashSet<usize>,
+    // Whether all the members are confluent regions.
+    is_confluent: bool,
+}
+
+impl MergingCluster {
+    fn is_mergeable(&self, other: &Self, params: &DecomposeParams) -> bool {
+        if self.reads.intersection(&other.reads).count() <= params.coverage_thr {
+            return false;
+        } else if self.is_confluent && other.is_confluent {
+            return true;
+        }
+        let is_forbidden = |r: &&usize| self.forbidden.contains(r) || other.forbidden.contains(r);
+        let ngs = self.reads.iter().filter(is_forbidden).count()
+            + other
+                .reads
+                .difference(&self.reads)
+                .filter(is_forbidden)
+                .count();
+        ngs < params.ngs_thr
+    }
+}
+
+/// Merge critical regions sharing more than `params.coverage_thr` reads into clusters.
+/// Two clusters are not merged if `params.ngs_thr` or more of their reads span either
+/// of them, unless both consist of confluent regions. Clusters are merged one pair at a time,
+/// the first mergeable pair in the order of the critical regions, and the reads spanning one
+/// of the pair are removed from the critical regions of the other.
+/// The mergeable pairs are kept in an ordered set, and only the pairs involving
+/// the merged cluster are re-examined after each merge.
+/// The result is the same as `merge_critical_regions_naive`.
+pub fn merge_critical_regions(
+    reads: &[ERead],
+    mut crs: Vec<CriticalRegion>,
+    params: &DecomposeParams,
+) -> Vec<Vec<CriticalRegion>> {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let ids = reads.iter().map(|r| r.id()).chain(
+        crs.iter()
+            .flat_map(|cr| cr.reads().iter().map(|id| id.as_str())),
+    );
+    for id in ids {
+        let len = index.len();
+        index.entry(id.to_string()).or_insert(len);
+    }
+    // As in `get_forbids_cluster`, the last one is used among the reads with the same ID.
+    let latest: HashMap<&str, &ERead> = reads.iter().map(|r| (r.id(), r)).collect();
+    let spanning: Vec<HashSet<usize>> = crs
+        .par_iter()
+        .map(|cr| {
+            latest
+                .values()
+                .filter(|r| cr.is_spanned_by(r))
+                .map(|r| index[r.id()])
+                .collect()
+        })
+        .collect();
+    let mut current: Vec<HashSet<usize>> = crs
+        .iter()
+        .map(|cr| cr.reads().iter().map(|id| index[id.as_str()]).collect())
+        .collect();
+    let mut clusters: Vec<Option<MergingCluster>> = crs
+        .iter()
+        .zip(spanning)
+        .enumerate()
+        .map(|(idx, (cr, forbidden))| {
+            Some(MergingCluster {
+                members: vec![idx],
+                reads: current[idx].clone(),
+                forbidden,
+                is_confluent: cr.confluent_region().is_some(),
+            })
+        })
+        .collect();
+    let len = clusters.len();
+    let mut mergeable: BTreeSet<(usize, usize)> = (0..len)
+        .into_par_iter()
+        .flat_map(|i| {
+            let clusters = &clusters;
+            (i + 1..len).into_par_iter().filter_map(move |j| {
+                let (x, y) = (clusters[i].as_ref()?, clusters[j].as_ref()?);
+                Some((i, j)).filter(|_| x.is_mergeable(y, params))
+            })
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .collect();
+    while let Some(&(i, j)) = mergeable.iter().next() {
+        let mut cluster = clusters[i].take().unwrap();
+        let absorbed = clusters[j].take().unwrap();
+        for &m in cluster.members.iter() {
+            current[m].retain(|r| !absorbed.forbidden.contains(r));
+        }
+        for &m in absorbed.members.iter() {
+            current[m].retain(|r| !cluster.forbidden.contains(r));
+        }
+        let retained = cluster
+            .reads
+            .iter()
+            .filter(|r| !absorbed.forbidden.contains(r));
+        let moved = absorbed
+            .reads
+            .iter()
+            .filter(|r| !cluster.forbidden.contains(r));
+        let reads: HashSet<usize> = retained.chain(moved).copied().collect();
+        cluster.reads = reads;
+        cluster.forbidden.extend(absorbed.forbidden);
+        cluster.members.extend(absorbed.members);
+        cluster.is_confluent &= absorbed.is_confluent;
+        mergeable.retain(|&(x, y)| x != i && x != j && y != i && y != j);
+        let pairs: Vec<_> = clusters
+            .par_iter()
+            .enumerate()
+            .filter_map(|(k, other)| other.as_ref().map(|other| (k, other)))
+            .filter(|(_, other)| cluster.is_mergeable(other, params))
+            .map(|(k, _)| (i.min(k), i.max(k)))
+            .collect();
+        mergeable.extend(pairs);
+        clusters[i] = Some(cluster);
+        debug!("Current Cluster:{}", clusters.iter().flatten().count());
+    }
+    let mut ids = vec![""; index.len()];
+    for (id, &idx) in index.iter() {
+        ids[idx] = id.as_str();
+    }
+    for (cr, reads) in crs.iter_mut().zip(current) {
+        cr.replace_reads(reads.into_iter().map(|r| ids[r].to_string()).collect());
+    }
+    let mut crs: Vec<_> = crs.into_iter().map(Some).collect();
+    clusters
+        .into_iter()
+        .flatten()
+        .map(|cl| cl.members.iter().map(|&m| crs[m].take().unwrap()).collect())
+        .collect()
+}
+
+pub fn initial_clusters(
+    encoded_reads: &[EncodedRead],
+    contigs: &Contigs,
+    repeats: &[RepeatPairs],
+    params: &DecomposeParams,
+) -> Vec<Cluster> {
+    let reads: Vec<_> = encoded_reads.iter().map(ERead::new_no_gapfill).collect();
+    let mut crs: Vec<_> = critical_regions(&reads, contigs, repeats, params);
+    refine_breakpoint::refine_contig_pairs(&mut crs, encoded_reads, contigs, params);
+    let crs = merge_critical_regions(&reads, crs, params);
     debug!("Resulting in {} clusters.", crs.len());
     crs.into_iter()
         .enumerate()
         .map(|(id, members)| {
             let members = members
                 .into_iter()
-                .map(|cr| Member { cr, cluster: id })
+                .map(|cr| {
+                    let (repeat, mechanism) = annotate(&cr, repeats);
+                    Member {
+                        rearrangement: cr.rearrangement(),
+                        repeat,
+                        mechanism,
+                        cr,
+                        cluster: id,
+                    }
+                })
                 .collect();
             Cluster { id, members }
         })
         .collect()
 }
 
-/// How many reads are needed to construct a separate class.
-/// Note that it should be more than 1, since there is a
-/// danger of chimeric reads.
-const CHECK_THR: u16 = 4;
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CriticalRegion {
     CP(ContigPair),
@@ -227,6 +512,25 @@ impl ReadClassify for CriticalRegion {
     }
 }
 impl CriticalRegion {
+    /// The significance of the critical region, if tested.
+    pub fn significance(&self) -> Option<&Significance> {
+        match self {
+            CriticalRegion::CP(ref cp) => cp.significance.as_ref(),
+            CriticalRegion::CR(ref cr) => cr.significance.as_ref(),
+        }
+    }
+    fn set_significance(&mut self, significance: Significance) {
+        match self {
+            CriticalRegion::CP(ref mut cp) => cp.significance = Some(significance),
+            CriticalRegion::CR(ref mut cr) => cr.significance = Some(significance),
+        }
+    }
+    pub fn rearrangement(&self) -> Rearrangement {
+        match self {
+            CriticalRegion::CP(ref cp) => cp.rearrangement(),
+            CriticalRegion::CR(_) => Rearrangement::Confluence,
+        }
+    }
     pub fn reads(&self) -> &HashSet<String> {
         match self {
             CriticalRegion::CP(ref cp) => &cp.reads,
@@ -287,6 +591,17 @@ pub struct Position {
     end_unit: u16,
     direction: Direction,
     longest: u16,
+    // Margin to check whether a read spans this position.
+    #[serde(default = "default_check_thr")]
+    check_thr: u16,
+    // The base-pair resolution position of the junction, if refined.
+    // It is the 0-based coordinate of the last base retained on this side.
+    #[serde(default)]
+    refined: Option<usize>,
+}
+
+fn default_check_thr() -> u16 {
+    DecomposeParams::default().check_thr
 }
 
 impl Ord for Position {
@@ -314,14 +629,23 @@ impl PartialOrd for Position {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Hash, PartialOrd, Ord)]
+/// The side of the junction where the sequence of the reference is retained.
+/// For example, `UpStream` means the reads come from the upstream region and jump at the position.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub enum Direction {
     UpStream,
     DownStream,
 }
 
 impl Position {
-    fn new(contig: u16, s: u16, e: u16, direction: Direction, max: u16) -> Self {
+    pub(crate) fn new(
+        contig: u16,
+        s: u16,
+        e: u16,
+        direction: Direction,
+        max: u16,
+        check_thr: u16,
+    ) -> Self {
         let (start_unit, end_unit) = (s, e);
         Self {
             contig,
@@ -329,6 +653,8 @@ impl Position {
             end_unit,
             direction,
             longest: max,
+            check_thr,
+            refined: None,
         }
     }
     fn overlap(&self, (contig, start, end): (u16, u16, u16)) -> bool {
@@ -347,6 +673,31 @@ impl Position {
     pub fn contig(&self) -> u16 {
         self.contig
     }
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+    /// The base-pair resolution position of the junction, if refined.
+    pub fn refined(&self) -> Option<usize> {
+        self.refined
+    }
+    // Whether the position overlaps the repeat. If refined, the refined position should be
+    // inside the repeat.
+    pub fn is_in(&self, repeat: &Repeat) -> bool {
+        if repeat.id() != self.contig {
+            false
+        } else if let Some(x) = self.refined {
+            repeat.start() <= x && x < repeat.end()
+        } else {
+            let start = self.start_unit as usize * UNIT_SIZE;
+            let end = (self.end_unit as usize + 1) * UNIT_SIZE;
+            start < repeat.end() && repeat.start() < end
+        }
+    }
+    // The refined position, or the center of the range in base pairs.
+    pub fn coordinate(&self) -> usize {
+        let center = (self.start_unit as usize + self.end_unit as usize) * UNIT_SIZE / 2;
+        self.refined.unwrap_or(center)
+    }
     fn format(&self, name: &str) -> String {
         let (header, footer) = match self.direction {
             Direction::DownStream => ("     ", "<--->"),
@@ -357,13 +708,14 @@ impl Position {
         format!("{}{}:{}:{}{}", header, s, name, t, footer)
     }
     fn is_spanned_by(&self, r: &ERead) -> bool {
-        let s = self.start_unit.max(CHECK_THR) - CHECK_THR;
-        let t = self.end_unit + CHECK_THR;
-        let (s_thr, t_thr) = (s.max(CHECK_THR) - CHECK_THR, t + CHECK_THR);
+        let check_thr = self.check_thr;
+        let s = self.start_unit.max(check_thr) - check_thr;
+        let t = self.end_unit + check_thr;
+        let (s_thr, t_thr) = (s.max(check_thr) - check_thr, t + check_thr);
         if t_thr < self.longest {
             r.does_touch(self.contig, s_thr, s) && r.does_touch(self.contig, t, t_thr)
         } else {
-            r.does_touch(self.contig, s_thr, s) && r.does_touch(self.contig, 0, CHECK_THR)
+            r.does_touch(self.contig, s_thr, s) && r.does_touch(self.contig, 0, check_thr)
         }
     }
 }
@@ -373,6 +725,42 @@ pub struct ContigPair {
     contig1: Position,
     contig2: Position,
     reads: HashSet<String>,
+    // The sequence at the junction, if refined.
+    #[serde(default)]
+    junction: Option<Junction>,
+    #[serde(default)]
+    significance: Option<Significance>,
+}
+
+/// The significance of a critical region against the background of chimeric reads.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Significance {
+    /// The expected number of supporting reads under the background.
+    pub expected: f64,
+    pub pvalue: f64,
+    /// The p-value adjusted by the Benjamini-Hochberg procedure.
+    pub qvalue: f64,
+}
+
+// The values are compared by their bits, so that the equality is total even for NaN.
+impl PartialEq for Significance {
+    fn eq(&self, other: &Self) -> bool {
+        let bits = |s: &Self| (s.expected.to_bits(), s.pvalue.to_bits(), s.qvalue.to_bits());
+        bits(self) == bits(other)
+    }
+}
+
+impl Eq for Significance {}
+
+/// The sequence at a refined junction, in the direction from `contig1` to `contig2`.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Default)]
+pub struct Junction {
+    /// The sequence shared by both sides of the junction.
+    pub microhomology: String,
+    /// The sequence inserted between both sides of the junction.
+    pub insertion: String,
+    /// The number of reads supporting the refined position.
+    pub support: usize,
 }
 
 impl Debug for ContigPair {
@@ -465,13 +853,42 @@ impl ReadClassify for ContigPair {
 }
 
 impl ContigPair {
+    pub fn rearrangement(&self) -> Rearrangement {
+        let (pos1, pos2) = (&self.contig1, &self.contig2);
+        if pos1.contig != pos2.contig {
+            Rearrangement::Translocation
+        } else if pos1.direction == pos2.direction {
+            Rearrangement::Inversion
+        } else {
+            let (up, down) = match pos1.direction {
+                Direction::UpStream => (pos1, pos2),
+                Direction::DownStream => (pos2, pos1),
+            };
+            if up.coordinate() < down.coordinate() {
+                Rearrangement::Deletion
+            } else {
+                Rearrangement::TandemDuplication
+            }
+        }
+    }
     pub fn new(contig1: Position, contig2: Position, reads: HashSet<String>) -> Self {
         Self {
             contig1,
             contig2,
             reads,
+            junction: None,
+            significance: None,
         }
     }
+    /// Set the base-pair resolution positions of the junction.
+    pub fn refine(&mut self, (pos1, pos2): (usize, usize), junction: Junction) {
+        self.contig1.refined = Some(pos1);
+        self.contig2.refined = Some(pos2);
+        self.junction = Some(junction);
+    }
+    pub fn junction(&self) -> Option<&Junction> {
+        self.junction.as_ref()
+    }
     pub fn contig1(&self) -> &Position {
         &self.contig1
     }
@@ -500,9 +917,10 @@ impl ContigPair {
             contig,
             start_unit,
             end_unit,
+            check_thr,
             ..
         } = cr.pos;
-        self.overlap((contig, start_unit - CHECK_THR, end_unit + CHECK_THR))
+        self.overlap((contig, start_unit - check_thr, end_unit + check_thr))
     }
     pub fn reads(&self) -> &HashSet<String> {
         &self.reads
@@ -514,6 +932,8 @@ pub struct ConfluentRegion {
     // Contains start and end position.
     pos: Position,
     reads: HashSet<String>,
+    #[serde(default)]
+    significance: Option<Significance>,
 }
 
 impl Debug for ConfluentRegion {
@@ -545,7 +965,11 @@ impl ConfluentRegion {
         &self.reads
     }
     fn new(pos: Position, reads: HashSet<String>) -> Self {
-        Self { pos, reads }
+        Self {
+            pos,
+            reads,
+            significance: None,
+        }
     }
     pub fn contig(&self) -> &Position {
         &self.pos
@@ -580,10 +1004,45 @@ impl ReadClassify for ConfluentRegion {
     }
 }
 
-/// Return critical regions.
-pub fn critical_regions(reads: &[ERead], contigs: &Contigs) -> Vec<CriticalRegion> {
-    let contig_pairs = contigpair_position(reads, contigs);
-    let confluent_regions = confluent_position(reads, contigs, last_tiling::UNIT_SIZE);
+/// Enumerate critical regions. If `params.breakpoint_test` is `Poisson`, candidates are
+/// tested against the background of chimeric reads, and the ones with q-values larger than
+/// `params.fdr` are removed.
+pub fn critical_regions(
+    reads: &[ERead],
+    contigs: &Contigs,
+    repeats: &[RepeatPairs],
+    params: &DecomposeParams,
+) -> Vec<CriticalRegion> {
+    let contig_pairs = contigpair_position(reads, contigs, params);
+    let confluent_regions = confluent_position(reads, contigs, last_tiling::UNIT_SIZE, params);
+    let (contig_pairs, confluent_regions) = match params.breakpoint_test {
+        BreakpointTest::Fixed => (contig_pairs, confluent_regions),
+        BreakpointTest::Poisson => {
+            let background = significance::Background::estimate(reads, contigs, repeats);
+            let candidates: Vec<_> = contig_pairs
+                .into_iter()
+                .map(CriticalRegion::CP)
+                .chain(confluent_regions.into_iter().map(CriticalRegion::CR))
+                .collect();
+            let tests: Vec<_> = candidates.iter().map(|cr| background.test(cr)).collect();
+            let pvalues: Vec<_> = tests.iter().map(|t| t.pvalue).collect();
+            let qvalues = significance::benjamini_hochberg(&pvalues);
+            let (mut contig_pairs, mut confluent_regions) = (vec![], vec![]);
+            for ((mut cr, mut test), qvalue) in candidates.into_iter().zip(tests).zip(qvalues) {
+                test.qvalue = qvalue;
+                debug!("{}\t{:?}", cr, test);
+                if qvalue > params.fdr {
+                    continue;
+                }
+                cr.set_significance(test);
+                match cr {
+                    CriticalRegion::CP(cp) => contig_pairs.push(cp),
+                    CriticalRegion::CR(cr) => confluent_regions.push(cr),
+                }
+            }
+            (contig_pairs, confluent_regions)
+        }
+    };
     let confluent_regions: Vec<_> = confluent_regions
         .into_iter()
         .filter(|cr| contig_pairs.iter().all(|cp| !cp.overlap_with(cr)))
@@ -596,7 +1055,11 @@ pub fn critical_regions(reads: &[ERead], contigs: &Contigs) -> Vec<CriticalRegio
         .collect()
 }
 
-pub fn contigpair_position(reads: &[ERead], contigs: &Contigs) -> Vec<ContigPair> {
+pub fn contigpair_position(
+    reads: &[ERead],
+    contigs: &Contigs,
+    params: &DecomposeParams,
+) -> Vec<ContigPair> {
     let mut from_up_counts: Vec<Vec<Vec<(u16, usize, bool)>>> = contigs
         .get_last_units()
         .iter()
@@ -630,9 +1093,9 @@ pub fn contigpair_position(reads: &[ERead], contigs: &Contigs) -> Vec<ContigPair
         }
     }
     let mut contigpairs = vec![];
-    let pairs = peak_call_contigpair(reads, from_up_counts, true, contigs);
+    let pairs = peak_call_contigpair(reads, from_up_counts, true, contigs, params);
     contigpairs.extend(pairs);
-    let pairs = peak_call_contigpair(reads, to_down_counts, false, contigs);
+    let pairs = peak_call_contigpair(reads, to_down_counts, false, contigs, params);
     contigpairs.extend(pairs);
     contigpairs
 }
@@ -644,6 +1107,7 @@ fn peak_call_contigpair(
     mut counts: Vec<Vec<Vec<(u16, usize, bool)>>>,
     from_upstream: bool,
     contigs: &Contigs,
+    params: &DecomposeParams,
 ) -> Vec<ContigPair> {
     let mut contigpairs = vec![];
     for (contig, jumps) in counts.iter_mut().enumerate() {
@@ -652,20 +1116,21 @@ fn peak_call_contigpair(
         let mut start_idx = 0;
         while start_idx < jumps.len() {
             // Search a heviest edge from this contig.
-            let (from, to_c, to, direction) = match search_jump_start(jumps, start_idx) {
+            let jump_start = search_jump_start(jumps, start_idx, params.jump_thr);
+            let (from, to_c, to, direction) = match jump_start {
                 Some(res) => res,
                 None => break,
             };
             // Determine the range of the start position ad the range of
             // the end position.
             let (from_start, from_end, to_start, to_end) =
-                search_jump_area(jumps, from, to_c, to, direction);
+                search_jump_area(jumps, from, to_c, to, direction, params.offset);
             // Collect the edges from (start,end) to (start_dst, end_dst)
             let counts = collect_edges_in(
                 jumps, from_start, from_end, to_c, to_start, to_end, direction,
             );
             // and remove them from counts.
-            if counts > COVERAGE_THR {
+            if counts > params.breakpoint_test.min_support(params) {
                 remove_edges(
                     jumps, from_start, from_end, to_c, to_start, to_end, direction,
                 );
@@ -681,9 +1146,17 @@ fn peak_call_contigpair(
                 let to_max = contigs.get_last_unit(to_c as u16).unwrap();
                 let (contig, from_start, from_end) =
                     (contig as u16, from_start as u16, from_end as u16);
-                let pos1 = Position::new(contig, from_start, from_end, from_direction, from_max);
+                let check_thr = params.check_thr;
+                let pos1 = Position::new(
+                    contig,
+                    from_start,
+                    from_end,
+                    from_direction,
+                    from_max,
+                    check_thr,
+                );
                 let (to_start, to_end) = (to_start as u16, to_end as u16);
-                let pos2 = Position::new(to_c, to_start, to_end, to_direction, to_max);
+                let pos2 = Position::new(to_c, to_start, to_end, to_direction, to_max, check_thr);
                 contigpairs.push(ContigPair::new(pos1, pos2, belong_reads));
             }
             start_idx = from_end;
@@ -692,11 +1165,11 @@ fn peak_call_contigpair(
     contigpairs
 }
 
-const JUMP_THR: usize = 5;
 // Return the start position of the edge, the contig of the destination, the position of the destimation, and whether the edge is 'to_downstream'.
 fn search_jump_start(
     jumps: &[Vec<(u16, usize, bool)>],
     s: usize,
+    jump_thr: usize,
 ) -> Option<(usize, u16, usize, bool)> {
     let mut counts: HashMap<_, usize> = HashMap::new();
     for (position, edges) in jumps.iter().enumerate().skip(s) {
@@ -705,7 +1178,7 @@ fn search_jump_start(
         }
         if let Some((&(x, y, z), _)) = counts
             .iter()
-            .filter(|&(_, &count)| count > JUMP_THR)
+            .filter(|&(_, &count)| count > jump_thr)
             .min_by_key(|&(&(_, pos, _), _)| pos)
         {
             return Some((position, x, y, z));
@@ -715,7 +1188,6 @@ fn search_jump_start(
     None
 }
 
-const OFFSET: usize = 5;
 // Return the area of the junctions.
 fn search_jump_area(
     jumps: &[Vec<(u16, usize, bool)>],
@@ -723,11 +1195,12 @@ fn search_jump_area(
     to_c: u16,
     to: usize,
     direction: bool,
+    offset: usize,
 ) -> (usize, usize, usize, usize) {
     // Count the number of edges from from_start position to to_c:[to_start..to_end).
     let count = |pos: usize, start: usize, end: usize| {
-        let start = start.max(OFFSET) - OFFSET;
-        let end = end + OFFSET;
+        let start = start.max(offset) - offset;
+        let end = end + offset;
         jumps[pos]
             .iter()
             .filter(|&&(c, t, d)| c == to_c && start <= t && t < end && d == direction)
@@ -735,8 +1208,8 @@ fn search_jump_area(
     };
     // Get the minimum/maximum position from 'pos' to the target range.
     let get_range = |pos: usize, start: usize, end: usize| {
-        let start = start.max(OFFSET) - OFFSET;
-        let end = end + OFFSET;
+        let start = start.max(offset) - offset;
+        let end = end + offset;
         let edges = jumps[pos]
             .iter()
             .filter(|&&(c, t, d)| c == to_c && start <= t && t < end && d == direction);
@@ -859,7 +1332,7 @@ fn remove_edges(
     }
 }
 
-fn is_jumping(w: &&[super::CUnit]) -> bool {
+pub(crate) fn is_jumping(w: &&[super::CUnit]) -> bool {
     const ACCEPTED_GAP: u16 = 10;
     fn diff(x: &super::CUnit, y: &super::CUnit) -> u16 {
         (x.unit).max(y.unit) - (x.unit).min(y.unit)
@@ -875,6 +1348,7 @@ pub fn confluent_position(
     reads: &[ERead],
     contigs: &Contigs,
     _unit_size: usize,
+    params: &DecomposeParams,
 ) -> Vec<ConfluentRegion> {
     let mut start_stop_count: HashMap<u16, Vec<_>> = contigs
         .get_last_units()
@@ -907,13 +1381,13 @@ pub fn confluent_position(
     let mut result = vec![];
     for (id, counts) in start_stop_count {
         let downstream: Vec<_> = counts.iter().map(|x| x.0).collect();
-        let chunks = peak_call(&downstream).into_iter().map(|(s, e)| {
+        let chunks = peak_call(&downstream, params).into_iter().map(|(s, e)| {
             let count = downstream[s..e].iter().sum::<usize>();
             (id, s, e, true, count)
         });
         result.extend(chunks);
         let upstream: Vec<_> = counts.iter().map(|x| x.1).collect();
-        let chunks = peak_call(&upstream).into_iter().map(|(s, e)| {
+        let chunks = peak_call(&upstream, params).into_iter().map(|(s, e)| {
             let count = upstream[s..e].iter().sum::<usize>();
             (id, s, e, false, count)
         });
@@ -927,12 +1401,14 @@ pub fn confluent_position(
             let max = contigs.get_last_unit(id).unwrap();
             use Direction::*;
             let di = if to_downstream { DownStream } else { UpStream };
-            let is_edge = (end < OFFSET as u16 && to_downstream)
-                || (max < OFFSET as u16 + start && !to_downstream);
+            let offset = params.offset as u16;
+            let is_edge =
+                (end < offset && to_downstream) || (max < offset + start && !to_downstream);
             if is_edge {
                 None
             } else {
-                let cr = ConfluentRegion::new(Position::new(id, start, end, di, max), reads);
+                let pos = Position::new(id, start, end, di, max, params.check_thr);
+                let cr = ConfluentRegion::new(pos, reads);
                 Some(cr)
             }
         })
@@ -960,7 +1436,7 @@ fn get_confluent_reads(
         .collect()
 }
 
-fn peak_call(counts: &[usize]) -> Vec<(usize, usize)> {
+fn peak_call(counts: &[usize], params: &DecomposeParams) -> Vec<(usize, usize)> {
     let (sum, sumsq) = counts.iter().fold((0., 0.), |(sum, sumsq), &x| {
         (sum + x as f64, sumsq + (x * x) as f64)
     });
@@ -969,14 +1445,14 @@ fn peak_call(counts: &[usize]) -> Vec<(usize, usize)> {
     let sd = (sumsq / len - mean * mean).sqrt();
     let four_sigma = mean + 4. * sd;
     debug!("MEAN:{:.2}\tSD:{:.2}\t4Sigma:{:.2}", mean, sd, four_sigma);
-    let thr = (four_sigma.floor() as usize).max(COVERAGE_THR);
+    let thr = (four_sigma.floor() as usize).max(params.breakpoint_test.min_support(params));
     let positions: Vec<_> = counts
         .iter()
         .enumerate()
         .filter(|(_, &count)| count >= thr)
         .map(|(idx, _)| idx)
         .collect();
-    merge_neighbors(positions, MERGE_THR)
+    merge_neighbors(positions, params.peak_merge_thr)
         .into_iter()
         .filter(|&(s, t)| counts[s..t].iter().sum::<usize>() / (t - s + 1) > thr)
         .collect()
@@ -999,3 +1475,26 @@ fn merge_neighbors(positions: Vec<usize>, thr: usize) -> Vec<(usize, usize)> {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulate::repeat_genome;
+    use rand::SeedableRng;
+    use rand_xoshiro::Xoshiro256StarStar;
+    #[test]
+    fn merge_critical_regions_test() {
+        let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(3_214);
+        let (contigs, reads) = repeat_genome(&mut rng, 8, 150);
+        let params = DecomposeParams {
+            breakpoint_test: BreakpointTest::Poisson,
+            ..DecomposeParams::default()
+        };
+        let reads: Vec<_> = reads.iter().map(ERead::new_no_gapfill).collect();
+        let crs = critical_regions(&reads, &contigs, &[], &params);
+        assert!(!crs.is_empty());
+        let naive = merge_critical_regions_naive(&reads, crs.clone(), &params);
+        let incremental = merge_critical_regions(&reads, crs, &params);
+        assert_eq!(naive, incremental);
+    }
+}
@@ -0,0 +1,190 @@
+//! Convergence diagnostics of the Gibbs sampler.
+//! Each run of the sampler on a window records, for each iteration, the total log-likelihood,
+//! the inverse temperature, the number of changed assignments and the sizes of the clusters.
+//! A run stops when the assignments are stable for `params.stable_limit` iterations, or is
+//! cut at `config.limit` iterations and retried once. A window with a retry also cut at the
+//! limit is under-converged, rather than ambiguous.
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// An iteration of the sampler.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Iteration {
+    pub iteration: u64,
+    /// The total log-likelihood of the reads before the iteration.
+    pub lk: f64,
+    pub beta: f64,
+    pub changed: u32,
+    /// The number of consecutive stable iterations so far.
+    pub stable_count: u32,
+    pub sizes: Vec<usize>,
+}
+
+/// A run of the sampler on a window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub seed: u64,
+    pub cluster_num: usize,
+    /// Whether this run is the retry after a run cut at the limit.
+    pub retry: bool,
+    /// Whether the assignments got stable before the limit.
+    pub stabilised: bool,
+    pub iterations: Vec<Iteration>,
+}
+
+/// Where the runs on a window are recorded. The clones share the records.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder(Arc<Mutex<Vec<Run>>>);
+
+impl Recorder {
+    pub fn push(&self, run: Run) {
+        self.0.lock().unwrap().push(run);
+    }
+    pub fn take(&self) -> Vec<Run> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+/// All the runs on a window, in the order they were run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowTrace {
+    pub window: usize,
+    pub range: (u16, u16, u16),
+    pub runs: Vec<Run>,
+}
+
+impl WindowTrace {
+    pub const HEADER: &'static str =
+        "run\tseed\tcluster_num\tretry\titeration\tlk\tbeta\tchanged\tstable_count\tsizes";
+    /// The number of runs cut at the limit.
+    pub fn limit_hits(&self) -> usize {
+        self.runs.iter().filter(|r| !r.stabilised).count()
+    }
+    /// The number of runs whose retry was also cut at the limit.
+    pub fn unconverged(&self) -> usize {
+        self.runs
+            .iter()
+            .filter(|r| r.retry && !r.stabilised)
+            .count()
+    }
+    /// Write the iterations of all the runs as a TSV, with the header.
+    pub fn write_tsv<W: Write>(&self, wtr: &mut W) -> std::io::Result<()> {
+        writeln!(wtr, "{}", Self::HEADER)?;
+        for (idx, run) in self.runs.iter().enumerate() {
+            for it in run.iterations.iter() {
+                let sizes: Vec<_> = it.sizes.iter().map(|s| format!("{}", s)).collect();
+                writeln!(
+                    wtr,
+                    "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}\t{}",
+                    idx,
+                    run.seed,
+                    run.cluster_num,
+                    run.retry,
+                    it.iteration,
+                    it.lk,
+                    it.beta,
+                    it.changed,
+                    it.stable_count,
+                    sizes.join(",")
+                )?;
+            }
+        }
+        Ok(())
+    }
+    pub fn summary(&self) -> Convergence {
+        Convergence {
+            window: self.window,
+            range: self.range,
+            runs: self.runs.len(),
+            limit_hits: self.limit_hits(),
+            unconverged: self.unconverged(),
+            iterations: self.runs.iter().map(|r| r.iterations.len()).sum(),
+        }
+    }
+}
+
+/// The summary of the convergence on a window.
+#[derive(Debug, Clone)]
+pub struct Convergence {
+    pub window: usize,
+    pub range: (u16, u16, u16),
+    pub runs: usize,
+    pub limit_hits: usize,
+    pub unconverged: usize,
+    /// The total number of iterations of the runs.
+    pub iterations: usize,
+}
+
+impl Convergence {
+    pub const HEADER: &'static str =
+        "window\tcontig\tstart\tend\truns\titerations\tlimit_hits\tunconverged";
+}
+
+impl std::fmt::Display for Convergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (contig, start, end) = self.range;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.window,
+            contig,
+            start,
+            end,
+            self.runs,
+            self.iterations,
+            self.limit_hits,
+            self.unconverged
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn window_trace_test() {
+        let iteration = Iteration {
+            iteration: 1,
+            lk: -10.,
+            beta: 0.1,
+            changed: 3,
+            stable_count: 0,
+            sizes: vec![4, 6],
+        };
+        let run = |retry, stabilised| Run {
+            seed: 0,
+            cluster_num: 2,
+            retry,
+            stabilised,
+            iterations: vec![iteration.clone()],
+        };
+        let recorder = Recorder::default();
+        recorder.clone().push(run(false, false));
+        recorder.push(run(true, false));
+        recorder.push(run(false, true));
+        let runs = recorder.take();
+        assert!(recorder.take().is_empty());
+        let trace = WindowTrace {
+            window: 3,
+            range: (0, 20, 40),
+            runs,
+        };
+        let summary = trace.summary();
+        assert_eq!((summary.limit_hits, summary.unconverged), (2, 1));
+        let mut tsv = vec![];
+        trace.write_tsv(&mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        assert_eq!(tsv.lines().count(), 4);
+        assert!(tsv.lines().nth(1).unwrap().ends_with("\t4,6"));
+    }
+    #[test]
+    fn gibbs_trace_param_test() {
+        let mut params = crate::DecomposeParams::default();
+        assert!(!params.gibbs_trace);
+        let key = params.key_of(crate::DecomposeParams::CLUSTERING);
+        params.set("gibbs_trace", "true").unwrap();
+        assert!(params.gibbs_trace);
+        // The clustering is run again to record the traces.
+        assert_ne!(params.key_of(crate::DecomposeParams::CLUSTERING), key);
+    }
+}
@@ -7,9 +7,13 @@ extern crate serde;
 pub use find_breakpoint::critical_regions;
 use rayon::prelude::*;
 pub mod bipartite_matching;
+pub mod consensus;
+pub mod constraints;
+pub mod contacts;
 mod eread;
 pub mod find_breakpoint;
 mod find_union;
+pub mod gibbs_trace;
 pub mod utils;
 pub use eread::*;
 pub use find_breakpoint::initial_clusters;
@@ -18,7 +22,7 @@ use find_breakpoint::ReadClassify;
 use rand::Rng;
 use rand::SeedableRng;
 use rand_xoshiro::Xoshiro256StarStar;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 pub mod annotate_contigs_to_reference;
 pub mod assemble;
 pub mod d3_data;
@@ -27,71 +31,120 @@ pub mod poa_clustering;
 use poa_hmm::Config;
 pub mod variant_calling;
 // mod digamma;
-// const WINDOW_SIZE: usize = 300;
-// const OVERLAP: usize = 50;
-const WINDOW_SIZE: usize = 20;
-const OVERLAP: usize = 0;
-const MIN_LEN: usize = 6_000;
-const CONNECTION_THR: f64 = 0.5;
-const MERGE_THR: usize = 50;
-const NG_THR: usize = 10;
-#[derive(Debug, Clone, Copy)]
-pub struct DecomposeConfig {
-    k: usize,
-    thr: usize,
-}
-impl DecomposeConfig {
-    pub fn new(k: usize, thr: usize) -> Self {
-        Self { k, thr }
-    }
-}
+pub mod params;
+pub mod phasing;
+pub mod recombination;
+pub mod recombination_graph;
+pub mod refine_breakpoint;
+pub mod significance;
+#[cfg(any(test, feature = "simulate"))]
+pub mod simulate;
+pub mod stoichiometry;
+pub mod sv_export;
+pub mod windows;
+pub use params::{
+    BreakpointTest, DecomposeParams, MergeStrategy, ModelSelection, Phasing, WindowLayout,
+};
 type Read<'a> = Vec<(usize, &'a [u8])>;
 
 #[derive(Debug, Clone)]
 pub struct DecomposedResult {
     pub reads: Vec<assemble::ChunkedRead>,
     pub assignments: Vec<(String, Option<u8>)>,
+    /// The posterior probabilities of each read on the final clusters, in the same order as `assignments`.
+    pub posteriors: Vec<Vec<f64>>,
+    /// The decoding of the reads without labels. Empty if `params.phasing` is `none`.
+    pub phases: Vec<phasing::Phase>,
+    /// The stability of each window. Empty if `params.consensus_seeds` is one.
+    pub stability: Vec<consensus::WindowStability>,
+    /// The runs of the sampler on each window. Empty if `params.gibbs_trace` is false.
+    pub traces: Vec<gibbs_trace::WindowTrace>,
     pub gfa: gfa::GFA,
     pub contigs: Vec<bio_utils::fasta::Record>,
 }
 
 /// Main method. Decomposing the reads.
 /// You should call "merge" method separatly(?) -- should be integrated with this function.
+#[allow(clippy::too_many_arguments)]
 pub fn decompose(
     encoded_reads: Vec<last_tiling::EncodedRead>,
     initial_clusters: &[Cluster],
     contigs: &last_tiling::Contigs,
+    contacts: &contacts::Contacts,
+    constraints: &constraints::Constraints,
     config: &Config,
     cluster_num: usize,
     limit: u64,
-    settings: &DecomposeConfig,
+    params: &DecomposeParams,
 ) -> DecomposedResult {
+    let (chunked_reads, stability, traces) = cluster_reads(
+        &encoded_reads,
+        initial_clusters,
+        contigs,
+        contacts,
+        constraints,
+        config,
+        cluster_num,
+        limit,
+        params,
+    );
+    let mut result = merge_chunked_reads(
+        chunked_reads,
+        &encoded_reads,
+        initial_clusters,
+        contacts,
+        params,
+    );
+    result.stability = stability;
+    result.traces = traces;
+    result
+}
+
+/// The clustering stage of `decompose`. Each read is chunked into windows,
+/// and each chunk is assigned to a cluster. The reads in `contacts` tend to be
+/// in the same cluster.
+/// `constraints` are merged into the labels and the forbidden clusters from `initial_clusters`.
+/// Return the chunked reads, and the stability and the traces of the windows
+/// (see `clustering_chunking`).
+#[allow(clippy::too_many_arguments)]
+pub fn cluster_reads(
+    encoded_reads: &[last_tiling::EncodedRead],
+    initial_clusters: &[Cluster],
+    contigs: &last_tiling::Contigs,
+    contacts: &contacts::Contacts,
+    constraints: &constraints::Constraints,
+    config: &Config,
+    cluster_num: usize,
+    limit: u64,
+    params: &DecomposeParams,
+) -> (
+    Vec<assemble::ChunkedRead>,
+    Vec<consensus::WindowStability>,
+    Vec<gibbs_trace::WindowTrace>,
+) {
     let ereads: Vec<_> = encoded_reads.iter().map(ERead::new_no_gapfill).collect();
     let coverages = get_coverages(contigs, &ereads);
-    let (dataset, labels, forbidden) = initial_clustering(ereads, initial_clusters);
+    let (dataset, labels, mut forbidden) = initial_clustering(ereads, initial_clusters);
+    let first_label = initial_clusters.iter().map(|c| c.id + 1).max().unwrap_or(0) as u8;
+    let (dataset, labels) = constraints.apply(dataset, labels, &mut forbidden, first_label);
+    let contacts = &contacts.with_links(&constraints.links(params.constraint_weight));
     let total_units = dataset.iter().map(|read| read.seq().len()).sum::<usize>();
     debug!("{} reads and {} units.", dataset.len(), total_units);
     debug!(
         "Forbids:{}",
         forbidden.values().filter(|e| !e.is_empty()).count()
     );
-    let contigs: Vec<_> = (0..contigs.get_num_of_contigs())
-        .map(|e| contigs.get_last_unit(e as u16).unwrap() as usize)
-        .collect();
-    let windows: Vec<_> = contigs
-        .iter()
-        .zip(coverages)
-        .enumerate()
-        .flat_map(|(idx, (len, cov))| create_windows(idx, *len, &cov))
-        .collect();
-    let predicts = clustering_chunking(
+    let windows = windows::layout(contigs, &coverages, initial_clusters, params);
+    let (predicts, stability, traces) = clustering_chunking(
         &dataset,
         &labels,
         &forbidden,
+        contacts,
         cluster_num,
         config,
         limit,
         &windows,
+        params,
     );
     let labels: HashMap<_, _> = dataset
         .iter()
@@ -108,10 +161,111 @@ pub fn decompose(
             Some(assemble::ChunkedRead::from(r, label, forbs, entries))
         })
         .collect();
-    let (assignments, gfa, contigs) =
-        assemble::assemble_reads(&chunked_reads, settings.k, settings.thr);
+    (chunked_reads, stability, traces)
+}
+
+/// The merging stage of `decompose`. The reads are phased by `params.phasing`,
+/// and then the clusters of the windows are merged by `params.merge_strategy`.
+/// The de Bruijn graph strategy expands the colours along `contacts` as well.
+pub fn merge_chunked_reads(
+    mut chunked_reads: Vec<assemble::ChunkedRead>,
+    encoded_reads: &[last_tiling::EncodedRead],
+    initial_clusters: &[Cluster],
+    contacts: &contacts::Contacts,
+    params: &DecomposeParams,
+) -> DecomposedResult {
+    let phases = phasing::phase_reads(&mut chunked_reads, params);
+    let mut result = match params.merge_strategy {
+        MergeStrategy::Dbg => assemble_chunked_reads(chunked_reads, contacts, params),
+        MergeStrategy::Bipartite => {
+            merge_chunked_reads_by_bipartite(chunked_reads, encoded_reads, initial_clusters, params)
+        }
+    };
+    result.phases = phases;
+    result
+}
+
+/// The assembly stage of `decompose`.
+pub fn assemble_chunked_reads(
+    chunked_reads: Vec<assemble::ChunkedRead>,
+    contacts: &contacts::Contacts,
+    params: &DecomposeParams,
+) -> DecomposedResult {
+    let (k, thr) = (params.assemble_k, params.assemble_thr);
+    let (assignments, gfa, contigs, posteriors) =
+        assemble::assemble_reads(&chunked_reads, contacts, k, thr, params.contact_thr);
+    DecomposedResult {
+        assignments,
+        posteriors,
+        phases: vec![],
+        stability: vec![],
+        traces: vec![],
+        gfa,
+        contigs,
+        reads: chunked_reads,
+    }
+}
+
+/// Merge the clusters of adjacent windows by maximum weight matchings, then merge the
+/// resulting components by the initial clusters and the background.
+pub fn merge_chunked_reads_by_bipartite(
+    chunked_reads: Vec<assemble::ChunkedRead>,
+    encoded_reads: &[last_tiling::EncodedRead],
+    initial_clusters: &[Cluster],
+    params: &DecomposeParams,
+) -> DecomposedResult {
+    let data: Vec<_> = encoded_reads.iter().map(ERead::new_no_gapfill).collect();
+    let windowlen = chunked_reads
+        .iter()
+        .flat_map(|read| read.nodes.iter())
+        .map(|node| node.window_position + 1)
+        .max()
+        .unwrap_or(0);
+    let mut clusterings: Vec<BTreeMap<u8, HashSet<String>>> = vec![BTreeMap::new(); windowlen];
+    for read in chunked_reads.iter() {
+        for node in read.nodes.iter() {
+            clusterings[node.window_position]
+                .entry(node.cluster)
+                .or_default()
+                .insert(read.id.clone());
+        }
+    }
+    let clusterings: Vec<Vec<_>> = clusterings
+        .into_iter()
+        .map(|clusters| clusters.into_values().collect())
+        .collect();
+    let forbidden: HashMap<_, _> = chunked_reads
+        .iter()
+        .map(|read| (read.id.clone(), read.forbidden.clone()))
+        .collect();
+    let predictions = resume_clustering(
+        clusterings,
+        windowlen,
+        &forbidden,
+        initial_clusters,
+        &data,
+        params,
+    );
+    let predictions: HashMap<_, _> = data
+        .iter()
+        .zip(predictions)
+        .map(|(read, asn)| (read.id(), asn))
+        .collect();
+    let assignments: Vec<_> = chunked_reads
+        .iter()
+        .map(|read| {
+            let asn = predictions.get(read.id.as_str()).copied().flatten();
+            (read.id.clone(), asn)
+        })
+        .collect();
+    let (assignments, gfa, contigs, posteriors) =
+        assemble::assemble_clusters(&chunked_reads, assignments);
     DecomposedResult {
         assignments,
+        posteriors,
+        phases: vec![],
+        stability: vec![],
+        traces: vec![],
         gfa,
         contigs,
         reads: chunked_reads,
@@ -157,7 +311,7 @@ fn initial_clustering(
     (dataset, labels, forbidden)
 }
 
-fn get_coverages(contigs: &last_tiling::Contigs, reads: &[ERead]) -> Vec<Vec<u32>> {
+pub(crate) fn get_coverages(contigs: &last_tiling::Contigs, reads: &[ERead]) -> Vec<Vec<u32>> {
     let mut coverage: Vec<Vec<_>> = contigs
         .get_last_units()
         .into_iter()
@@ -258,7 +412,25 @@ pub fn clustering_via_alignment(
     predictions
 }
 
-pub fn create_windows(idx: usize, len: usize, covs: &[u32]) -> Vec<(u16, u16, u16)> {
+pub fn create_windows(
+    idx: usize,
+    len: usize,
+    covs: &[u32],
+    params: &DecomposeParams,
+) -> Vec<(u16, u16, u16)> {
+    create_windows_with_breakpoints(idx, len, covs, &[], params)
+}
+
+/// Windows on the `idx`-th contig, laid out by `params.window_layout`.
+/// `breakpoints` are the units where windows are split in the `Breakpoint` layout.
+pub fn create_windows_with_breakpoints(
+    idx: usize,
+    len: usize,
+    covs: &[u32],
+    breakpoints: &[usize],
+    params: &DecomposeParams,
+) -> Vec<(u16, u16, u16)> {
+    let (window_size, overlap) = (params.window_size, params.overlap);
     let mean = covs.iter().sum::<u32>() / covs.len() as u32;
     let mean_sq = covs.iter().fold(0, |x, y| x + y * y) / covs.len() as u32;
     let sd = ((mean_sq - mean * mean) as f64).sqrt().floor() as u32;
@@ -274,24 +446,35 @@ pub fn create_windows(idx: usize, len: usize, covs: &[u32]) -> Vec<(u16, u16, u1
         }
         sub_windows
             .into_iter()
-            .filter(|(s, e)| e - s > WINDOW_SIZE / 3)
+            .filter(|(s, e)| e - s > window_size / 3)
             .collect()
     };
+    let sub_windows = match params.window_layout {
+        WindowLayout::Breakpoint => windows::split_at(&sub_windows, breakpoints),
+        _ => sub_windows,
+    };
     sub_windows
         .into_iter()
         .flat_map(|(start, end)| {
-            if end - start < WINDOW_SIZE {
+            match params.window_layout {
+                WindowLayout::Fixed => {}
+                WindowLayout::Coverage => {
+                    return windows::by_coverage((start, end), covs, mean, params)
+                }
+                WindowLayout::Breakpoint => return windows::tile((start, end), params),
+            }
+            if end - start < window_size {
                 return vec![(start, end)];
             }
-            let window_num = (end - start) / (WINDOW_SIZE - OVERLAP);
-            let last_pos = start + (window_num - 1) * (WINDOW_SIZE - OVERLAP);
+            let window_num = (end - start) / (window_size - overlap);
+            let last_pos = start + (window_num - 1) * (window_size - overlap);
             assert!(last_pos >= 1);
-            if end - last_pos < WINDOW_SIZE / 2 {
+            if end - last_pos < window_size / 2 {
                 (0..window_num)
                     .map(|i| {
-                        let s = start + i * (WINDOW_SIZE - OVERLAP);
+                        let s = start + i * (window_size - overlap);
                         if i < last_pos - 1 {
-                            (s, s + WINDOW_SIZE)
+                            (s, s + window_size)
                         } else {
                             (s, end)
                         }
@@ -300,9 +483,9 @@ pub fn create_windows(idx: usize, len: usize, covs: &[u32]) -> Vec<(u16, u16, u1
             } else {
                 (0..=window_num)
                     .map(|i| {
-                        let s = start + i * (WINDOW_SIZE - OVERLAP);
+                        let s = start + i * (window_size - overlap);
                         if i < last_pos {
-                            (s, s + WINDOW_SIZE)
+                            (s, s + window_size)
                         } else {
                             (s, end)
                         }
@@ -320,6 +503,7 @@ fn select_within(
     label: &[u8],
     forbidden: &HashMap<String, Vec<u8>>,
     init_cluster: &[Cluster],
+    params: &DecomposeParams,
 ) -> (Vec<ERead>, Vec<u8>, Vec<Vec<u8>>) {
     let (mut s_data, mut s_label, mut s_forbid) = (vec![], vec![], vec![]);
     debug!("Selecting {}\t{}\t{}...", contig, start, end);
@@ -338,7 +522,7 @@ fn select_within(
     for (idx, read) in data.iter().enumerate() {
         let original_len = read.seq().len();
         let read = read.clone_within(contig, start, end);
-        let unit_thr = (MIN_LEN / last_tiling::UNIT_SIZE).min(original_len / 2);
+        let unit_thr = (params.min_len / last_tiling::UNIT_SIZE).min(original_len / 2);
         if read.seq().len() > unit_thr {
             let mut forbid = forbidden.get(read.id()).cloned().unwrap_or_else(Vec::new);
             forbid.extend(additional_forbiddens(&read));
@@ -359,13 +543,14 @@ fn find_matching(
     forbidden: &HashMap<String, Vec<u8>>,
     initial_clusters: &[Cluster],
     lengths: &HashMap<String, usize>,
+    params: &DecomposeParams,
 ) -> Vec<(usize, usize)> {
     let node1 = prev.len();
     let node2 = after.len();
     let filter_short = |hm: &HashSet<String>| {
         hm.iter()
             .filter(|&id| match lengths.get(id) {
-                Some(&res) => res > MIN_LEN / last_tiling::UNIT_SIZE,
+                Some(&res) => res > params.min_len / last_tiling::UNIT_SIZE,
                 None => false,
             })
             .cloned()
@@ -407,9 +592,13 @@ fn find_matching(
                 .filter_map(|(to, (cl2, cl2_boundary))| {
                     let intersect = cl1.intersection(cl2).count().pow(2);
                     let union = cl1_boundary.len() * cl2_boundary.len();
-                    let sim = (intersect as f64 / union as f64).sqrt();
+                    let sim = if union == 0 {
+                        0.
+                    } else {
+                        (intersect as f64 / union as f64).sqrt()
+                    };
                     debug!("{}->({:.3}={}/{})->{}", from, sim, intersect, union, to);
-                    if sim > CONNECTION_THR || union == 0 {
+                    if sim > params.connection_thr || union == 0 {
                         Some((to, sim))
                     } else {
                         None
@@ -485,6 +674,10 @@ pub struct Entry<'a> {
     pub forbid: &'a [u8],
     pub label: Option<u8>,
     pub assignment: u8,
+    /// The posterior probability on each cluster in the window.
+    pub posterior: Vec<f64>,
+    /// The mean co-assignment with the other reads in the cluster, see `consensus`.
+    pub stability: f64,
 }
 impl<'a> Entry<'a> {
     fn new(
@@ -505,19 +698,31 @@ impl<'a> Entry<'a> {
             forbid,
             label,
             assignment: 0,
+            posterior: vec![],
+            stability: 1.,
         }
     }
 }
 
+/// Cluster the reads in each window. If `params.consensus_seeds` is more than one,
+/// the stability of each window is returned as well. If `params.gibbs_trace` is true,
+/// so are the runs of the sampler on each window.
+#[allow(clippy::too_many_arguments)]
 pub fn clustering_chunking<'a>(
     data: &'a [ERead],
     label: &[u8],
     forbidden: &'a HashMap<String, Vec<u8>>,
+    contacts: &contacts::Contacts,
     cluster_num: usize,
     c: &Config,
     limit: u64,
     windows: &[(u16, u16, u16)],
-) -> HashMap<String, Vec<Entry<'a>>> {
+    params: &DecomposeParams,
+) -> (
+    HashMap<String, Vec<Entry<'a>>>,
+    Vec<consensus::WindowStability>,
+    Vec<gibbs_trace::WindowTrace>,
+) {
     let mut pileups: Vec<Vec<_>> = vec![vec![]; windows.len()];
     for (pos, &(contig, start, end)) in windows.iter().enumerate() {
         for (idx, read) in data.iter().enumerate() {
@@ -555,11 +760,11 @@ pub fn clustering_chunking<'a>(
         });
     });
     // Parallelize here to get most efficient algorithm.
-    pileups
+    let (stability, traces): (Vec<_>, Vec<_>) = pileups
         .par_iter_mut()
         .zip(windows.into_par_iter())
         .enumerate()
-        .for_each(|(idx, (pileup, range))| {
+        .map(|(idx, (pileup, range))| {
             let label_map = pileup.iter().fold(HashMap::new(), |mut res, entry| {
                 let next = res.len() as u8;
                 if let Some(cluster) = entry.label {
@@ -597,16 +802,123 @@ pub fn clustering_chunking<'a>(
             let id = idx as u64;
             // Do not parallelize.
             use poa_clustering::ClusteringConfig;
-            let config =
+            let mut config =
                 ClusteringConfig::new(chain_len, cluster_num, limit, coverage, id, false, c);
+            if !contacts.is_empty() {
+                let index: HashMap<&str, usize> =
+                    pileup.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+                config.links = pileup
+                    .iter()
+                    .map(|e| {
+                        contacts
+                            .partners(e.id)
+                            .iter()
+                            .filter_map(|(id, w)| {
+                                Some((*index.get(id.as_str())?, w * params.contact_weight))
+                            })
+                            .collect()
+                    })
+                    .collect();
+            }
+            if params.gibbs_trace {
+                config.trace = Some(gibbs_trace::Recorder::default());
+            }
+            let recorder = config.trace.clone();
+            let trace = || {
+                recorder.as_ref().map(|r| gibbs_trace::WindowTrace {
+                    window: idx,
+                    range: *range,
+                    runs: r.take(),
+                })
+            };
             let alnparam = &poa_clustering::DEFAULT_ALN;
-            let predictions =
-                poa_clustering::gibbs_sampling(&data, &labels, None, &forbs, alnparam, config);
+            let (predictions, posteriors) = match params.model_selection {
+                ModelSelection::Fixed => poa_clustering::gibbs_sampling_with_posterior(
+                    &data,
+                    &labels,
+                    None,
+                    &forbs,
+                    alnparam,
+                    config.clone(),
+                    params,
+                ),
+                _ => {
+                    // Labeled clusters and at least one more cluster for unlabeled reads.
+                    let min_cluster = label_map.len() + 1;
+                    let max_cluster = params.max_cluster_num.max(min_cluster);
+                    let range = (min_cluster, max_cluster);
+                    poa_clustering::gibbs_sampling_select_k(
+                        &data,
+                        &labels,
+                        None,
+                        &forbs,
+                        alnparam,
+                        config.clone(),
+                        range,
+                        params,
+                    )
+                }
+            };
+            pileup
+                .iter_mut()
+                .zip(predictions.iter())
+                .zip(posteriors)
+                .for_each(|((mut e, &p), post)| {
+                    e.assignment = p;
+                    e.posterior = post;
+                });
+            if params.consensus_seeds <= 1 || pileup.is_empty() {
+                return (None, trace());
+            }
+            // Run the sampler with the other seeds under the selected number of clusters.
+            let cluster_num = pileup.iter().map(|e| e.posterior.len()).max().unwrap();
+            let config = ClusteringConfig {
+                cluster_num,
+                ..config
+            };
+            let (predictions, posteriors, consensus) = poa_clustering::gibbs_sampling_consensus(
+                &data,
+                &labels,
+                &forbs,
+                alnparam,
+                config,
+                predictions,
+                params,
+            );
+            let mean_stability = consensus.stability.iter().sum::<f64>() / coverage as f64;
             pileup
                 .iter_mut()
                 .zip(predictions)
-                .for_each(|(mut e, p)| e.assignment = p);
-        });
+                .zip(posteriors)
+                .zip(consensus.stability.iter())
+                .for_each(|(((mut e, p), post), &stability)| {
+                    e.assignment = p;
+                    e.posterior = post;
+                    e.stability = stability;
+                });
+            let mut stability = consensus::WindowStability {
+                window: idx,
+                range: *range,
+                seeds: params.consensus_seeds,
+                agreement: consensus.agreement,
+                mean_stability,
+                read_stability: consensus.stability,
+                unstable_reads: 0,
+                unstable: false,
+            };
+            stability.judge(params.stability_thr);
+            if stability.unstable {
+                warn!(
+                    "{}-{:?}: unstable clustering(agreement {:.3} over {} seeds)",
+                    idx, range, consensus.agreement, params.consensus_seeds
+                );
+            }
+            (Some(stability), trace())
+        })
+        .unzip();
+    let stability: Vec<_> = stability.into_iter().flatten().collect();
+    let traces: Vec<_> = traces.into_iter().flatten().collect();
+    windows::stitch(&mut pileups, windows);
     if log_enabled!(log::Level::Debug) {
         let id2desc: HashMap<_, _> = data
             .iter()
@@ -636,7 +948,7 @@ pub fn clustering_chunking<'a>(
     encoded_reads
         .iter_mut()
         .for_each(|(_, entries)| entries.sort_by_key(|e| e.read_position));
-    encoded_reads
+    (encoded_reads, stability, traces)
 }
 
 fn merge_windows_by_bipartite(
@@ -645,6 +957,7 @@ fn merge_windows_by_bipartite(
     forbidden: &HashMap<String, Vec<u8>>,
     initial_clusters: &[Cluster],
     data: &[ERead],
+    params: &DecomposeParams,
 ) -> Vec<HashSet<String>> {
     let max_cluster_num = clusterings.iter().map(|e| e.len()).max().unwrap_or(0);
     let lengths: HashMap<String, usize> = data
@@ -655,9 +968,13 @@ fn merge_windows_by_bipartite(
     for idx in 0..clusterings.len() {
         let prev_idx = idx;
         let after_idx = (idx + 1) % clusterings.len();
+        if prev_idx == after_idx {
+            continue;
+        }
         let prev = &clusterings[prev_idx];
         let after = &clusterings[after_idx];
-        for (i, j) in find_matching(prev, after, forbidden, initial_clusters, &lengths) {
+        let matching = find_matching(prev, after, forbidden, initial_clusters, &lengths, params);
+        for (i, j) in matching {
             let i = i + prev_idx * max_cluster_num;
             let j = j + after_idx * max_cluster_num;
             fu.unite(i, j).unwrap();
@@ -699,13 +1016,14 @@ fn merge_by_initial_cluster(
     forbidden: &HashMap<String, Vec<u8>>,
     initial_clusters: &[Cluster],
     mut components: Vec<HashSet<String>>,
+    params: &DecomposeParams,
 ) -> Vec<HashSet<String>> {
     for cluster in initial_clusters {
         debug!("Merging by {:?}", cluster);
         components = {
             let (merged, mut result): (Vec<_>, Vec<_>) = components
                 .into_iter()
-                .partition(|c| is_overlap(c, cluster, forbidden));
+                .partition(|c| is_overlap(c, cluster, forbidden, params));
             debug!("There are {} cluster merged.", merged.len());
             let merged = merged.into_iter().fold(HashSet::new(), |mut acc, x| {
                 acc.extend(x);
@@ -723,12 +1041,13 @@ fn merge_with_background(
     initial_clusters: &[Cluster],
     components: Vec<HashSet<String>>,
     _forbidden: &HashMap<String, Vec<u8>>,
+    params: &DecomposeParams,
 ) -> Vec<HashSet<String>> {
     let (components, background): (Vec<_>, Vec<_>) =
         components.into_iter().partition(|component| {
             initial_clusters
                 .iter()
-                .any(|cl| cl.ids().intersection(component).count() > MERGE_THR)
+                .any(|cl| cl.ids().intersection(component).count() > params.merge_thr)
         });
     let mut background: HashSet<String> =
         background.into_iter().fold(HashSet::new(), |mut x, y| {
@@ -748,7 +1067,7 @@ fn merge_with_background(
     result
 }
 
-fn determine_occupy(components: &Vec<HashSet<String>>, data: &[ERead]) -> Vec<bool> {
+fn determine_occupy(components: &[HashSet<String>], data: &[ERead]) -> Vec<bool> {
     let (matrix_pos, chain_len) = poa_clustering::to_pos(data);
     let component_len = components.len();
     let components: HashMap<_, usize> =
@@ -761,7 +1080,7 @@ fn determine_occupy(components: &Vec<HashSet<String>>, data: &[ERead]) -> Vec<bo
                 }
                 x
             });
-    let mut count: Vec<_> = vec![vec![0; components.len()]; chain_len];
+    let mut count: Vec<_> = vec![vec![0; component_len]; chain_len];
     let mut total = vec![0; chain_len];
     for read in data.iter() {
         if let Some(&cl) = components.get(read.id()) {
@@ -791,45 +1110,53 @@ fn determine_occupy(components: &Vec<HashSet<String>>, data: &[ERead]) -> Vec<bo
         .collect()
 }
 
-// fn resume_clustering(
-//     clusterings: Vec<Vec<HashSet<String>>>,
-//     windowlen: usize,
-//     forbidden: &HashMap<String, Vec<u8>>,
-//     initial_clusters: &[Cluster],
-//     data: &[ERead],
-// ) -> Vec<Option<u8>> {
-//     debug!(
-//         "On windows:{}",
-//         clusterings.iter().map(|cl| cl.len()).sum::<usize>()
-//     );
-//     let components =
-//         merge_windows_by_bipartite(clusterings, windowlen, forbidden, initial_clusters, data);
-//     debug!("Merged:{}", components.len());
-//     let components = merge_by_initial_cluster(forbidden, initial_clusters, components);
-//     debug!("Merge by Init:{}", components.len());
-//     let components = if initial_clusters.is_empty() {
-//         components
-//     } else {
-//         merge_with_background(data, initial_clusters, components, forbidden)
-//     };
-//     debug!("Resulting in {} clusters.", components.len());
-//     let result: HashMap<String, u8> =
-//         components
-//             .into_iter()
-//             .enumerate()
-//             .fold(HashMap::new(), |mut acc, (idx, cluster)| {
-//                 for read_name in cluster {
-//                     acc.insert(read_name, idx as u8);
-//                 }
-//                 acc
-//             });
-//     data.iter().map(|r| result.get(r.id()).cloned()).collect()
-// }
+fn resume_clustering(
+    clusterings: Vec<Vec<HashSet<String>>>,
+    windowlen: usize,
+    forbidden: &HashMap<String, Vec<u8>>,
+    initial_clusters: &[Cluster],
+    data: &[ERead],
+    params: &DecomposeParams,
+) -> Vec<Option<u8>> {
+    debug!(
+        "On windows:{}",
+        clusterings.iter().map(|cl| cl.len()).sum::<usize>()
+    );
+    let components = merge_windows_by_bipartite(
+        clusterings,
+        windowlen,
+        forbidden,
+        initial_clusters,
+        data,
+        params,
+    );
+    debug!("Merged:{}", components.len());
+    let components = merge_by_initial_cluster(forbidden, initial_clusters, components, params);
+    debug!("Merge by Init:{}", components.len());
+    let components = if initial_clusters.is_empty() {
+        components
+    } else {
+        merge_with_background(data, initial_clusters, components, forbidden, params)
+    };
+    debug!("Resulting in {} clusters.", components.len());
+    let result: HashMap<String, u8> = components
+        .into_iter()
+        .filter(|cluster| !cluster.is_empty())
+        .enumerate()
+        .fold(HashMap::new(), |mut acc, (idx, cluster)| {
+            for read_name in cluster {
+                acc.insert(read_name, idx as u8);
+            }
+            acc
+        });
+    data.iter().map(|r| result.get(r.id()).cloned()).collect()
+}
 
 fn is_overlap(
     component: &HashSet<String>,
     cluster: &Cluster,
     forbids: &HashMap<String, Vec<u8>>,
+    params: &DecomposeParams,
 ) -> bool {
     let ngs: usize = component
         .iter()
@@ -838,7 +1165,7 @@ fn is_overlap(
         .count();
     let share = component.iter().filter(|id| cluster.has(id)).count();
     // debug!("Shares {} reads, NGS {} reads.", share, ngs);
-    share > MERGE_THR && ngs <= NG_THR
+    share > params.merge_thr && ngs <= params.ng_thr
 }
 
 fn dump_pred(assignments: &[Option<u8>], data: &[ERead], idx: usize) {
@@ -0,0 +1,440 @@
+//! Parameters of the decomposition.
+//! The default values are the ones tuned for the mitochondrial genomes of plants.
+//! Every field can be omitted in a configuration file, and the default value is used for it.
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DecomposeParams {
+    // ---- Windows ----
+    /// The number of units in a window.
+    pub window_size: usize,
+    /// The number of units shared by two adjacent windows. The labels of reads in the shared
+    /// units are reconciled between the windows.
+    pub overlap: usize,
+    /// How to lay out the windows on the contigs.
+    pub window_layout: WindowLayout,
+    /// Reads shorter than this length(bp) are not used to connect windows.
+    pub min_len: usize,
+    /// Minimum similarity to connect two clusters in adjacent windows.
+    pub connection_thr: f64,
+    /// Minimum number of reads shared by a component and an initial cluster to be merged.
+    pub merge_thr: usize,
+    /// Maximum number of reads forbidden to be in an initial cluster, to merge a component into it.
+    pub ng_thr: usize,
+    // ---- Critical regions ----
+    /// Minimum number of reads supporting a critical region, used when `breakpoint_test` is `fixed`.
+    /// Also, minimum number of reads shared by two critical regions to be merged.
+    pub coverage_thr: usize,
+    /// Maximum number of reads spanning either of two critical regions to be merged.
+    pub ngs_thr: usize,
+    /// Margin(units) around a critical region to check whether a read spans it.
+    pub check_thr: u16,
+    /// Minimum number of jumps at a position to start searching a contig pair.
+    pub jump_thr: usize,
+    /// Margin(units) to search jumps around a contig pair.
+    pub offset: usize,
+    /// Peaks of start/stop counts closer than this(units) are merged.
+    pub peak_merge_thr: usize,
+    /// How to decide whether a candidate critical region is kept.
+    pub breakpoint_test: BreakpointTest,
+    /// The false discovery rate of critical regions, used when `breakpoint_test` is `poisson`.
+    pub fdr: f64,
+    // ---- Gibbs sampling ----
+    /// The inverse temperature is multiplied by this value when the likelihood decreases.
+    pub beta_increase: f64,
+    /// The inverse temperature is multiplied by this value when the likelihood increases.
+    pub beta_decrease: f64,
+    /// The maximum of the inverse temperature.
+    pub beta_max: f64,
+    /// The number of consecutive stable iterations to stop sampling.
+    pub stable_limit: u32,
+    /// Reads whose posterior probability on the assigned cluster is less than this value
+    /// are reported as ambiguous.
+    pub min_confidence: f64,
+    /// How to determine the number of clusters in each window.
+    pub model_selection: ModelSelection,
+    /// The maximum number of clusters in a window, used when `model_selection` is not `fixed`.
+    pub max_cluster_num: usize,
+    /// The number of seeds to run the sampler on each window. If more than one, the consensus
+    /// of the runs is used, and the stability of the reads and the windows is reported.
+    pub consensus_seeds: usize,
+    /// Reads and windows with stability less than this value are reported as unstable.
+    pub stability_thr: f64,
+    /// Whether to record the log-likelihood, the inverse temperature, the number of changed
+    /// assignments and the cluster sizes of each iteration of the sampler.
+    pub gibbs_trace: bool,
+    // ---- Contacts ----
+    /// The weight of the contacts between reads(e.g., Hi-C pairs) in the prior of the clustering.
+    /// Zero disables them in the clustering.
+    pub contact_weight: f64,
+    /// In the colour expansion on the de Bruijn graph, a read without labels moves to the cluster
+    /// of its contacts if the total weight of them is at least this value.
+    pub contact_thr: f64,
+    /// The weight of a must-link pair supplied by users, relative to a contact.
+    /// A cannot-link pair has the negative of this weight.
+    pub constraint_weight: f64,
+    // ---- Phasing ----
+    /// How to decode each read through the clusters of the windows before the assembly.
+    pub phasing: Phasing,
+    /// The number of Baum-Welch iterations to learn the transitions between clusters,
+    /// used when `phasing` is not `none`.
+    pub phasing_iteration: usize,
+    // ---- Assembly ----
+    /// How to merge the clusters of the windows into structures.
+    pub merge_strategy: MergeStrategy,
+    /// K of the de Bruijn graph on the chunked reads.
+    pub assemble_k: usize,
+    /// Nodes with weight less than this value are removed.
+    pub assemble_thr: usize,
+    // ---- Reports ----
+    /// The number of bootstrap replicates for confidence intervals.
+    pub bootstrap_num: usize,
+}
+
+impl std::default::Default for DecomposeParams {
+    fn default() -> Self {
+        Self {
+            window_size: 20,
+            overlap: 0,
+            window_layout: WindowLayout::Fixed,
+            min_len: 6_000,
+            connection_thr: 0.5,
+            merge_thr: 50,
+            ng_thr: 10,
+            coverage_thr: 15,
+            ngs_thr: 10,
+            check_thr: 4,
+            jump_thr: 5,
+            offset: 5,
+            peak_merge_thr: 5,
+            breakpoint_test: BreakpointTest::Fixed,
+            fdr: 0.01,
+            beta_increase: 1.02,
+            beta_decrease: 1.05,
+            beta_max: 0.8,
+            stable_limit: 6,
+            min_confidence: 0.,
+            model_selection: ModelSelection::Fixed,
+            max_cluster_num: 6,
+            consensus_seeds: 1,
+            stability_thr: 0.8,
+            gibbs_trace: false,
+            contact_weight: 1.,
+            contact_thr: 5.,
+            constraint_weight: 10.,
+            phasing: Phasing::None,
+            phasing_iteration: 5,
+            merge_strategy: MergeStrategy::Dbg,
+            assemble_k: 5,
+            assemble_thr: 15,
+            bootstrap_num: 1_000,
+        }
+    }
+}
+
+/// The layout of the windows.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowLayout {
+    /// Windows of `window_size` units.
+    Fixed,
+    /// Windows with about `window_size` times the mean coverage of encoded units, between
+    /// a half and twice `window_size` units. Windows are shorter where the coverage is higher.
+    Coverage,
+    /// Windows of at most `window_size` units, split at the critical regions of the initial
+    /// clusters so that no window straddles a critical region.
+    Breakpoint,
+}
+
+impl std::str::FromStr for WindowLayout {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fixed" => Ok(WindowLayout::Fixed),
+            "coverage" => Ok(WindowLayout::Coverage),
+            "breakpoint" => Ok(WindowLayout::Breakpoint),
+            _ => Err(invalid_data(format!("Unknown window layout:{}", s))),
+        }
+    }
+}
+
+/// The criterion to select the number of clusters in each window.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelSelection {
+    /// Use the number of clusters given by the user(and the number of initial clusters).
+    Fixed,
+    /// Bayesian information criterion.
+    Bic,
+    /// Akaike information criterion.
+    Aic,
+}
+
+impl std::str::FromStr for ModelSelection {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "fixed" => Ok(ModelSelection::Fixed),
+            "bic" => Ok(ModelSelection::Bic),
+            "aic" => Ok(ModelSelection::Aic),
+            _ => Err(invalid_data(format!("Unknown model selection:{}", s))),
+        }
+    }
+}
+
+impl ModelSelection {
+    /// The penalty for `num_params` free parameters estimated from `num_data` data points.
+    /// The score is `-2 ln L + penalty`, thus smaller is better.
+    pub fn penalty(&self, num_params: usize, num_data: usize) -> f64 {
+        match self {
+            ModelSelection::Fixed => 0.,
+            ModelSelection::Bic => num_params as f64 * (num_data.max(1) as f64).ln(),
+            ModelSelection::Aic => 2. * num_params as f64,
+        }
+    }
+}
+
+/// The decoding of reads through the clusters of adjacent windows.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phasing {
+    /// Keep the clusters of each window as they are.
+    None,
+    /// The most probable path of clusters along each read.
+    Viterbi,
+    /// The most probable cluster at each window, from the posterior probabilities.
+    ForwardBackward,
+}
+
+impl std::str::FromStr for Phasing {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Phasing::None),
+            "viterbi" => Ok(Phasing::Viterbi),
+            "forward-backward" => Ok(Phasing::ForwardBackward),
+            _ => Err(invalid_data(format!("Unknown phasing:{}", s))),
+        }
+    }
+}
+
+/// The strategy to merge the clusters of the windows.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    /// Color expansion on the de Bruijn graph of the chunked reads, with `assemble_k` and
+    /// `assemble_thr`.
+    Dbg,
+    /// Maximum weight matchings between the clusters of adjacent windows, with `min_len`,
+    /// `connection_thr`, `merge_thr`, and `ng_thr`.
+    Bipartite,
+}
+
+impl std::str::FromStr for MergeStrategy {
+    type Err = std::io::Error;
+    fn from_str(s: &str) -> std::io::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "dbg" => Ok(MergeStrategy::Dbg),
+            "bipartite" => Ok(MergeStrategy::Bipartite),
+            _ => Err(invalid_data(format!("Unknown merge strategy:{}", s))),
+        }
+    }
+}
+
+/// The criterion to keep a candidate critical region.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BreakpointTest {
+    /// Keep candidates supported by more than `coverage_thr` reads.
+    Fixed,
+    /// Test candidates supported by more than `jump_thr` reads against the background rate of
+    /// chimeric reads, and keep the ones passing `fdr`.
+    Poisson,
+}
+
+impl BreakpointTest {
+    /// The number of supporting reads a candidate should exceed.
+    pub fn min_support(&self, params: &DecomposeParams) -> usize {
+        match self {
+            BreakpointTest::Fixed => params.coverage_thr,
+            BreakpointTest::Poisson => params.jump_thr,
+        }
+    }
+}
+
+pub(crate) fn invalid_data<E: std::fmt::Display>(why: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, why.to_string())
+}
+
+impl DecomposeParams {
+    /// The parameters read by `initial_clusters`.
+    pub const INITIAL_CLUSTERS: &'static [&'static str] = &[
+        "coverage_thr",
+        "ngs_thr",
+        "check_thr",
+        "jump_thr",
+        "offset",
+        "peak_merge_thr",
+        "breakpoint_test",
+        "fdr",
+    ];
+    /// The parameters read by `cluster_reads`, other than the ones of `initial_clusters`.
+    pub const CLUSTERING: &'static [&'static str] = &[
+        "window_size",
+        "overlap",
+        "window_layout",
+        "beta_increase",
+        "beta_decrease",
+        "beta_max",
+        "stable_limit",
+        "model_selection",
+        "max_cluster_num",
+        "consensus_seeds",
+        "contact_weight",
+        "constraint_weight",
+        "gibbs_trace",
+    ];
+    /// The parameters read only after `cluster_reads`, to merge the windows, to assemble
+    /// the structures, and to write the reports.
+    pub const AFTER_CLUSTERING: &'static [&'static str] = &[
+        "min_len",
+        "connection_thr",
+        "merge_thr",
+        "ng_thr",
+        "min_confidence",
+        "stability_thr",
+        "phasing",
+        "phasing_iteration",
+        "merge_strategy",
+        "assemble_k",
+        "assemble_thr",
+        "contact_thr",
+        "bootstrap_num",
+    ];
+    /// The parameters in `names` as a JSON object, to be used as a part of a checkpoint key.
+    pub fn key_of(&self, names: &[&str]) -> String {
+        let params = serde_json::to_value(self).unwrap();
+        let subset: serde_json::Map<_, _> = names
+            .iter()
+            .map(|&name| (name.to_string(), params[name].clone()))
+            .collect();
+        serde_json::Value::Object(subset).to_string()
+    }
+    /// Parse a configuration in JSON.
+    pub fn from_json(input: &str) -> std::io::Result<Self> {
+        serde_json::de::from_str(input).map_err(invalid_data)
+    }
+    /// Parse a configuration in TOML.
+    pub fn from_toml(input: &str) -> std::io::Result<Self> {
+        toml::de::from_str(input).map_err(invalid_data)
+    }
+    /// Load a configuration file. If the extension is `.json`, it is parsed as JSON.
+    /// Otherwise, it is parsed as TOML.
+    pub fn from_file<P: AsRef<Path>>(file: P) -> std::io::Result<Self> {
+        let input = std::fs::read_to_string(&file)?;
+        match file.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json(&input),
+            _ => Self::from_toml(&input),
+        }
+    }
+    pub fn to_toml(&self) -> String {
+        toml::ser::to_string_pretty(self).unwrap()
+    }
+    /// Overwrite a parameter by its name, such as `window_size` and "30".
+    /// A value which is not valid JSON is treated as a string, such as `model_selection` and "bic".
+    pub fn set(&mut self, key: &str, value: &str) -> std::io::Result<()> {
+        let mut params = serde_json::to_value(&*self).map_err(invalid_data)?;
+        let entry = params
+            .get_mut(key)
+            .ok_or_else(|| invalid_data(format!("Unknown parameter:{}", key)))?;
+        *entry = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.to_string()));
+        *self = serde_json::from_value(params)
+            .map_err(|why| invalid_data(format!("{}={}:{}", key, value, why)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn config_file_test() {
+        let params = DecomposeParams::from_toml("window_size = 30\nbeta_max = 0.5\n").unwrap();
+        let expected = DecomposeParams {
+            window_size: 30,
+            beta_max: 0.5,
+            ..DecomposeParams::default()
+        };
+        assert_eq!(params, expected);
+        assert_eq!(
+            DecomposeParams::from_toml(&expected.to_toml()).unwrap(),
+            expected
+        );
+    }
+    #[test]
+    fn set_test() {
+        let mut params = DecomposeParams::default();
+        params.set("check_thr", "10").unwrap();
+        assert_eq!(params.check_thr, 10);
+        assert!(params.set("no_such_param", "10").is_err());
+        assert!(params.set("check_thr", "-1").is_err());
+    }
+    #[test]
+    fn model_selection_test() {
+        let mut params = DecomposeParams::default();
+        params.set("model_selection", "bic").unwrap();
+        assert_eq!(params.model_selection, ModelSelection::Bic);
+        assert_eq!(
+            "AIC".parse::<ModelSelection>().unwrap(),
+            ModelSelection::Aic
+        );
+    }
+    #[test]
+    fn window_layout_test() {
+        let mut params = DecomposeParams::default();
+        params.set("window_layout", "breakpoint").unwrap();
+        assert_eq!(params.window_layout, WindowLayout::Breakpoint);
+        assert!("straddle".parse::<WindowLayout>().is_err());
+    }
+    #[test]
+    fn phasing_test() {
+        let mut params = DecomposeParams::default();
+        params.set("phasing", "forward-backward").unwrap();
+        assert_eq!(params.phasing, Phasing::ForwardBackward);
+        assert_eq!("Viterbi".parse::<Phasing>().unwrap(), Phasing::Viterbi);
+    }
+    #[test]
+    fn merge_strategy_test() {
+        let mut params = DecomposeParams::default();
+        params.set("merge_strategy", "bipartite").unwrap();
+        assert_eq!(params.merge_strategy, MergeStrategy::Bipartite);
+    }
+    #[test]
+    fn stages_test() {
+        // Each parameter is read in exactly one stage.
+        let stages = [
+            DecomposeParams::INITIAL_CLUSTERS,
+            DecomposeParams::CLUSTERING,
+            DecomposeParams::AFTER_CLUSTERING,
+        ];
+        let mut names: Vec<_> = stages.iter().flat_map(|s| s.iter()).copied().collect();
+        names.sort();
+        let params = serde_json::to_value(DecomposeParams::default()).unwrap();
+        let mut fields: Vec<_> = params
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        fields.sort();
+        assert_eq!(names, fields);
+        let mut params = DecomposeParams::default();
+        let key = params.key_of(DecomposeParams::CLUSTERING);
+        params.bootstrap_num = 10;
+        assert_eq!(params.key_of(DecomposeParams::CLUSTERING), key);
+        params.beta_max = 0.1;
+        assert_ne!(params.key_of(DecomposeParams::CLUSTERING), key);
+    }
+}
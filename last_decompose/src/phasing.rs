@@ -0,0 +1,399 @@
+//! Phasing reads through the clusters of the windows by a hidden Markov model.
+//! The hidden state at each node of a read is the true cluster in the window of the node,
+//! and the emission is the cluster(or the posterior probabilities) given by the clustering
+//! of the window. The transition probabilities between the clusters of two windows adjacent
+//! in reads are learned from the reads by the Baum-Welch algorithm. Then, each read is decoded
+//! by the Viterbi algorithm or by the forward-backward algorithm.
+use super::assemble::ChunkedRead;
+use super::{DecomposeParams, Phasing};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// The probability that the clustering of a window is wrong.
+const EMISSION_ERROR: f64 = 0.05;
+/// The pseudo count of each cluster and each transition.
+const PSEUDO_COUNT: f64 = 1.;
+
+/// The decoded clusters of a read.
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub id: String,
+    /// The windows traversed by the read, in the order of the read.
+    pub windows: Vec<usize>,
+    /// The clusters given by the clustering of each window.
+    pub before: Vec<u8>,
+    /// The decoded clusters.
+    pub after: Vec<u8>,
+    /// The posterior probability of the decoded clusters as a whole.
+    pub probability: f64,
+    /// The minimum posterior probability of the decoded cluster among the windows.
+    pub min_posterior: f64,
+}
+
+impl Phase {
+    pub const HEADER: &'static str =
+        "read\twindows\tbefore\tafter\tchanged\tprobability\tmin_posterior";
+    /// The number of windows where the cluster is changed by the decoding.
+    pub fn changed(&self) -> usize {
+        self.before
+            .iter()
+            .zip(self.after.iter())
+            .filter(|(b, a)| b != a)
+            .count()
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let join = |xs: &mut dyn Iterator<Item = String>| xs.collect::<Vec<_>>().join(",");
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}",
+            self.id,
+            join(&mut self.windows.iter().map(|x| x.to_string())),
+            join(&mut self.before.iter().map(|x| x.to_string())),
+            join(&mut self.after.iter().map(|x| x.to_string())),
+            self.changed(),
+            self.probability,
+            self.min_posterior
+        )
+    }
+}
+
+/// Decode the reads without labels by `params.phasing`. The cluster of each node is replaced
+/// with the decoded one, and the posterior probabilities with the ones by the forward-backward
+/// algorithm. Reads with labels are used to learn the transitions, but are not changed.
+pub fn phase_reads(reads: &mut [ChunkedRead], params: &DecomposeParams) -> Vec<Phase> {
+    if params.phasing == Phasing::None {
+        return vec![];
+    }
+    let mut model = Model::new(reads);
+    for i in 0..params.phasing_iteration {
+        let (next, lk) = model.reestimate(reads);
+        debug!("Baum-Welch {}:{:.3}", i, lk);
+        model = next;
+    }
+    let phases: Vec<_> = reads
+        .par_iter_mut()
+        .filter(|read| read.label.is_none() && !read.nodes.is_empty())
+        .map(|read| model.decode(read, params.phasing))
+        .collect();
+    let changed = phases.iter().filter(|p| p.changed() > 0).count();
+    debug!(
+        "{} out of {} reads changed by phasing",
+        changed,
+        phases.len()
+    );
+    phases
+}
+
+struct Model {
+    // The number of clusters in each window.
+    states: HashMap<usize, usize>,
+    // The count of each cluster in each window.
+    clusters: HashMap<usize, Vec<f64>>,
+    // The joint count of the clusters in windows `(a, b)` adjacent in reads, with `a <= b`.
+    joint: HashMap<(usize, usize), Vec<Vec<f64>>>,
+}
+
+// The parameters of the hidden Markov model along a read.
+struct Chain {
+    prior: Vec<f64>,
+    emissions: Vec<Vec<f64>>,
+    // `transitions[t][g][h]` is the probability from `g` at `t` to `h` at `t+1`.
+    transitions: Vec<Vec<Vec<f64>>>,
+}
+
+struct ForwardBackward {
+    posteriors: Vec<Vec<f64>>,
+    // The expected number of each transition.
+    transitions: Vec<Vec<Vec<f64>>>,
+    log_likelihood: f64,
+}
+
+impl Model {
+    fn new(reads: &[ChunkedRead]) -> Self {
+        let mut states: HashMap<usize, usize> = HashMap::new();
+        for node in reads.iter().flat_map(|read| read.nodes.iter()) {
+            let k = (node.cluster as usize + 1).max(node.posterior.len());
+            let max = states.entry(node.window_position).or_default();
+            *max = (*max).max(k);
+        }
+        let mut model = Self::empty(states);
+        for read in reads.iter() {
+            for node in read.nodes.iter() {
+                model.add_cluster(node.window_position, node.cluster as usize, 1.);
+            }
+            for w in read.nodes.windows(2) {
+                let (a, b) = (w[0].window_position, w[1].window_position);
+                model.add_transition((a, b), (w[0].cluster as usize, w[1].cluster as usize), 1.);
+            }
+        }
+        model
+    }
+    fn empty(states: HashMap<usize, usize>) -> Self {
+        Self {
+            states,
+            clusters: HashMap::new(),
+            joint: HashMap::new(),
+        }
+    }
+    fn add_cluster(&mut self, window: usize, cluster: usize, count: f64) {
+        let k = self.states[&window];
+        self.clusters
+            .entry(window)
+            .or_insert_with(|| vec![PSEUDO_COUNT; k])[cluster] += count;
+    }
+    fn add_transition(&mut self, (a, b): (usize, usize), (g, h): (usize, usize), count: f64) {
+        let (key, (g, h)) = if a <= b {
+            ((a, b), (g, h))
+        } else {
+            ((b, a), (h, g))
+        };
+        let (k_a, k_b) = (self.states[&key.0], self.states[&key.1]);
+        self.joint
+            .entry(key)
+            .or_insert_with(|| vec![vec![PSEUDO_COUNT; k_b]; k_a])[g][h] += count;
+    }
+    fn prior(&self, window: usize) -> Vec<f64> {
+        normalize(&self.clusters[&window]).0
+    }
+    fn transition(&self, a: usize, b: usize) -> Vec<Vec<f64>> {
+        if a <= b {
+            self.joint[&(a, b)]
+                .iter()
+                .map(|row| normalize(row).0)
+                .collect()
+        } else {
+            let joint = &self.joint[&(b, a)];
+            (0..self.states[&a])
+                .map(|g| {
+                    let column: Vec<_> = joint.iter().map(|row| row[g]).collect();
+                    normalize(&column).0
+                })
+                .collect()
+        }
+    }
+    fn chain(&self, read: &ChunkedRead) -> Chain {
+        let emissions = read
+            .nodes
+            .iter()
+            .map(|node| {
+                let k = self.states[&node.window_position];
+                (0..k)
+                    .map(|h| {
+                        let p = if !node.posterior.is_empty() {
+                            node.posterior.get(h).copied().unwrap_or(0.)
+                        } else if node.cluster as usize == h {
+                            1.
+                        } else {
+                            0.
+                        };
+                        (1. - EMISSION_ERROR) * p + EMISSION_ERROR / k as f64
+                    })
+                    .collect()
+            })
+            .collect();
+        let transitions = read
+            .nodes
+            .windows(2)
+            .map(|w| self.transition(w[0].window_position, w[1].window_position))
+            .collect();
+        Chain {
+            prior: self.prior(read.nodes[0].window_position),
+            emissions,
+            transitions,
+        }
+    }
+    fn reestimate(&self, reads: &[ChunkedRead]) -> (Self, f64) {
+        let reads: Vec<_> = reads.iter().filter(|r| !r.nodes.is_empty()).collect();
+        let results: Vec<_> = reads
+            .par_iter()
+            .map(|read| self.chain(read).forward_backward())
+            .collect();
+        let mut model = Self::empty(self.states.clone());
+        let mut lk = 0.;
+        for (read, result) in reads.iter().zip(results) {
+            lk += result.log_likelihood;
+            for (node, posterior) in read.nodes.iter().zip(result.posteriors.iter()) {
+                for (h, &p) in posterior.iter().enumerate() {
+                    model.add_cluster(node.window_position, h, p);
+                }
+            }
+            for (w, xi) in read.nodes.windows(2).zip(result.transitions.iter()) {
+                let (a, b) = (w[0].window_position, w[1].window_position);
+                for (g, row) in xi.iter().enumerate() {
+                    for (h, &x) in row.iter().enumerate() {
+                        model.add_transition((a, b), (g, h), x);
+                    }
+                }
+            }
+        }
+        (model, lk)
+    }
+    fn decode(&self, read: &mut ChunkedRead, phasing: Phasing) -> Phase {
+        let chain = self.chain(read);
+        let result = chain.forward_backward();
+        let path = match phasing {
+            Phasing::Viterbi => chain.viterbi(),
+            _ => result.posteriors.iter().map(|p| argmax(p)).collect(),
+        };
+        let probability = (chain.log_joint(&path) - result.log_likelihood).exp();
+        let min_posterior = path
+            .iter()
+            .zip(result.posteriors.iter())
+            .map(|(&h, p)| p[h])
+            .fold(1., f64::min);
+        let windows = read.nodes.iter().map(|n| n.window_position).collect();
+        let before = read.nodes.iter().map(|n| n.cluster).collect();
+        for ((node, &h), posterior) in read
+            .nodes
+            .iter_mut()
+            .zip(path.iter())
+            .zip(result.posteriors)
+        {
+            node.cluster = h as u8;
+            node.posterior = posterior;
+        }
+        Phase {
+            id: read.id.clone(),
+            windows,
+            before,
+            after: path.iter().map(|&h| h as u8).collect(),
+            probability,
+            min_posterior,
+        }
+    }
+}
+
+impl Chain {
+    // Scaled forward-backward algorithm.
+    fn forward_backward(&self) -> ForwardBackward {
+        let len = self.emissions.len();
+        let mut forward = Vec::with_capacity(len);
+        let mut scales = Vec::with_capacity(len);
+        let first: Vec<_> = self
+            .prior
+            .iter()
+            .zip(&self.emissions[0])
+            .map(|(p, e)| p * e)
+            .collect();
+        let (first, scale) = normalize(&first);
+        forward.push(first);
+        scales.push(scale);
+        for t in 1..len {
+            let (prev, trans) = (&forward[t - 1], &self.transitions[t - 1]);
+            let current: Vec<_> = self.emissions[t]
+                .iter()
+                .enumerate()
+                .map(|(h, e)| prev.iter().zip(trans).map(|(a, tr)| a * tr[h]).sum::<f64>() * e)
+                .collect();
+            let (current, scale) = normalize(&current);
+            forward.push(current);
+            scales.push(scale);
+        }
+        let mut backward = vec![vec![]; len];
+        backward[len - 1] = vec![1.; self.emissions[len - 1].len()];
+        for t in (0..len - 1).rev() {
+            let (next, emission) = (&backward[t + 1], &self.emissions[t + 1]);
+            backward[t] = self.transitions[t]
+                .iter()
+                .map(|tr| {
+                    let sum: f64 = (0..next.len()).map(|h| tr[h] * emission[h] * next[h]).sum();
+                    sum / scales[t + 1]
+                })
+                .collect();
+        }
+        let posteriors = forward
+            .iter()
+            .zip(backward.iter())
+            .map(|(f, b)| {
+                let prod: Vec<_> = f.iter().zip(b).map(|(x, y)| x * y).collect();
+                normalize(&prod).0
+            })
+            .collect();
+        let transitions = (0..len - 1)
+            .map(|t| {
+                let (emission, next) = (&self.emissions[t + 1], &backward[t + 1]);
+                self.transitions[t]
+                    .iter()
+                    .zip(forward[t].iter())
+                    .map(|(tr, f)| {
+                        (0..next.len())
+                            .map(|h| f * tr[h] * emission[h] * next[h] / scales[t + 1])
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        ForwardBackward {
+            posteriors,
+            transitions,
+            log_likelihood: scales.iter().map(|s| s.ln()).sum(),
+        }
+    }
+    fn viterbi(&self) -> Vec<usize> {
+        let len = self.emissions.len();
+        let mut scores: Vec<f64> = self
+            .prior
+            .iter()
+            .zip(&self.emissions[0])
+            .map(|(p, e)| p.ln() + e.ln())
+            .collect();
+        let mut traceback: Vec<Vec<usize>> = Vec::with_capacity(len);
+        for t in 1..len {
+            let trans = &self.transitions[t - 1];
+            let (next, from): (Vec<f64>, Vec<usize>) = self.emissions[t]
+                .iter()
+                .enumerate()
+                .map(|(h, e)| {
+                    let (g, score) = scores
+                        .iter()
+                        .enumerate()
+                        .map(|(g, s)| (g, s + trans[g][h].ln()))
+                        .max_by(|a, b| (a.1).total_cmp(&b.1))
+                        .unwrap();
+                    (score + e.ln(), g)
+                })
+                .unzip();
+            scores = next;
+            traceback.push(from);
+        }
+        let mut path = vec![argmax(&scores)];
+        for from in traceback.iter().rev() {
+            let prev = from[*path.last().unwrap()];
+            path.push(prev);
+        }
+        path.reverse();
+        path
+    }
+    fn log_joint(&self, path: &[usize]) -> f64 {
+        let start = self.prior[path[0]].ln();
+        let emissions: f64 = self
+            .emissions
+            .iter()
+            .zip(path)
+            .map(|(e, &h)| e[h].ln())
+            .sum();
+        let transitions: f64 = self
+            .transitions
+            .iter()
+            .zip(path.windows(2))
+            .map(|(tr, w)| tr[w[0]][w[1]].ln())
+            .sum();
+        start + emissions + transitions
+    }
+}
+
+fn normalize(xs: &[f64]) -> (Vec<f64>, f64) {
+    let sum: f64 = xs.iter().sum();
+    (xs.iter().map(|x| x / sum).collect(), sum)
+}
+
+fn argmax(xs: &[f64]) -> usize {
+    xs.iter()
+        .enumerate()
+        .max_by(|a, b| (a.1).total_cmp(b.1))
+        .map(|x| x.0)
+        .unwrap_or(0)
+}
@@ -1,15 +1,15 @@
+use super::consensus;
+use super::gibbs_trace;
 use super::variant_calling;
-use super::{ERead, Read};
+use super::{DecomposeParams, ERead, Read};
 use poa_hmm::*;
 use rand::{seq::SliceRandom, Rng, SeedableRng};
 use rand_xoshiro::Xoshiro256StarStar;
 use rayon::prelude::*;
-const BETA_INCREASE: f64 = 1.02;
-const BETA_DECREASE: f64 = 1.05;
-const BETA_MAX: f64 = 0.8;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 const CHANGE_FRAC: f64 = 0.01;
 const SMALL_WEIGHT: f64 = 0.000_000_001;
-const STABLE_LIMIT: u32 = 6;
 #[derive(Debug, Clone)]
 pub struct ClusteringConfig {
     pub chain_len: usize,
@@ -19,8 +19,18 @@ pub struct ClusteringConfig {
     pub id: u64,
     pub is_par: bool,
     pub poa_config: poa_hmm::Config,
+    /// The index of the run on the window. The random number generator is seeded by
+    /// `id` and this value, and zero gives the default run.
     pub seed: u64,
     pub pick_prob: f64,
+    /// The weighted contacts from each read to the other reads, used as a prior
+    /// favouring the clusters of the linked reads.
+    pub links: Vec<Vec<(usize, f64)>>,
+    /// Where to record the runs of the sampler, if any.
+    pub trace: Option<gibbs_trace::Recorder>,
+    /// Whether to keep the models and the likelihoods on them between the picks of the
+    /// sampler, instead of building them on each pick. It does not change the result.
+    pub reuse_models: bool,
 }
 
 impl ClusteringConfig {
@@ -43,6 +53,9 @@ impl ClusteringConfig {
             poa_config: poa_config.clone(),
             seed: 0,
             pick_prob: 0.01,
+            links: vec![],
+            trace: None,
+            reuse_models: true,
         }
     }
 }
@@ -132,56 +145,201 @@ fn get_cluster_fraction(asns: &[u8], flags: &[bool], cluster_num: usize) -> Vec<
         .collect()
 }
 
-#[allow(clippy::too_many_arguments)]
-fn get_models<F, R>(
+// The maximum number of units to build a model.
+const MAX_UNITS: usize = 30;
+
+// The units to build the model on each position of each cluster, as the pairs of
+// the index of a read and the index of the unit in the read.
+// Reads in `sampled` are not used. Positions not in `use_position` have no units.
+type Units = Vec<Vec<Option<Vec<(usize, usize)>>>>;
+
+fn select_units<R: Rng>(
     data: &[Read],
     assignments: &[u8],
     sampled: &[bool],
     rng: &mut R,
-    param: (i32, i32, &F),
     use_position: &[bool],
     config: &ClusteringConfig,
-) -> Vec<Vec<POA>>
-where
-    R: Rng,
-    F: Fn(u8, u8) -> i32 + std::marker::Sync,
-{
+) -> Units {
     let mut chunks: Vec<_> = vec![vec![vec![]; config.chain_len]; config.cluster_num];
     let choises: Vec<u8> = (0..config.cluster_num).map(|e| e as u8).collect();
-    for ((read, &asn), &b) in data.iter().zip(assignments.iter()).zip(sampled) {
+    for (idx, ((read, &asn), &b)) in data.iter().zip(assignments.iter()).zip(sampled).enumerate() {
         if b {
             continue;
         }
         let chosen = *choises
             .choose_weighted(rng, |&k| if k == asn { 1. } else { 0. })
             .unwrap();
-        for &(pos, unit) in read.iter() {
+        for (k, &(pos, _)) in read.iter().enumerate() {
             if use_position[pos] {
-                chunks[chosen as usize][pos].push(unit);
+                chunks[chosen as usize][pos].push((idx, k));
             }
         }
     }
     chunks
         .iter_mut()
         .for_each(|cluster| cluster.iter_mut().for_each(|cs| cs.shuffle(rng)));
-    let ws = vec![1.; 30];
-    let cluster_to_poas = |cluster: Vec<Vec<&[u8]>>| {
+    chunks
+        .into_iter()
+        .map(|cluster| {
+            cluster
+                .into_iter()
+                .zip(use_position.iter())
+                .map(|(mut cs, &b)| {
+                    cs.truncate(MAX_UNITS);
+                    if b {
+                        Some(cs)
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn build_model<F>(data: &[Read], units: &Option<Vec<(usize, usize)>>, param: (i32, i32, &F)) -> POA
+where
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    match units {
+        Some(units) => {
+            let ws = vec![1.; MAX_UNITS];
+            let cs: Vec<_> = units.iter().map(|&(idx, k)| data[idx][k].1).collect();
+            POA::from_slice(&cs, &ws, param)
+        }
+        None => POA::default(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn get_models<F, R>(
+    data: &[Read],
+    assignments: &[u8],
+    sampled: &[bool],
+    rng: &mut R,
+    param: (i32, i32, &F),
+    use_position: &[bool],
+    config: &ClusteringConfig,
+) -> Vec<Vec<POA>>
+where
+    R: Rng,
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let units = select_units(data, assignments, sampled, rng, use_position, config);
+    let cluster_to_poas = |cluster: Vec<Option<Vec<(usize, usize)>>>| {
         cluster
             .iter()
-            .zip(use_position.iter())
-            .map(|(cs, &b)| {
-                let cs: Vec<_> = cs.iter().copied().take(30).collect();
-                match b {
-                    true => POA::from_slice(&cs, &ws, param),
-                    false => POA::default(),
-                }
-            })
+            .map(|units| build_model(data, units, param))
             .collect::<Vec<_>>()
     };
     if config.is_par {
-        chunks.into_par_iter().map(cluster_to_poas).collect()
+        units.into_par_iter().map(cluster_to_poas).collect()
     } else {
-        chunks.into_iter().map(cluster_to_poas).collect()
+        units.into_iter().map(cluster_to_poas).collect()
+    }
+}
+
+// The models and the log-likelihoods of the units on them, kept across the picks of
+// the sampling. A model is keyed by the units it is built from, in the order they are
+// sampled, and reused only if the same units are sampled again. Otherwise, it is rebuilt
+// and the likelihoods on it are discarded. The likelihoods are keyed by the index of
+// the read and the index of the unit in the read.
+struct ModelCache {
+    units: Units,
+    models: Vec<Vec<POA>>,
+    lks: Vec<Vec<HashMap<(usize, usize), f64>>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl ModelCache {
+    fn new(config: &ClusteringConfig) -> Self {
+        let (cl, len) = (config.cluster_num, config.chain_len);
+        Self {
+            units: vec![vec![None; len]; cl],
+            models: (0..cl)
+                .map(|_| (0..len).map(|_| POA::default()).collect())
+                .collect(),
+            lks: vec![vec![HashMap::new(); len]; cl],
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+    // Rebuild the models whose units differ from `units`.
+    fn update<F>(&mut self, units: Units, data: &[Read], param: (i32, i32, &F), is_par: bool)
+    where
+        F: Fn(u8, u8) -> i32 + std::marker::Sync,
+    {
+        let changed: Vec<(usize, usize)> = units
+            .iter()
+            .enumerate()
+            .flat_map(|(cl, us)| us.iter().enumerate().map(move |(pos, u)| (cl, pos, u)))
+            .filter(|&(cl, pos, u)| &self.units[cl][pos] != u)
+            .map(|(cl, pos, _)| (cl, pos))
+            .collect();
+        let build = |&(cl, pos): &(usize, usize)| build_model(data, &units[cl][pos], param);
+        let models: Vec<_> = if is_par {
+            changed.par_iter().map(build).collect()
+        } else {
+            changed.iter().map(build).collect()
+        };
+        for (&(cl, pos), model) in changed.iter().zip(models) {
+            self.models[cl][pos] = model;
+            self.lks[cl][pos].clear();
+        }
+        self.units = units;
+    }
+    // The log-likelihoods of the units of the reads in `targets` on each cluster.
+    fn likelihoods(
+        &mut self,
+        targets: &[usize],
+        data: &[Read],
+        config: &poa_hmm::Config,
+    ) -> Vec<Likelihoods> {
+        let likelihoods: Vec<Likelihoods> = {
+            let cache = &*self;
+            targets
+                .par_iter()
+                .map(|&idx| cache.read_likelihoods(idx, &data[idx], config))
+                .collect()
+        };
+        for (&idx, lks) in targets.iter().zip(likelihoods.iter()) {
+            for (k, (pos, lks)) in lks.iter().enumerate() {
+                for (cache, &lk) in self.lks.iter_mut().zip(lks.iter()) {
+                    cache[*pos].insert((idx, k), lk);
+                }
+            }
+        }
+        likelihoods
+    }
+    fn read_likelihoods(&self, idx: usize, read: &Read, config: &poa_hmm::Config) -> Likelihoods {
+        read.iter()
+            .enumerate()
+            .map(|(k, &(pos, u))| {
+                let lks = self
+                    .models
+                    .iter()
+                    .zip(self.lks.iter())
+                    .map(|(ms, lks)| match lks[pos].get(&(idx, k)) {
+                        Some(&lk) => {
+                            self.hits.fetch_add(1, Ordering::Relaxed);
+                            lk
+                        }
+                        None => {
+                            self.misses.fetch_add(1, Ordering::Relaxed);
+                            ms[pos].forward(u, config)
+                        }
+                    })
+                    .collect();
+                (pos, lks)
+            })
+            .collect()
+    }
+    fn report(&self, id: u64) {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        debug!("CACHE\t{}\t{}\t{}", id, hits, misses);
     }
 }
 
@@ -210,37 +368,49 @@ fn get_fraction_on_positions(
         .collect()
 }
 
-fn get_new_assignment(
+// The log prior of the `idx`-th read on each cluster from its contacts.
+fn get_link_prior(idx: usize, assignments: &[u8], config: &ClusteringConfig) -> Vec<f64> {
+    let mut prior = vec![0.; config.cluster_num];
+    if let Some(links) = config.links.get(idx) {
+        for &(j, w) in links.iter() {
+            prior[assignments[j] as usize] += w;
+        }
+    }
+    prior
+}
+
+// The position and the log-likelihoods on each cluster of each unit of a read.
+type Likelihoods = Vec<(usize, Vec<f64>)>;
+
+fn get_likelihoods(read: &Read, models: &[Vec<POA>], config: &poa_hmm::Config) -> Likelihoods {
+    read.iter()
+        .map(|&(pos, u)| {
+            let lks = models.iter().map(|ms| ms[pos].forward(u, config)).collect();
+            (pos, lks)
+        })
+        .collect()
+}
+
+// Return the (unnormalized) weights of the read on each cluster.
+fn get_weights(
     read: &Read,
+    likelihoods: &[(usize, Vec<f64>)],
     fractions: &[Vec<f64>],
-    f: &[u8],
-    models: &[Vec<POA>],
     betas: &[Vec<Vec<f64>>],
-    config: &poa_hmm::Config,
-    beta: f64,
-) -> u8 {
-    let likelihoods: Vec<(usize, Vec<_>)> = read
-        .iter()
-        .map(|&(pos, ref u)| {
-            let lks = models
-                .iter()
-                .map(|ms| ms[pos].forward(u, &config))
-                .collect();
-            (pos, lks)
-        })
-        .collect();
+    (beta, links): (f64, &[f64]),
+) -> Vec<f64> {
     let ws: Vec<_> = fractions
         .iter()
         .map(|ws| read.iter().map(|&(pos, _)| ws[pos]).sum::<f64>() / read.len() as f64)
         .collect();
     let cluster_num = fractions.len();
-    let weights: Vec<_> = (0..cluster_num)
+    (0..cluster_num)
         .map(|l| {
             (0..cluster_num)
                 .map(|k| {
                     if k != l {
                         let (i, j) = (l.max(k), l.min(k));
-                        let prior = beta * (ws[k].ln() - ws[l].ln());
+                        let prior = beta * (ws[k].ln() - ws[l].ln() + links[k] - links[l]);
                         prior
                             + likelihoods
                                 .iter()
@@ -254,7 +424,10 @@ fn get_new_assignment(
                 .sum::<f64>()
                 .recip()
         })
-        .collect();
+        .collect()
+}
+
+fn get_new_assignment(weights: &[f64], f: &[u8]) -> u8 {
     let (mut max, mut argmax) = (-0.1, 0);
     for (cl, &p) in weights.iter().enumerate() {
         if !f.contains(&(cl as u8)) && max < p {
@@ -265,12 +438,14 @@ fn get_new_assignment(
     argmax
 }
 
+// Update the assignments of the reads in `targets`, where `likelihoods` are the
+// likelihoods of each of them.
 #[allow(clippy::too_many_arguments)]
 fn update_assignments(
-    models: &[Vec<POA>],
+    targets: &[usize],
+    likelihoods: &[Likelihoods],
     assignments: &mut [u8],
     data: &[Read],
-    sampled: &[bool],
     betas: &[Vec<Vec<f64>>],
     forbidden: &[Vec<u8>],
     beta: f64,
@@ -279,11 +454,11 @@ fn update_assignments(
     let fractions: Vec<Vec<f64>> =
         get_fraction_on_positions(assignments, config.cluster_num, config.chain_len, data);
     let mut changed = vec![];
-    for (idx, _) in sampled.iter().enumerate().filter(|&(_, &b)| b) {
-        let f = &forbidden[idx];
-        let poa_config = &config.poa_config;
-        let new_asn =
-            get_new_assignment(&data[idx], &fractions, f, &models, &betas, poa_config, beta);
+    for (&idx, lks) in targets.iter().zip(likelihoods.iter()) {
+        let links = get_link_prior(idx, assignments, config);
+        let prior = (beta, links.as_slice());
+        let weights = get_weights(&data[idx], lks, &fractions, betas, prior);
+        let new_asn = get_new_assignment(&weights, &forbidden[idx]);
         if new_asn != assignments[idx] {
             assignments[idx] = new_asn;
             changed.push(idx);
@@ -336,6 +511,7 @@ pub fn gibbs_sampling<F>(
     f: &[Vec<u8>],
     aln: &AlnParam<F>,
     mut config: ClusteringConfig,
+    params: &DecomposeParams,
 ) -> Vec<u8>
 where
     F: Fn(u8, u8) -> i32 + std::marker::Sync,
@@ -345,7 +521,7 @@ where
     }
     assert_eq!(f.len(), data.len());
     let per_cluster_coverage = config.coverage / config.cluster_num;
-    config.seed = config.id;
+    config.seed = config.id ^ (config.seed << 32);
     config.pick_prob = if per_cluster_coverage < 40 {
         0.002
     } else if per_cluster_coverage < 100 {
@@ -353,7 +529,18 @@ where
     } else {
         0.05
     };
-    let res = gibbs_sampling_inner(data, labels, answer, f, aln, &config);
+    let mut iterations = vec![];
+    let res = gibbs_sampling_inner(
+        data,
+        labels,
+        answer,
+        f,
+        aln,
+        &config,
+        params,
+        &mut iterations,
+    );
+    record_run(&config, false, res.is_ok(), iterations);
     if let Ok(res) = res {
         debug!("{}\tClustered", config.id);
         res
@@ -361,7 +548,18 @@ where
         config.pick_prob = 2. * config.pick_prob;
         config.limit /= 2;
         config.seed *= 2;
-        let res = gibbs_sampling_inner(data, labels, answer, f, aln, &config);
+        let mut iterations = vec![];
+        let res = gibbs_sampling_inner(
+            data,
+            labels,
+            answer,
+            f,
+            aln,
+            &config,
+            params,
+            &mut iterations,
+        );
+        record_run(&config, true, res.is_ok(), iterations);
         debug!("{}\tClustered", config.id);
         match res {
             Ok(res) => res,
@@ -370,6 +568,248 @@ where
     }
 }
 
+fn record_run(
+    config: &ClusteringConfig,
+    retry: bool,
+    stabilised: bool,
+    iterations: Vec<gibbs_trace::Iteration>,
+) {
+    if let Some(recorder) = config.trace.as_ref() {
+        recorder.push(gibbs_trace::Run {
+            seed: config.seed,
+            cluster_num: config.cluster_num,
+            retry,
+            stabilised,
+            iterations,
+        });
+    }
+}
+
+/// Gibbs sampling, returning the posterior probabilities as well as the assignments.
+/// The i-th posterior is the probability that the i-th read belongs to each cluster.
+/// Labeled reads have one-hot posteriors.
+pub fn gibbs_sampling_with_posterior<F>(
+    data: &[Read],
+    labels: &[u8],
+    answer: Option<&[u8]>,
+    f: &[Vec<u8>],
+    aln: &AlnParam<F>,
+    config: ClusteringConfig,
+    params: &DecomposeParams,
+) -> (Vec<u8>, Vec<Vec<f64>>)
+where
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let cluster_num = config.cluster_num.max(1);
+    if config.cluster_num <= 1 || data.len() <= 2 {
+        let posteriors = (0..data.len()).map(|_| one_hot(0, cluster_num)).collect();
+        return (vec![0; data.len()], posteriors);
+    }
+    let seed = config.id;
+    let assignments = gibbs_sampling(data, labels, answer, f, aln, config.clone(), params);
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(seed);
+    let param = (aln.ins, aln.del, &aln.score);
+    let posteriors = get_posteriors(
+        data,
+        &assignments,
+        labels,
+        f,
+        &mut rng,
+        param,
+        &config,
+        params,
+    );
+    (assignments, posteriors)
+}
+
+/// Gibbs sampling with `params.consensus_seeds` seeds, where `first` is the result of the
+/// default run. Return the consensus assignments, the posteriors under them, and the consensus.
+pub fn gibbs_sampling_consensus<F>(
+    data: &[Read],
+    labels: &[u8],
+    f: &[Vec<u8>],
+    aln: &AlnParam<F>,
+    config: ClusteringConfig,
+    first: Vec<u8>,
+    params: &DecomposeParams,
+) -> (Vec<u8>, Vec<Vec<f64>>, consensus::Consensus)
+where
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let mut runs = vec![first];
+    for seed in 1..params.consensus_seeds as u64 {
+        let config = ClusteringConfig {
+            seed,
+            ..config.clone()
+        };
+        runs.push(gibbs_sampling(data, labels, None, f, aln, config, params));
+    }
+    let consensus = consensus::consensus(&runs, labels, f);
+    debug!(
+        "{}\tConsensus of {} seeds\tAgreement={:.3}",
+        config.id,
+        runs.len(),
+        consensus.agreement
+    );
+    let assignments = consensus.assignments.clone();
+    let cluster_num = assignments.iter().map(|&x| x as usize + 1).max();
+    let config = ClusteringConfig {
+        cluster_num: cluster_num.unwrap_or(1).max(config.cluster_num),
+        ..config
+    };
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(config.id);
+    let param = (aln.ins, aln.del, &aln.score);
+    let posteriors = get_posteriors(
+        data,
+        &assignments,
+        labels,
+        f,
+        &mut rng,
+        param,
+        &config,
+        params,
+    );
+    (assignments, posteriors, consensus)
+}
+
+/// Gibbs sampling with the number of clusters selected from `min_cluster..=max_cluster`
+/// by the criterion `params.model_selection`. The score of each number is logged.
+/// Return the assignments and the posteriors under the selected number of clusters.
+#[allow(clippy::too_many_arguments)]
+pub fn gibbs_sampling_select_k<F>(
+    data: &[Read],
+    labels: &[u8],
+    answer: Option<&[u8]>,
+    f: &[Vec<u8>],
+    aln: &AlnParam<F>,
+    config: ClusteringConfig,
+    (min_cluster, max_cluster): (usize, usize),
+    params: &DecomposeParams,
+) -> (Vec<u8>, Vec<Vec<f64>>)
+where
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let min_cluster = min_cluster.max(1);
+    let max_cluster = max_cluster.max(min_cluster);
+    let mut best: Option<(f64, usize, Vec<u8>, Vec<Vec<f64>>)> = None;
+    for cluster_num in min_cluster..=max_cluster {
+        let config = ClusteringConfig {
+            cluster_num,
+            ..config.clone()
+        };
+        let (asn, posteriors) =
+            gibbs_sampling_with_posterior(data, labels, answer, f, aln, config.clone(), params);
+        let lk = model_likelihood(data, &asn, aln, &config);
+        // Each cluster has a fraction and a model with one parameter per position.
+        let num_params = cluster_num - 1 + cluster_num * config.chain_len;
+        let score = -2. * lk + params.model_selection.penalty(num_params, data.len());
+        debug!(
+            "{}\tK={}\tLK={:.2}\t{:?}={:.2}",
+            config.id, cluster_num, lk, params.model_selection, score
+        );
+        if best.as_ref().map(|b| score < b.0).unwrap_or(true) {
+            best = Some((score, cluster_num, asn, posteriors));
+        }
+    }
+    let (_, selected, asn, posteriors) = best.unwrap();
+    debug!("{}\tSelected K={}", config.id, selected);
+    (asn, posteriors)
+}
+
+// The log likelihood of the data under the models constructed from all the reads.
+fn model_likelihood<F>(
+    data: &[Read],
+    asn: &[u8],
+    aln: &AlnParam<F>,
+    config: &ClusteringConfig,
+) -> f64
+where
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(config.id);
+    let param = (aln.ins, aln.del, &aln.score);
+    let sampled = vec![false; data.len()];
+    let use_position = vec![true; config.chain_len];
+    let models = get_models(data, asn, &sampled, &mut rng, param, &use_position, config);
+    let ws = get_cluster_fraction(asn, &sampled, config.cluster_num);
+    variant_calling::get_lk(&models, data, config, &ws)
+}
+
+fn one_hot(cluster: u8, cluster_num: usize) -> Vec<f64> {
+    let mut posterior = vec![0.; cluster_num];
+    posterior[cluster as usize] = 1.;
+    posterior
+}
+
+// The number of folds to compute posteriors. The posterior of a read is computed
+// by the models constructed without the fold containing the read.
+const POSTERIOR_FOLD: usize = 5;
+#[allow(clippy::too_many_arguments)]
+fn get_posteriors<F, R>(
+    data: &[Read],
+    asn: &[u8],
+    label: &[u8],
+    forbidden: &[Vec<u8>],
+    rng: &mut R,
+    param: (i32, i32, &F),
+    config: &ClusteringConfig,
+    params: &DecomposeParams,
+) -> Vec<Vec<f64>>
+where
+    R: Rng,
+    F: Fn(u8, u8) -> i32 + std::marker::Sync,
+{
+    let (variants, _) = get_variants(data, asn, rng, config, param);
+    let (variants, pos) = select_variants(variants, config.chain_len);
+    let betas = normalize_weights(&variants, 2.);
+    let fractions = get_fraction_on_positions(asn, config.cluster_num, config.chain_len, data);
+    let mut posteriors: Vec<_> = label
+        .iter()
+        .map(|&l| one_hot(l, config.cluster_num))
+        .collect();
+    posteriors.extend((label.len()..data.len()).map(|_| vec![]));
+    for fold in 0..POSTERIOR_FOLD {
+        let sampled: Vec<_> = (0..data.len())
+            .map(|idx| idx >= label.len() && idx % POSTERIOR_FOLD == fold)
+            .collect();
+        if sampled.iter().all(|&b| !b) {
+            continue;
+        }
+        let models = get_models(data, asn, &sampled, rng, param, &pos, config);
+        for (idx, _) in sampled.iter().enumerate().filter(|&(_, &b)| b) {
+            let links = get_link_prior(idx, asn, config);
+            let lks = get_likelihoods(&data[idx], &models, &config.poa_config);
+            let prior = (params.beta_max, links.as_slice());
+            let weights = get_weights(&data[idx], &lks, &fractions, &betas, prior);
+            posteriors[idx] = normalize_posterior(weights, &forbidden[idx]);
+        }
+    }
+    posteriors
+}
+
+// Normalize the weights into probabilities, where forbidden clusters have zero probability.
+// If no cluster has a valid weight, return the uniform distribution on the allowed clusters.
+fn normalize_posterior(mut weights: Vec<f64>, forbidden: &[u8]) -> Vec<f64> {
+    for (cl, w) in weights.iter_mut().enumerate() {
+        if forbidden.contains(&(cl as u8)) || !w.is_finite() {
+            *w = 0.;
+        }
+    }
+    let sum = weights.iter().sum::<f64>();
+    if sum > 0. {
+        weights.iter_mut().for_each(|w| *w /= sum);
+        return weights;
+    }
+    let allowed: Vec<_> = (0..weights.len())
+        .map(|cl| !forbidden.contains(&(cl as u8)))
+        .collect();
+    let num = allowed.iter().filter(|&&b| b).count().max(1) as f64;
+    allowed
+        .iter()
+        .map(|&b| if b { 1. / num } else { 0. })
+        .collect()
+}
+
 fn print_lk_gibbs<F>(
     asns: &[u8],
     data: &[Read],
@@ -435,6 +875,9 @@ fn gen_assignment<R: Rng>(not_allowed: &[u8], rng: &mut R, c: usize) -> u8 {
     }
 }
 
+// Return `Err` with the last assignments if the sampling did not get stable in `config.limit`
+// iterations. Each iteration is appended to `trace`.
+#[allow(clippy::too_many_arguments)]
 fn gibbs_sampling_inner<F>(
     data: &[Read],
     label: &[u8],
@@ -442,6 +885,8 @@ fn gibbs_sampling_inner<F>(
     forbidden: &[Vec<u8>],
     aln: &AlnParam<F>,
     config: &ClusteringConfig,
+    params: &DecomposeParams,
+    trace: &mut Vec<gibbs_trace::Iteration>,
 ) -> Result<Vec<u8>, Vec<u8>>
 where
     F: Fn(u8, u8) -> i32 + std::marker::Sync,
@@ -465,23 +910,25 @@ where
     let asn = &mut assignments;
     //let start = std::time::Instant::now();
     let mut lk = std::f64::NEG_INFINITY;
+    let mut cache = ModelCache::new(config);
     if log_enabled!(log::Level::Trace) {
         if let Some(answer) = answer {
             print_lk_gibbs(asn, &data, (label, answer), "B", param, config);
         }
     }
     let mut iter_num = 0;
-    while count < STABLE_LIMIT {
+    while count < params.stable_limit {
         iter_num += 1;
         let (variants, next_lk) = get_variants(&data, asn, rng, config, param);
         let (variants, pos) = select_variants(variants, config.chain_len);
         let betas = normalize_weights(&variants, 2.);
         coef *= match lk.partial_cmp(&next_lk) {
-            Some(std::cmp::Ordering::Less) => BETA_DECREASE,
-            Some(std::cmp::Ordering::Greater) => BETA_INCREASE,
+            Some(std::cmp::Ordering::Less) => params.beta_decrease,
+            Some(std::cmp::Ordering::Greater) => params.beta_increase,
             _ => 1.,
         };
         lk = next_lk;
+        let beta = (coef * beta).min(params.beta_max);
         let changed_num = (0..config.pick_prob.recip().ceil() as usize / 2)
             .map(|_| {
                 let s: Vec<_> = (0..data.len())
@@ -490,10 +937,23 @@ where
                         _ => rng.gen_bool(config.pick_prob),
                     })
                     .collect();
-                let ms = get_models(&data, asn, &s, rng, param, &pos, config);
+                let targets: Vec<usize> = (0..data.len()).filter(|&idx| s[idx]).collect();
+                // The likelihoods do not depend on the assignments. Thus, they can be computed
+                // in parallel.
+                let poa_config = &config.poa_config;
+                let lks: Vec<Likelihoods> = if config.reuse_models {
+                    let units = select_units(data, asn, &s, rng, &pos, config);
+                    cache.update(units, data, param, config.is_par);
+                    cache.likelihoods(&targets, data, poa_config)
+                } else {
+                    let ms = get_models(data, asn, &s, rng, param, &pos, config);
+                    targets
+                        .par_iter()
+                        .map(|&idx| get_likelihoods(&data[idx], &ms, poa_config))
+                        .collect()
+                };
                 let f = forbidden;
-                let beta = (coef * beta).min(BETA_MAX);
-                let up = update_assignments(&ms, asn, &data, &s, &betas, f, beta, config);
+                let up = update_assignments(&targets, &lks, asn, data, &betas, f, beta, config);
                 up.len() as u32
             })
             .sum::<u32>();
@@ -502,13 +962,24 @@ where
         count += has_changed as u32;
         count *= has_changed as u32;
         predictions.push_back(asn.clone());
-        if predictions.len() as u32 > STABLE_LIMIT {
+        if predictions.len() as u32 > params.stable_limit {
             predictions.pop_front();
         }
         report_gibbs(asn, changed_num, count, config);
+        trace.push(gibbs_trace::Iteration {
+            iteration: iter_num,
+            lk,
+            beta,
+            changed: changed_num,
+            stable_count: count,
+            sizes: cluster_sizes(asn, config.cluster_num),
+        });
         // let elapsed = (std::time::Instant::now() - start).as_secs();
         // if elapsed > config.limit && count < STABLE_LIMIT / 2 {
         if iter_num > config.limit {
+            if config.reuse_models {
+                cache.report(config.id);
+            }
             debug!("BREAK\t{}\t{}\tLIMIT", config.id, iter_num);
             return Err(predictions.pop_back().unwrap());
         }
@@ -518,13 +989,22 @@ where
             print_lk_gibbs(asn, &data, (label, answer), "A", param, config);
         }
     }
+    if config.reuse_models {
+        cache.report(config.id);
+    }
     debug!("BREAK\t{}\t{}\tSUCCESS", config.id, iter_num);
     Ok(predictions.pop_back().unwrap())
 }
 
+fn cluster_sizes(asn: &[u8], cluster_num: usize) -> Vec<usize> {
+    (0..cluster_num)
+        .map(|c| bytecount::count(asn, c as u8))
+        .collect()
+}
+
 fn report_gibbs(asn: &[u8], change_num: u32, count: u32, c: &ClusteringConfig) {
-    let line = (0..c.cluster_num)
-        .map(|c| bytecount::count(&asn, c as u8))
+    let line = cluster_sizes(asn, c.cluster_num)
+        .iter()
         .map(|e| format!("{}", e))
         .collect::<Vec<_>>()
         .join("\t");
@@ -542,13 +1022,7 @@ fn calc_probs(
     bs: &[Vec<Vec<f64>>],
     fractions: &[Vec<f64>],
 ) -> Vec<String> {
-    let likelihoods: Vec<(usize, Vec<_>)> = read
-        .iter()
-        .map(|&(pos, ref u)| {
-            let lks = models.iter().map(|ms| ms[pos].forward(u, c)).collect();
-            (pos, lks)
-        })
-        .collect();
+    let likelihoods = get_likelihoods(read, models, c);
     let ws: Vec<_> = fractions
         .iter()
         .map(|ws| read.iter().map(|&(pos, _)| ws[pos]).sum::<f64>())
@@ -624,3 +1098,116 @@ fn normalize_weights(variants: &[Vec<Vec<f64>>], beta: f64) -> Vec<Vec<Vec<f64>>
     });
     betas
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Reads on `chain_len` positions, each spanning some positions. The i-th read is
+    // from the (i % 2)-th template.
+    fn reads(seed: u64, num: usize, chain_len: usize) -> Vec<Vec<(usize, Vec<u8>)>> {
+        let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(seed);
+        let mut random =
+            |len: usize| -> Vec<u8> { (0..len).map(|_| b"ACGT"[rng.gen_range(0, 4)]).collect() };
+        let template: Vec<_> = (0..chain_len).map(|_| random(50)).collect();
+        let variant: Vec<_> = template
+            .iter()
+            .map(|u| {
+                let mut u = u.clone();
+                for &i in &[12, 37] {
+                    u[i] = if u[i] == b'A' { b'C' } else { b'A' };
+                }
+                u
+            })
+            .collect();
+        let templates = [template, variant];
+        let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(seed + 1);
+        (0..num)
+            .map(|i| {
+                let start = rng.gen_range(0, chain_len / 2);
+                (start..chain_len)
+                    .map(|pos| {
+                        let unit = templates[i % 2][pos]
+                            .iter()
+                            .map(|&b| match rng.gen_bool(0.15) {
+                                true => b"ACGT"[rng.gen_range(0, 4)],
+                                false => b,
+                            })
+                            .collect();
+                        (pos, unit)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+    fn as_reads(seqs: &[Vec<(usize, Vec<u8>)>]) -> Vec<Read> {
+        seqs.iter()
+            .map(|r| r.iter().map(|(p, u)| (*p, u.as_slice())).collect())
+            .collect()
+    }
+    #[test]
+    fn model_cache_test() {
+        let seqs = reads(3, 10, 4);
+        let data = as_reads(&seqs);
+        let config = ClusteringConfig::new(4, 2, 10, 5, 0, false, &poa_hmm::DEFAULT_CONFIG);
+        let param = (DEFAULT_ALN.ins, DEFAULT_ALN.del, &DEFAULT_ALN.score);
+        let poa_config = &config.poa_config;
+        let units = |xs: &[usize]| Some(xs.iter().map(|&idx| (idx, 4 - seqs[idx].len())).collect());
+        let mut cache = ModelCache::new(&config);
+        let mut sample = vec![vec![None; 4]; 2];
+        sample[0][0] = units(&[0, 2]);
+        sample[1][0] = units(&[1, 3]);
+        cache.update(sample.clone(), &data, param, false);
+        let first = cache.likelihoods(&[4, 5], &data, poa_config);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 0);
+        // The same units in the same order: the likelihoods are looked up.
+        cache.update(sample.clone(), &data, param, false);
+        assert_eq!(cache.likelihoods(&[4, 5], &data, poa_config), first);
+        let misses = cache.misses.load(Ordering::Relaxed);
+        assert_eq!(cache.hits.load(Ordering::Relaxed), misses);
+        // In another order, the model of the cluster is built again.
+        sample[0][0] = units(&[2, 0]);
+        cache.update(sample, &data, param, false);
+        cache.likelihoods(&[4, 5], &data, poa_config);
+        let rebuilt = data[4..6].iter().flatten().filter(|&&(pos, _)| pos == 0);
+        assert_eq!(
+            cache.misses.load(Ordering::Relaxed),
+            misses + rebuilt.count()
+        );
+    }
+    #[test]
+    fn reuse_models_test() {
+        let (num, chain_len) = (60, 8);
+        let seqs = reads(12, num, chain_len);
+        let data = as_reads(&seqs);
+        let label = &[0, 1, 0, 1, 0, 1];
+        let forbidden = vec![vec![]; num];
+        let params = DecomposeParams::default();
+        let poa_config = &poa_hmm::DEFAULT_CONFIG;
+        let aln = &DEFAULT_ALN;
+        // The assignments and the likelihood, the changes, and the sizes in each iteration.
+        let run = |config: ClusteringConfig| {
+            let recorder = gibbs_trace::Recorder::default();
+            let config = ClusteringConfig {
+                trace: Some(recorder.clone()),
+                ..config
+            };
+            let asn = gibbs_sampling(&data, label, None, &forbidden, aln, config, &params);
+            let trace: Vec<_> = recorder
+                .take()
+                .into_iter()
+                .flat_map(|run| run.iterations)
+                .map(|it| (it.lk.to_bits(), it.changed, it.sizes))
+                .collect();
+            (asn, trace)
+        };
+        for id in 0..3 {
+            let config = ClusteringConfig::new(chain_len, 2, 20, num, id, false, poa_config);
+            // The models are built on each pick by `get_models`, as without the cache.
+            let baseline = ClusteringConfig {
+                reuse_models: false,
+                ..config.clone()
+            };
+            assert_eq!(run(config), run(baseline));
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! Recombination frequency of each repeat pair.
+//! Let the two copies of a repeat be A and B, with the left/right flanking regions
+//! A_L, A_R, B_L, and B_R on the reference. A read traversing a copy connects two flanks.
+//! The parental configurations are A_L-A_R and B_L-B_R. The recombinant configurations are
+//! A_L-B_R and B_L-A_R if the copies are in the same direction, and A_L-B_L and A_R-B_R
+//! if they are inverted.
+use super::DecomposeParams;
+use last_tiling::repeat::RepeatPairs;
+use last_tiling::unit::ChunkedUnit;
+use last_tiling::EncodedRead;
+use std::collections::HashSet;
+
+/// The z-score of the two-sided 95% confidence interval.
+const Z_95: f64 = 1.96;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepeatRecombination {
+    /// The index of the repeat pair in `repeats.json`.
+    pub repeat: usize,
+    pub contig_a: String,
+    pub start_a: usize,
+    pub end_a: usize,
+    pub contig_b: String,
+    pub start_b: usize,
+    pub end_b: usize,
+    pub is_inverted: bool,
+    /// The mean length of the two copies.
+    pub length: usize,
+    /// The number of reads in each configuration.
+    pub parental_a: usize,
+    pub parental_b: usize,
+    pub recombinant_a: usize,
+    pub recombinant_b: usize,
+    /// The fraction of recombinant reads and its Wilson score interval.
+    pub fraction: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl RepeatRecombination {
+    pub const HEADER: &'static str = "repeat\tcontig_a\tstart_a\tend_a\tcontig_b\tstart_b\tend_b\tis_inverted\tlength\tparental_a\tparental_b\trecombinant_a\trecombinant_b\tfraction\tlower\tupper";
+}
+
+impl std::fmt::Display for RepeatRecombination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+            self.repeat,
+            self.contig_a,
+            self.start_a,
+            self.end_a,
+            self.contig_b,
+            self.start_b,
+            self.end_b,
+            self.is_inverted,
+            self.length
+        )?;
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+            self.parental_a,
+            self.parental_b,
+            self.recombinant_a,
+            self.recombinant_b,
+            self.fraction,
+            self.lower,
+            self.upper
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Flank {
+    LeftA,
+    RightA,
+    LeftB,
+    RightB,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Configuration {
+    ParentalA,
+    ParentalB,
+    RecombinantA,
+    RecombinantB,
+}
+
+// The units of a repeat copy and its flanks. Flanks are `width` units long.
+struct RepeatCopy {
+    contig: u16,
+    start: u16,
+    end: u16,
+    width: u16,
+}
+
+impl RepeatCopy {
+    // Return Some(Some(flank)) if the unit is in a flank, Some(None) if in the copy itself.
+    fn classify(&self, contig: u16, unit: u16, left: Flank, right: Flank) -> Option<Option<Flank>> {
+        if contig != self.contig {
+            None
+        } else if self.start <= unit && unit <= self.end {
+            Some(None)
+        } else if self.start.max(self.width) - self.width <= unit && unit < self.start {
+            Some(Some(left))
+        } else if self.end < unit && unit <= self.end + self.width {
+            Some(Some(right))
+        } else {
+            None
+        }
+    }
+}
+
+fn configuration(x: Flank, y: Flank, is_inverted: bool) -> Option<Configuration> {
+    use Flank::*;
+    let (x, y) = if (x as u8) < (y as u8) {
+        (x, y)
+    } else {
+        (y, x)
+    };
+    match (x, y, is_inverted) {
+        (LeftA, RightA, _) => Some(Configuration::ParentalA),
+        (LeftB, RightB, _) => Some(Configuration::ParentalB),
+        (LeftA, RightB, false) | (LeftA, LeftB, true) => Some(Configuration::RecombinantA),
+        (RightA, LeftB, false) | (RightA, RightB, true) => Some(Configuration::RecombinantB),
+        _ => None,
+    }
+}
+
+// The configurations of a read around the repeat. Flanks are connected if there is no
+// unit outside of the repeat copies and the flanks between them.
+fn configurations_of(
+    read: &EncodedRead,
+    (a, b): (&RepeatCopy, &RepeatCopy),
+    is_inverted: bool,
+) -> HashSet<Configuration> {
+    let mut configurations = HashSet::new();
+    let mut last = None;
+    for unit in read.seq() {
+        let encode = match unit {
+            ChunkedUnit::En(e) => e,
+            ChunkedUnit::Gap(_) => continue,
+        };
+        let class = a
+            .classify(encode.contig, encode.unit, Flank::LeftA, Flank::RightA)
+            .or_else(|| b.classify(encode.contig, encode.unit, Flank::LeftB, Flank::RightB));
+        match class {
+            Some(Some(flank)) => {
+                if let Some(prev) = last {
+                    if prev != flank {
+                        configurations.extend(configuration(prev, flank, is_inverted));
+                    }
+                }
+                last = Some(flank);
+            }
+            Some(None) => {}
+            None => last = None,
+        }
+    }
+    configurations
+}
+
+/// Count the reads in each configuration of each repeat pair. Flanking regions are
+/// `params.check_thr` units long.
+pub fn recombination_frequencies(
+    reads: &[EncodedRead],
+    repeats: &[RepeatPairs],
+    params: &DecomposeParams,
+) -> Vec<RepeatRecombination> {
+    let width = params.check_thr.max(1);
+    repeats
+        .iter()
+        .enumerate()
+        .filter(|(_, pair)| pair.len() == 2)
+        .map(|(idx, pair)| {
+            let (rep_a, rep_b) = (&pair[0], &pair[1]);
+            let to_copy = |rep: &last_tiling::repeat::Repeat| RepeatCopy {
+                contig: rep.id(),
+                start: rep.start_in_unit(),
+                end: rep.end_in_unit(),
+                width,
+            };
+            let (a, b) = (to_copy(rep_a), to_copy(rep_b));
+            let is_inverted = rep_a.is_forward() != rep_b.is_forward();
+            let mut counts = [0; 4];
+            for read in reads {
+                for c in configurations_of(read, (&a, &b), is_inverted) {
+                    counts[c as usize] += 1;
+                }
+            }
+            let recombinant = counts[2] + counts[3];
+            let total = counts.iter().sum::<usize>();
+            let fraction = recombinant as f64 / total.max(1) as f64;
+            let (lower, upper) = wilson_interval(recombinant, total);
+            let length = (rep_a.end() - rep_a.start() + rep_b.end() - rep_b.start()) / 2;
+            RepeatRecombination {
+                repeat: idx,
+                contig_a: rep_a.name().to_string(),
+                start_a: rep_a.start(),
+                end_a: rep_a.end(),
+                contig_b: rep_b.name().to_string(),
+                start_b: rep_b.start(),
+                end_b: rep_b.end(),
+                is_inverted,
+                length,
+                parental_a: counts[0],
+                parental_b: counts[1],
+                recombinant_a: counts[2],
+                recombinant_b: counts[3],
+                fraction,
+                lower,
+                upper,
+            }
+        })
+        .collect()
+}
+
+// Wilson score interval of a binomial proportion.
+fn wilson_interval(success: usize, total: usize) -> (f64, f64) {
+    if total == 0 {
+        return (0., 1.);
+    }
+    let n = total as f64;
+    let p = success as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let center = (p + z2 / (2. * n)) / (1. + z2 / n);
+    let margin = Z_95 / (1. + z2 / n) * (p * (1. - p) / n + z2 / (4. * n * n)).sqrt();
+    ((center - margin).max(0.), (center + margin).min(1.))
+}
@@ -0,0 +1,504 @@
+//! Recombination graph of the reference and the circular molecules it implies.
+//! Each contig of the reference is regarded as a circle(the master circle), and cut at
+//! the positions of the `ContigPair`s in the initial clusters. The nodes of the graph are
+//! the segments between the cuts, and the edges are the adjacencies of the segments on
+//! the reference and the recombinant adjacencies observed as `ContigPair`s.
+//! A repeat-mediated junction is moved to the start of the repeat copy it lies in, and to
+//! the homologous position in the other copy, and the copies are cut out as segments.
+//! Other junctions closer than `UNIT_SIZE` to an existing cut are put at that cut.
+//! Each simple cycle of the graph is a candidate circular molecule. It is the master circle
+//! if it consists of the reference adjacencies only, and a subgenomic circle otherwise.
+use super::find_breakpoint::{Cluster, Direction, Mechanism, Position};
+use bio_utils::fasta;
+use last_tiling::repeat::RepeatPairs;
+use last_tiling::unit::ChunkedUnit;
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// The maximum number of circles to be enumerated.
+pub const MAX_CIRCLES: usize = 1_000;
+
+/// The maximum number of paths to be extended in the enumeration of circles.
+/// A dense graph has exponentially many paths, even if only a few of them close.
+pub const MAX_EXPANSIONS: usize = 1_000_000;
+
+/// A segment of a contig.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub contig: u16,
+    /// 0-based, half-open range on the contig.
+    pub start: usize,
+    pub end: usize,
+    /// The index of the repeat pair if the segment is a copy of it.
+    pub repeat: Option<usize>,
+}
+
+impl Segment {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+    /// The name of the segment, `{contig}_{start}_{end}`.
+    pub fn name(&self, contigs: &Contigs) -> String {
+        let contig = contigs.get_name_by_id(self.contig);
+        format!("{}_{}_{}", contig, self.start, self.end)
+    }
+}
+
+/// An end of a segment, the index of the segment and whether it is the end at `Segment::end`.
+pub type End = (usize, bool);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdjacencyKind {
+    /// Adjacent on the reference.
+    Reference,
+    /// Joined by a `ContigPair`.
+    Recombinant,
+}
+
+impl std::fmt::Display for AdjacencyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AdjacencyKind::Reference => write!(f, "reference"),
+            AdjacencyKind::Recombinant => write!(f, "recombinant"),
+        }
+    }
+}
+
+/// An edge joining two ends of segments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Adjacency {
+    pub ends: (End, End),
+    pub kind: AdjacencyKind,
+    /// The number of reads supporting the adjacency. For a reference adjacency, it is
+    /// the number of reads passing through the unit containing the adjacency.
+    pub support: usize,
+}
+
+/// A circular molecule, as a cycle of the recombination graph.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Circle {
+    pub id: usize,
+    /// The segments and whether each of them is traversed forward.
+    pub path: Vec<(usize, bool)>,
+    /// The adjacency from each segment to the next one. The last one closes the circle.
+    pub adjacencies: Vec<usize>,
+    pub length: usize,
+    pub is_master: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecombinationGraph {
+    pub segments: Vec<Segment>,
+    pub adjacencies: Vec<Adjacency>,
+}
+
+// The position of the cut for a position of a `ContigPair`. A refined position is
+// the last retained base if the upstream is retained, and the first one otherwise.
+fn cut_of(pos: &Position) -> usize {
+    match (pos.refined(), pos.direction()) {
+        (Some(x), Direction::UpStream) => x + 1,
+        _ => pos.coordinate(),
+    }
+}
+
+// The cuts of a repeat-mediated junction. The position in one copy is moved to its start,
+// and the position in the other copy to the homologous position.
+fn snap(p1: &Position, p2: &Position, pair: &RepeatPairs) -> Option<(usize, usize)> {
+    if pair.len() != 2 {
+        return None;
+    }
+    let (a, b) = if p1.is_in(&pair[0]) && p2.is_in(&pair[1]) {
+        (&pair[0], &pair[1])
+    } else if p1.is_in(&pair[1]) && p2.is_in(&pair[0]) {
+        (&pair[1], &pair[0])
+    } else {
+        return None;
+    };
+    let homologous = if a.is_forward() == b.is_forward() {
+        b.start()
+    } else {
+        b.end()
+    };
+    Some((a.start(), homologous))
+}
+
+impl RecombinationGraph {
+    pub fn new(
+        clusters: &[Cluster],
+        reads: &[EncodedRead],
+        contigs: &Contigs,
+        repeats: &[RepeatPairs],
+    ) -> Self {
+        let lengths: Vec<usize> = (0..contigs.get_num_of_contigs())
+            .map(|c| contigs.get_by_id(c as u16).map(|s| s.len()).unwrap_or(0))
+            .collect();
+        let mut cuts: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); lengths.len()];
+        let mut copies: HashMap<(u16, usize, usize), usize> = HashMap::new();
+        // The junctions, ((contig, cut, direction) of each side, support, whether fixed).
+        let mut junctions = vec![];
+        let contig_pairs = clusters
+            .iter()
+            .flat_map(|cl| cl.members.iter())
+            .filter_map(|m| m.cr.contig_pair().map(|cp| (m, cp)));
+        for (member, cp) in contig_pairs {
+            let (p1, p2) = (cp.contig1(), cp.contig2());
+            let snapped = match (member.mechanism, member.repeat.as_ref()) {
+                (Mechanism::RepeatMediated, Some(ann)) => repeats
+                    .get(ann.repeat)
+                    .and_then(|pair| snap(p1, p2, pair).map(|x| (ann.repeat, pair, x))),
+                _ => None,
+            };
+            let (x1, x2, is_fixed) = match snapped {
+                Some((idx, pair, (x1, x2))) => {
+                    for copy in pair.inner() {
+                        let contig = copy.id();
+                        cuts[contig as usize].insert(copy.start());
+                        cuts[contig as usize].insert(copy.end());
+                        copies.insert((contig, copy.start(), copy.end()), idx);
+                    }
+                    (x1, x2, true)
+                }
+                None => (cut_of(p1), cut_of(p2), false),
+            };
+            let side1 = (p1.contig(), x1, p1.direction());
+            let side2 = (p2.contig(), x2, p2.direction());
+            junctions.push((side1, side2, cp.reads().len(), is_fixed));
+        }
+        // Put the other junctions at the cuts nearby.
+        let mut floating: Vec<_> = junctions
+            .iter()
+            .filter(|j| !j.3)
+            .flat_map(|&(s1, s2, _, _)| vec![(s1.0, s1.1), (s2.0, s2.1)])
+            .collect();
+        floating.sort();
+        let mut moved: HashMap<(u16, usize), usize> = HashMap::new();
+        for (contig, x) in floating {
+            let cuts = &mut cuts[contig as usize];
+            let lower = cuts.range(..=x).next_back().filter(|&&c| x - c < UNIT_SIZE);
+            let upper = cuts.range(x..).next().filter(|&&c| c - x < UNIT_SIZE);
+            let cut = match (lower, upper) {
+                (Some(&l), Some(&u)) if u - x < x - l => u,
+                (Some(&l), _) => l,
+                (None, Some(&u)) => u,
+                (None, None) => x,
+            };
+            cuts.insert(cut);
+            moved.insert((contig, x), cut);
+        }
+        let mut segments = vec![];
+        // (contig, start) -> segment, and (contig, end) -> segment.
+        let mut starting: HashMap<(u16, usize), usize> = HashMap::new();
+        let mut ending: HashMap<(u16, usize), usize> = HashMap::new();
+        let mut adjacencies = vec![];
+        for (contig, (cuts, &len)) in cuts.iter().zip(lengths.iter()).enumerate() {
+            let contig = contig as u16;
+            let mut boundaries = vec![0];
+            boundaries.extend(cuts.iter().copied().filter(|&c| 0 < c && c < len));
+            boundaries.push(len);
+            let first = segments.len();
+            for w in boundaries.windows(2).filter(|w| w[0] < w[1]) {
+                let (start, end) = (w[0], w[1]);
+                starting.insert((contig, start), segments.len());
+                ending.insert((contig, end), segments.len());
+                let repeat = copies.get(&(contig, start, end)).copied();
+                segments.push(Segment {
+                    contig,
+                    start,
+                    end,
+                    repeat,
+                });
+            }
+            // The adjacencies on the reference, including the one at the origin.
+            let last = segments.len();
+            for i in first..last {
+                let next = if i + 1 == last { first } else { i + 1 };
+                adjacencies.push(Adjacency {
+                    ends: ((i, true), (next, false)),
+                    kind: AdjacencyKind::Reference,
+                    support: 0,
+                });
+            }
+        }
+        let end_of = |(contig, x, direction): (u16, usize, Direction), is_fixed: bool| {
+            let x = if is_fixed { x } else { moved[&(contig, x)] };
+            let len = lengths[contig as usize];
+            match direction {
+                Direction::UpStream => {
+                    let x = if x == 0 { len } else { x };
+                    ending.get(&(contig, x)).map(|&s| (s, true))
+                }
+                Direction::DownStream => {
+                    let x = if x >= len { 0 } else { x };
+                    starting.get(&(contig, x)).map(|&s| (s, false))
+                }
+            }
+        };
+        let mut recombinant: HashMap<(End, End), usize> = HashMap::new();
+        for &(side1, side2, support, is_fixed) in junctions.iter() {
+            if let (Some(x), Some(y)) = (end_of(side1, is_fixed), end_of(side2, is_fixed)) {
+                *recombinant.entry((x.min(y), x.max(y))).or_default() += support;
+            }
+        }
+        let mut recombinant: Vec<_> = recombinant.into_iter().collect();
+        recombinant.sort();
+        adjacencies.extend(recombinant.into_iter().map(|(ends, support)| Adjacency {
+            ends,
+            kind: AdjacencyKind::Recombinant,
+            support,
+        }));
+        let mut graph = Self {
+            segments,
+            adjacencies,
+        };
+        graph.count_reference_supports(reads, contigs);
+        graph
+    }
+    // Count the reads passing through each reference adjacency.
+    fn count_reference_supports(&mut self, reads: &[EncodedRead], contigs: &Contigs) {
+        let segments = &self.segments;
+        let adjacencies: Vec<_> = self
+            .adjacencies
+            .iter()
+            .enumerate()
+            .filter(|(_, adj)| adj.kind == AdjacencyKind::Reference)
+            .map(|(idx, adj)| (idx, &segments[(adj.ends.0).0]))
+            .collect();
+        let mut supports = vec![0; self.adjacencies.len()];
+        for read in reads {
+            let mut passed = HashSet::new();
+            let encodes: Vec<_> = read
+                .seq()
+                .iter()
+                .filter_map(|u| match u {
+                    ChunkedUnit::En(e) => Some(e),
+                    ChunkedUnit::Gap(_) => None,
+                })
+                .collect();
+            for w in encodes.windows(2) {
+                if w[0].contig != w[1].contig || w[0].is_forward != w[1].is_forward {
+                    continue;
+                }
+                let contig = w[0].contig;
+                let (lo, hi) = (w[0].unit.min(w[1].unit), w[0].unit.max(w[1].unit));
+                let last = contigs.get_last_unit(contig).unwrap_or(0);
+                let is_origin = lo == 0 && hi == last && last > 2;
+                if !is_origin && !(1..=2).contains(&(hi - lo)) {
+                    continue;
+                }
+                let (lo, hi) = (lo as usize * UNIT_SIZE, (hi as usize + 1) * UNIT_SIZE);
+                for &(idx, seg) in adjacencies.iter().filter(|(_, seg)| seg.contig == contig) {
+                    let is_passed = if is_origin {
+                        seg.end >= last as usize * UNIT_SIZE
+                    } else {
+                        lo < seg.end && seg.end < hi
+                    };
+                    if is_passed {
+                        passed.insert(idx);
+                    }
+                }
+            }
+            for idx in passed {
+                supports[idx] += 1;
+            }
+        }
+        for (adj, support) in self.adjacencies.iter_mut().zip(supports) {
+            if adj.kind == AdjacencyKind::Reference {
+                adj.support = support;
+            }
+        }
+    }
+    /// Enumerate the simple cycles of the graph, up to `MAX_CIRCLES` circles and
+    /// `MAX_EXPANSIONS` extensions of paths. The master circles
+    /// come first, and the others are sorted by the minimum support of their adjacencies.
+    pub fn circles(&self) -> Vec<Circle> {
+        let mut edges: Vec<Vec<(usize, End)>> = vec![vec![]; 2 * self.segments.len()];
+        let index = |(seg, is_tail): End| 2 * seg + is_tail as usize;
+        for (idx, adj) in self.adjacencies.iter().enumerate() {
+            let (x, y) = adj.ends;
+            edges[index(x)].push((idx, y));
+            if x != y {
+                edges[index(y)].push((idx, x));
+            }
+        }
+        let mut circles = vec![];
+        let mut budget = MAX_EXPANSIONS;
+        for start in 0..self.segments.len() {
+            let mut visited = vec![false; self.segments.len()];
+            visited[start] = true;
+            let mut path = vec![(start, true)];
+            let mut used = vec![];
+            let (circles, budget) = (&mut circles, &mut budget);
+            self.search(&edges, &mut path, &mut used, &mut visited, circles, budget);
+        }
+        if budget == 0 {
+            warn!(
+                "The search of circles stopped after {} extensions. {} circles found so far.",
+                MAX_EXPANSIONS,
+                circles.len()
+            );
+        }
+        let min_support = |c: &Circle| {
+            c.adjacencies
+                .iter()
+                .map(|&a| self.adjacencies[a].support)
+                .min()
+                .unwrap_or(0)
+        };
+        circles.sort_by_key(|c| (!c.is_master, std::cmp::Reverse(min_support(c))));
+        for (id, circle) in circles.iter_mut().enumerate() {
+            circle.id = id;
+        }
+        circles
+    }
+    // Extend the path by depth-first search. Only the segments after the first one
+    // are used, so that each cycle is found once, from its first segment traversed forward.
+    // Each extension of the path consumes `budget`, and the search stops when it runs out.
+    fn search(
+        &self,
+        edges: &[Vec<(usize, End)>],
+        path: &mut Vec<(usize, bool)>,
+        used: &mut Vec<usize>,
+        visited: &mut [bool],
+        circles: &mut Vec<Circle>,
+        budget: &mut usize,
+    ) {
+        let start = path[0].0;
+        let &(seg, forward) = path.last().unwrap();
+        for &(idx, (next, is_tail)) in edges[2 * seg + forward as usize].iter() {
+            if circles.len() >= MAX_CIRCLES || *budget == 0 {
+                return;
+            }
+            if next == start && !is_tail {
+                let mut adjacencies = used.clone();
+                adjacencies.push(idx);
+                let is_master = adjacencies
+                    .iter()
+                    .all(|&a| self.adjacencies[a].kind == AdjacencyKind::Reference);
+                let length = path.iter().map(|&(s, _)| self.segments[s].len()).sum();
+                circles.push(Circle {
+                    id: 0,
+                    path: path.clone(),
+                    adjacencies,
+                    length,
+                    is_master,
+                });
+            } else if next > start && !visited[next] {
+                *budget -= 1;
+                visited[next] = true;
+                path.push((next, !is_tail));
+                used.push(idx);
+                self.search(edges, path, used, visited, circles, budget);
+                used.pop();
+                path.pop();
+                visited[next] = false;
+            }
+        }
+    }
+    fn sequence(&self, circle: &Circle, contigs: &Contigs) -> Vec<u8> {
+        let mut seq = vec![];
+        for &(s, forward) in circle.path.iter() {
+            let seg = &self.segments[s];
+            let bases = contigs
+                .get_by_id(seg.contig)
+                .map(|x| &x[seg.start..seg.end])
+                .unwrap_or(&[]);
+            if forward {
+                seq.extend_from_slice(bases);
+            } else {
+                seq.extend(last_tiling::revcmp(bases));
+            }
+        }
+        seq
+    }
+    fn path_of(&self, circle: &Circle, contigs: &Contigs) -> String {
+        let path: Vec<_> = circle
+            .path
+            .iter()
+            .map(|&(s, forward)| {
+                let orientation = if forward { '+' } else { '-' };
+                format!("{}{}", self.segments[s].name(contigs), orientation)
+            })
+            .collect();
+        path.join(",")
+    }
+    /// The sequences of the circles, starting from the first segment of each path.
+    pub fn to_fasta(&self, circles: &[Circle], contigs: &Contigs) -> Vec<fasta::Record> {
+        circles
+            .iter()
+            .map(|c| {
+                let id = format!("circle_{}", c.id);
+                let kind = if c.is_master { "master" } else { "subgenomic" };
+                let desc = format!(
+                    "{} length={} path={}",
+                    kind,
+                    c.length,
+                    self.path_of(c, contigs)
+                );
+                fasta::Record::with_data(&id, &Some(desc), &self.sequence(c, contigs))
+            })
+            .collect()
+    }
+    /// GFA 1.0 of the graph. The circles are written as paths. Each link has
+    /// the number of supporting reads(`RC`) and the kind of the adjacency(`kd`).
+    pub fn to_gfa(&self, circles: &[Circle], contigs: &Contigs) -> String {
+        let mut lines = vec!["H\tVN:Z:1.0".to_string()];
+        for seg in self.segments.iter() {
+            let seq = contigs
+                .get_by_id(seg.contig)
+                .map(|x| String::from_utf8_lossy(&x[seg.start..seg.end]).to_string())
+                .unwrap_or_else(|| "*".to_string());
+            let mut line = format!("S\t{}\t{}\tLN:i:{}", seg.name(contigs), seq, seg.len());
+            if let Some(repeat) = seg.repeat {
+                line += &format!("\trp:i:{}", repeat);
+            }
+            lines.push(line);
+        }
+        for adj in self.adjacencies.iter() {
+            // Leaving a segment from its end traverses it forward.
+            let ((s, s_tail), (t, t_tail)) = adj.ends;
+            let from = (
+                self.segments[s].name(contigs),
+                if s_tail { '+' } else { '-' },
+            );
+            let to = (
+                self.segments[t].name(contigs),
+                if t_tail { '-' } else { '+' },
+            );
+            lines.push(format!(
+                "L\t{}\t{}\t{}\t{}\t0M\tRC:i:{}\tkd:Z:{}",
+                from.0, from.1, to.0, to.1, adj.support, adj.kind
+            ));
+        }
+        for circle in circles {
+            let path = self.path_of(circle, contigs);
+            lines.push(format!("P\tcircle_{}\t{}\t*", circle.id, path));
+        }
+        lines.join("\n")
+    }
+    /// A summary of the circles. The supports are of the adjacencies along the path.
+    pub fn to_tsv(&self, circles: &[Circle], contigs: &Contigs) -> String {
+        let mut lines = vec!["id\tkind\tlength\tpath\tsupports\tmin_support".to_string()];
+        for c in circles {
+            let supports: Vec<_> = c
+                .adjacencies
+                .iter()
+                .map(|&a| self.adjacencies[a].support)
+                .collect();
+            let min = supports.iter().min().copied().unwrap_or(0);
+            let supports: Vec<_> = supports.iter().map(|x| x.to_string()).collect();
+            lines.push(format!(
+                "circle_{}\t{}\t{}\t{}\t{}\t{}",
+                c.id,
+                if c.is_master { "master" } else { "subgenomic" },
+                c.length,
+                self.path_of(c, contigs),
+                supports.join(","),
+                min
+            ));
+        }
+        lines.join("\n")
+    }
+}
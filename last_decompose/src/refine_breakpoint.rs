@@ -0,0 +1,349 @@
+//! Base-pair resolution refinement of the junctions of `ContigPair`s.
+//! For each read supporting a junction, the bases of the two encoded units around the jump
+//! and the bases between them are aligned to the reference sequences of both sides.
+//! The junction is the split of the read maximizing the sum of the two alignment scores.
+//! A range of equally good splits is reported as microhomology, and read bases
+//! aligned to neither side as an inserted sequence.
+//! The refined position of a `ContigPair` is the mode among its reads.
+use super::find_breakpoint::{ContigPair, CriticalRegion, Direction, Junction, Position};
+use super::DecomposeParams;
+use last_tiling::unit::{ChunkedUnit, Encode};
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+const MATCH: i32 = 2;
+const MISMATCH: i32 = -4;
+const GAP: i32 = -4;
+// The penalty of an insertion at the junction. It does not depend on the length,
+// as random bases often align to either side with a positive score otherwise.
+const INSERTION: i32 = -4;
+// Reads with a longer gap at the jump are not used.
+const MAX_GAP: usize = 1_000;
+// The margin of the reference beyond the length of the query.
+const MARGIN: usize = 50;
+// The minimum number of read bases aligned to each side.
+const MIN_ANCHOR: usize = 20;
+/// The minimum number of reads agreeing on the refined position.
+pub const MIN_SUPPORT: usize = 2;
+
+/// Refine the positions of the `ContigPair`s in `crs` by the bases of `reads`.
+/// A `ContigPair` supported by fewer than `MIN_SUPPORT` reads at the same position
+/// is left as it is.
+pub fn refine_contig_pairs(
+    crs: &mut [CriticalRegion],
+    reads: &[EncodedRead],
+    contigs: &Contigs,
+    params: &DecomposeParams,
+) {
+    let reads: HashMap<_, _> = reads.iter().map(|r| (r.id.as_str(), r)).collect();
+    crs.par_iter_mut()
+        .filter_map(|cr| match cr {
+            CriticalRegion::CP(ref mut cp) => Some(cp),
+            CriticalRegion::CR(_) => None,
+        })
+        .for_each(|cp| {
+            let supports: Vec<_> = cp
+                .reads()
+                .iter()
+                .filter_map(|id| reads.get(id.as_str()))
+                .filter_map(|read| refine_by_read(cp, read, contigs, params))
+                .collect();
+            if let Some((position, junction)) = consensus(supports) {
+                debug!("Refined {:?} -> {:?} {:?}", cp, position, junction);
+                cp.refine(position, junction);
+            }
+        });
+}
+
+// The refined positions of contig1 and contig2, the microhomology, and the insertion,
+// in the direction from contig1 to contig2.
+type Support = ((usize, usize), Vec<u8>, Vec<u8>);
+
+fn consensus(supports: Vec<Support>) -> Option<((usize, usize), Junction)> {
+    let mut counts: HashMap<_, usize> = HashMap::new();
+    for (position, _, _) in supports.iter() {
+        *counts.entry(*position).or_default() += 1;
+    }
+    let (&position, &support) = counts
+        .iter()
+        .max_by_key(|&(position, count)| (count, std::cmp::Reverse(*position)))?;
+    if support < MIN_SUPPORT {
+        return None;
+    }
+    let mut seqs: HashMap<_, usize> = HashMap::new();
+    for (_, mh, ins) in supports.into_iter().filter(|x| x.0 == position) {
+        *seqs.entry((mh, ins)).or_default() += 1;
+    }
+    let ((mh, ins), _) = seqs
+        .into_iter()
+        .max_by(|(a, x), (b, y)| x.cmp(y).then(b.cmp(a)))?;
+    let junction = Junction {
+        microhomology: String::from_utf8_lossy(&mh).to_string(),
+        insertion: String::from_utf8_lossy(&ins).to_string(),
+        support,
+    };
+    Some((position, junction))
+}
+
+fn is_near(pos: &Position, encode: &Encode, offset: usize) -> bool {
+    let (start, end) = pos.range();
+    let (start, end) = (start as usize, end as usize);
+    let unit = encode.unit as usize;
+    encode.contig == pos.contig() && start.max(offset) - offset <= unit && unit <= end + offset
+}
+
+// The direction of the retained side of a unit right before(`is_first`) or right after a jump.
+fn direction_of(encode: &Encode, is_first: bool) -> Direction {
+    if encode.is_forward == is_first {
+        Direction::UpStream
+    } else {
+        Direction::DownStream
+    }
+}
+
+fn is_jump(a: &Encode, b: &Encode) -> bool {
+    let step = b.unit as i32 - a.unit as i32;
+    let step = if a.is_forward { step } else { -step };
+    a.contig != b.contig || a.is_forward != b.is_forward || !(1..=2).contains(&step)
+}
+
+fn refine_by_read(
+    cp: &ContigPair,
+    read: &EncodedRead,
+    contigs: &Contigs,
+    params: &DecomposeParams,
+) -> Option<Support> {
+    let (pos1, pos2) = (cp.contig1(), cp.contig2());
+    let matches = |pos: &Position, e: &Encode, is_first: bool| {
+        is_near(pos, e, params.offset) && direction_of(e, is_first) == pos.direction()
+    };
+    let mut last: Option<&Encode> = None;
+    let mut gap: &[u8] = &[];
+    for unit in read.seq() {
+        let b = match unit {
+            ChunkedUnit::Gap(g) => {
+                gap = g.bases();
+                continue;
+            }
+            ChunkedUnit::En(e) => e,
+        };
+        if let Some(a) = last.filter(|&a| is_jump(a, b) && gap.len() <= MAX_GAP) {
+            if matches(pos1, a, true) && matches(pos2, b, false) {
+                return refine_jump((a, gap, b), contigs, false);
+            } else if matches(pos2, a, true) && matches(pos1, b, false) {
+                return refine_jump((a, gap, b), contigs, true);
+            }
+        }
+        last = Some(b);
+        gap = &[];
+    }
+    None
+}
+
+// Refine a jump from `a` to `b` in a read. If `swapped`, `a` is on contig2.
+fn refine_jump(
+    (a, gap, b): (&Encode, &[u8], &Encode),
+    contigs: &Contigs,
+    swapped: bool,
+) -> Option<Support> {
+    let query: Vec<u8> = a
+        .bases
+        .as_bytes()
+        .iter()
+        .chain(gap.iter())
+        .chain(b.bases.as_bytes().iter())
+        .map(u8::to_ascii_uppercase)
+        .collect();
+    let len = query.len() + MARGIN;
+    let (seq1, coords1) = flank(contigs.get_by_id(a.contig)?, a, len, true);
+    let (seq2, coords2) = flank(contigs.get_by_id(b.contig)?, b, len, false);
+    let rev = |xs: &[u8]| -> Vec<u8> { xs.iter().rev().copied().collect() };
+    let prefix = prefix_alignment(&query, &seq1);
+    let suffix = prefix_alignment(&rev(&query), &rev(&seq2));
+    let (start, end, mh) = split(&prefix, &suffix, swapped)?;
+    let (j1, j2) = (prefix[start].1, suffix[query.len() - end].1);
+    let (x, y) = (coords1[j1 - 1], coords2[coords2.len() - j2]);
+    let insertion = &query[start..end];
+    if swapped {
+        let homology = &query[start - mh..start];
+        Some(((y, x), revcmp(homology), revcmp(insertion)))
+    } else {
+        let homology = &query[start..start + mh];
+        Some(((x, y), homology.to_vec(), insertion.to_vec()))
+    }
+}
+
+// The reference sequence around `unit` in the orientation of the read,
+// and the coordinate of each base. If `is_first`, the sequence starts at the unit and
+// extends `len` bases. Otherwise, it ends at the unit.
+fn flank(seq: &[u8], unit: &Encode, len: usize, is_first: bool) -> (Vec<u8>, Vec<usize>) {
+    let start = (unit.unit as usize * UNIT_SIZE).min(seq.len());
+    let end = ((unit.unit as usize + 1) * UNIT_SIZE).min(seq.len());
+    let coords: Vec<usize> = match (is_first, unit.is_forward) {
+        (true, true) => (start..(start + len).min(seq.len())).collect(),
+        (true, false) => (end.saturating_sub(len)..end).rev().collect(),
+        (false, true) => (end.saturating_sub(len)..end).collect(),
+        (false, false) => (start..(start + len).min(seq.len())).rev().collect(),
+    };
+    let bases = coords
+        .iter()
+        .map(|&c| seq[c].to_ascii_uppercase())
+        .map(|b| if unit.is_forward { b } else { complement(b) })
+        .collect();
+    (bases, coords)
+}
+
+// For each prefix of the query, the best score of an alignment of it to a prefix of
+// the reference, and the length of the reference prefix.
+fn prefix_alignment(query: &[u8], reference: &[u8]) -> Vec<(i32, usize)> {
+    let mut prev: Vec<i32> = (0..=reference.len() as i32).map(|j| j * GAP).collect();
+    let mut result = vec![(0, 0)];
+    let mut current = vec![0; reference.len() + 1];
+    for (i, &q) in query.iter().enumerate() {
+        current[0] = (i as i32 + 1) * GAP;
+        for (j, &r) in reference.iter().enumerate() {
+            let mat = if q == r { MATCH } else { MISMATCH };
+            current[j + 1] = (prev[j] + mat).max(prev[j + 1] + GAP).max(current[j] + GAP);
+        }
+        let (j, &score) = current
+            .iter()
+            .enumerate()
+            .max_by_key(|&(j, &score)| (score, std::cmp::Reverse(j)))
+            .unwrap();
+        result.push((score, j));
+        std::mem::swap(&mut prev, &mut current);
+    }
+    result
+}
+
+// The best split of the query into the prefix `[..start]` aligned to the first side,
+// the insertion `[start..end]`, and the suffix `[end..]` aligned to the second side.
+// Ties are broken by the leftmost split(rightmost if `rightmost`). The length of the range
+// of tied splits without insertion is returned as the length of microhomology.
+fn split(
+    prefix: &[(i32, usize)],
+    suffix: &[(i32, usize)],
+    rightmost: bool,
+) -> Option<(usize, usize, usize)> {
+    let len = prefix.len() - 1;
+    if len < 2 * MIN_ANCHOR {
+        return None;
+    }
+    let score = |start: usize, end: usize| {
+        let ins = if start == end { 0 } else { INSERTION };
+        prefix[start].0 + suffix[len - end].0 + ins
+    };
+    let splits = || {
+        (MIN_ANCHOR..=len - MIN_ANCHOR).flat_map(move |start| {
+            (start..=len - MIN_ANCHOR).map(move |end| (score(start, end), start, end))
+        })
+    };
+    let best = splits().map(|x| x.0).max()?;
+    let optimal = splits().filter(|x| x.0 == best);
+    let (_, start, end) = if rightmost {
+        optimal.max_by_key(|&(_, s, e)| (s, std::cmp::Reverse(e)))?
+    } else {
+        optimal.min_by_key(|&(_, s, e)| (s, e))?
+    };
+    if prefix[start].1 == 0 || suffix[len - end].1 == 0 {
+        return None;
+    }
+    if start != end {
+        return Some((start, end, 0));
+    }
+    let is_tied = |s: usize| score(s, s) == best;
+    if rightmost {
+        let left = (MIN_ANCHOR..start)
+            .rev()
+            .take_while(|&s| is_tied(s))
+            .count();
+        Some((start, end, left))
+    } else {
+        let right = (start + 1..=len - MIN_ANCHOR)
+            .take_while(|&s| is_tied(s))
+            .count();
+        Some((start, end, right))
+    }
+}
+
+fn complement(b: u8) -> u8 {
+    match b {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+fn revcmp(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&b| complement(b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio_utils::fasta;
+    use rand::{Rng, SeedableRng};
+    use rand_xoshiro::Xoshiro256StarStar;
+    fn unit(unit: u16, is_forward: bool, bases: &[u8]) -> Encode {
+        let mut encode = Encode::sketch(0, unit, is_forward);
+        encode.bases = String::from_utf8(bases.to_vec()).unwrap();
+        encode
+    }
+    // A unit on contig1 and its bases.
+    type Unit<'a> = (u16, &'a [u8]);
+    // Refine the jump from `first` to `second` on the forward strand, and on the reverse strand,
+    // where `first` is on contig1. Both should give the same position and junction.
+    fn refine(
+        contigs: &Contigs,
+        (first, second): (Unit, Unit),
+        gap: &[u8],
+    ) -> Vec<((usize, usize), Junction)> {
+        let forward = (unit(first.0, true, first.1), unit(second.0, true, second.1));
+        let reverse = (
+            unit(second.0, false, &revcmp(second.1)),
+            unit(first.0, false, &revcmp(first.1)),
+        );
+        let forward = refine_jump((&forward.0, gap, &forward.1), contigs, false).unwrap();
+        let gap = revcmp(gap);
+        let reverse = refine_jump((&reverse.0, &gap, &reverse.1), contigs, true).unwrap();
+        vec![forward, reverse]
+            .into_iter()
+            .map(|support| consensus(vec![support.clone(), support]).unwrap())
+            .collect()
+    }
+    #[test]
+    fn refine_jump_test() {
+        let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(24);
+        let mut seq: Vec<u8> = (0..20 * UNIT_SIZE)
+            .map(|_| b"ACGT"[rng.gen_range(0, 4)])
+            .collect();
+        // Microhomology of GCA at 377..380 and 1217..1220.
+        seq[376] = b'A';
+        seq[377..380].copy_from_slice(b"GCA");
+        seq[380] = b'T';
+        seq[1216] = b'C';
+        seq[1217..1220].copy_from_slice(b"GCA");
+        seq[1220] = b'G';
+        // The inserted bases differ from the bases next to the junction.
+        seq[560..568].copy_from_slice(b"GGCTAAGC");
+        seq[1532..1540].copy_from_slice(b"CAATGTGA");
+        let contigs = Contigs::new(vec![fasta::Record::with_data("test", &None, &seq)]);
+        let junction = |mh: &str, ins: &str| Junction {
+            microhomology: mh.to_string(),
+            insertion: ins.to_string(),
+            support: 2,
+        };
+        let jump = ((3, &seq[300..380]), (12, &seq[1220..1300]));
+        for refined in refine(&contigs, jump, &[]) {
+            assert_eq!(refined, ((376, 1217), junction("GCA", "")));
+        }
+        // An insertion between 500..560 and 1540..1600.
+        let jump = ((5, &seq[500..560]), (15, &seq[1540..1600]));
+        for refined in refine(&contigs, jump, b"TTGACCAT") {
+            assert_eq!(refined, ((559, 1540), junction("", "TTGACCAT")));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Statistical tests of critical regions against the background of chimeric reads.
+//! The background rates are the number of jumps(split reads) and the number of read ends
+//! per read covering a unit, estimated from the units outside of repeats.
+//! Units with a significant excess of jumps or ends are excluded from the estimation,
+//! as they are likely to be real breakpoints.
+//! The number of reads supporting a critical region is compared with the expected
+//! number under the background, a Poisson distribution with the mean proportional to
+//! the local coverage. Thus, the number of reads required to call a critical region
+//! scales with the coverage. The p-values are adjusted by the Benjamini-Hochberg procedure.
+use super::find_breakpoint::{is_jumping, CriticalRegion, Position, Significance};
+use super::ERead;
+use last_tiling::repeat::RepeatPairs;
+use last_tiling::{Contigs, UNIT_SIZE};
+
+// Units with the p-value less than this value are excluded from the background.
+const OUTLIER_PVALUE: f64 = 0.001;
+
+/// The background rates of jumps and read ends.
+#[derive(Debug, Clone)]
+pub struct Background {
+    coverages: Vec<Vec<u32>>,
+    /// The number of jumps per read covering a unit.
+    pub jump_rate: f64,
+    /// The number of read ends per read covering a unit.
+    pub end_rate: f64,
+}
+
+impl Background {
+    pub fn estimate(reads: &[ERead], contigs: &Contigs, repeats: &[RepeatPairs]) -> Self {
+        let coverages = super::get_coverages(contigs, reads);
+        let mut jumps: Vec<Vec<u32>> = coverages.iter().map(|c| vec![0; c.len()]).collect();
+        let mut ends = jumps.clone();
+        for read in reads {
+            for w in read.seq().windows(4).filter(is_jumping) {
+                jumps[w[1].contig as usize][w[1].unit as usize] += 1;
+                jumps[w[2].contig as usize][w[2].unit as usize] += 1;
+            }
+            if let Some(((first, _), (last, _))) = read.get_edges() {
+                ends[first.contig as usize][first.unit as usize] += 1;
+                ends[last.contig as usize][last.unit as usize] += 1;
+            }
+        }
+        let is_repeat = |contig: usize, unit: usize| {
+            let (start, end) = (unit * UNIT_SIZE, (unit + 1) * UNIT_SIZE);
+            repeats
+                .iter()
+                .flat_map(|pair| pair.inner())
+                .any(|r| r.id() as usize == contig && r.start() < end && start < r.end())
+        };
+        // (contig, unit, coverage) of the units outside of repeats.
+        let units: Vec<_> = coverages
+            .iter()
+            .enumerate()
+            .flat_map(|(c, cov)| cov.iter().enumerate().map(move |(u, &x)| (c, u, x)))
+            .filter(|&(c, u, x)| x > 0 && !is_repeat(c, u))
+            .collect();
+        let robust_rate = |counts: &[Vec<u32>]| -> f64 {
+            let rate = |units: &mut dyn Iterator<Item = &(usize, usize, u32)>| {
+                let (count, cov) = units.fold((0., 0.), |(count, cov), &(c, u, x)| {
+                    (count + counts[c][u] as f64, cov + x as f64)
+                });
+                count / cov.max(1.)
+            };
+            let initial = rate(&mut units.iter());
+            let mut inliers = units.iter().filter(|&&(c, u, x)| {
+                poisson_sf(counts[c][u] as usize, initial * x as f64) >= OUTLIER_PVALUE
+            });
+            rate(&mut inliers)
+        };
+        let jump_rate = robust_rate(&jumps);
+        let end_rate = robust_rate(&ends);
+        debug!(
+            "Background: jump rate {:.5}, end rate {:.5}",
+            jump_rate, end_rate
+        );
+        Self {
+            coverages,
+            jump_rate,
+            end_rate,
+        }
+    }
+    // The total coverage of the units in the position.
+    fn coverage(&self, pos: &Position) -> f64 {
+        let (start, end) = pos.range();
+        let coverage = &self.coverages[pos.contig() as usize];
+        let end = (end.max(start) as usize + 1).min(coverage.len());
+        let start = (start.max(0) as usize).min(end);
+        coverage[start..end].iter().map(|&x| x as f64).sum()
+    }
+    /// Test a critical region. For a `ContigPair`, the side with the larger coverage is used.
+    pub fn test(&self, cr: &CriticalRegion) -> Significance {
+        let (support, expected) = match cr {
+            CriticalRegion::CP(cp) => {
+                let coverage = self.coverage(cp.contig1()).max(self.coverage(cp.contig2()));
+                (cp.reads().len(), self.jump_rate * coverage)
+            }
+            CriticalRegion::CR(cr) => {
+                let coverage = self.coverage(cr.contig());
+                (cr.reads().len(), self.end_rate * coverage)
+            }
+        };
+        let pvalue = poisson_sf(support, expected);
+        Significance {
+            expected,
+            pvalue,
+            qvalue: pvalue,
+        }
+    }
+}
+
+/// P(X >= k) for X ~ Poisson(lambda).
+pub fn poisson_sf(k: usize, lambda: f64) -> f64 {
+    if k == 0 {
+        return 1.;
+    } else if lambda <= 0. {
+        return 0.;
+    }
+    // Sum the upper tail from the k-th term in the log space.
+    let ln_factorial = (1..=k).map(|i| (i as f64).ln()).sum::<f64>();
+    let mut ln_term = -lambda + k as f64 * lambda.ln() - ln_factorial;
+    let mut sum = 0.;
+    let mut i = k;
+    loop {
+        let term = ln_term.exp();
+        sum += term;
+        i += 1;
+        ln_term += lambda.ln() - (i as f64).ln();
+        if (i as f64) > lambda && term <= sum * 1e-12 {
+            break;
+        }
+    }
+    sum.min(1.)
+}
+
+/// The q-values of the p-values by the Benjamini-Hochberg procedure.
+pub fn benjamini_hochberg(pvalues: &[f64]) -> Vec<f64> {
+    let len = pvalues.len() as f64;
+    let mut order: Vec<_> = (0..pvalues.len()).collect();
+    order.sort_by(|&a, &b| pvalues[a].total_cmp(&pvalues[b]));
+    let mut qvalues = vec![1.; pvalues.len()];
+    let mut min = 1f64;
+    for (rank, &idx) in order.iter().enumerate().rev() {
+        min = min.min(pvalues[idx] * len / (rank + 1) as f64);
+        qvalues[idx] = min;
+    }
+    qvalues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn poisson_test() {
+        assert_eq!(poisson_sf(0, 3.), 1.);
+        assert!((poisson_sf(1, 2.) - (1. - (-2f64).exp())).abs() < 1e-9);
+        // P(X >= 3) = 1 - e^-1(1 + 1 + 1/2)
+        assert!((poisson_sf(3, 1.) - (1. - 2.5 * (-1f64).exp())).abs() < 1e-9);
+        assert!(poisson_sf(50, 1.) < 1e-50);
+    }
+    #[test]
+    fn bh_test() {
+        let qvalues = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.5]);
+        let expected = [0.04, 0.04 * 4. / 3., 0.04 * 4. / 3., 0.5];
+        for (q, e) in qvalues.iter().zip(expected.iter()) {
+            assert!((q - e).abs() < 1e-9, "{:?}", qvalues);
+        }
+    }
+    #[test]
+    fn breakpoint_test_param_test() {
+        use crate::{BreakpointTest, DecomposeParams};
+        // The Poisson test is opt-in.
+        let mut params = DecomposeParams::default();
+        assert_eq!(params.breakpoint_test, BreakpointTest::Fixed);
+        params.set("breakpoint_test", "poisson").unwrap();
+        assert_eq!(params.breakpoint_test, BreakpointTest::Poisson);
+    }
+}
@@ -0,0 +1,97 @@
+//! Simulation of reads on a synthetic genome with repeats, for benchmarks and tests.
+//! Reads are simulated directly as sequences of encoded units.
+use bio_utils::fasta;
+use last_tiling::unit::{ChunkedUnit, Encode};
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use rand::Rng;
+pub const REPEAT_LEN: u16 = 20;
+pub const RECOMBINATION: f64 = 0.3;
+
+/// (start, end, partner, is_inverted) of each copy, in units.
+pub type RepeatCopy = (u16, u16, usize, bool);
+
+/// A genome with `num_repeats` pairs of repeats, and reads on it with `coverage`
+/// percent of the genome in units.
+pub fn repeat_genome<R: Rng>(
+    rng: &mut R,
+    num_repeats: usize,
+    coverage: usize,
+) -> (Contigs, Vec<EncodedRead>) {
+    let len = num_repeats * 2 * REPEAT_LEN as usize * 3;
+    let seq: Vec<u8> = (0..len * UNIT_SIZE)
+        .map(|_| b"ACGT"[rng.gen_range(0, 4)])
+        .collect();
+    let contigs = Contigs::new(vec![fasta::Record::with_data("genome", &None, &seq)]);
+    let copies = place_repeats(rng, num_repeats, len as u16);
+    let num_reads = len * coverage / 100;
+    let reads: Vec<_> = (0..num_reads)
+        .map(|i| simulate_read(rng, i, &copies, len as u16))
+        .collect();
+    (contigs, reads)
+}
+
+/// Place `num_repeats` pairs of repeats on a genome of `len` units.
+pub fn place_repeats<R: Rng>(rng: &mut R, num_repeats: usize, len: u16) -> Vec<RepeatCopy> {
+    // Each copy is placed in its own slot, so that copies do not overlap.
+    let slot = len / (2 * num_repeats as u16);
+    let mut slots: Vec<u16> = (0..2 * num_repeats as u16).collect();
+    for i in (1..slots.len()).rev() {
+        slots.swap(i, rng.gen_range(0, i + 1));
+    }
+    let mut copies = vec![];
+    for pair in slots.chunks_exact(2) {
+        let is_inverted = rng.gen_bool(0.5);
+        let idx = copies.len();
+        for (k, &s) in pair.iter().enumerate() {
+            let start = s * slot + rng.gen_range(1, slot - REPEAT_LEN);
+            let partner = if k == 0 { idx + 1 } else { idx };
+            copies.push((start, start + REPEAT_LEN - 1, partner, is_inverted));
+        }
+    }
+    copies
+}
+
+/// Simulate a read from a random position of the genome. A read entering a copy of a repeat
+/// goes out of the other copy with probability `RECOMBINATION`.
+pub fn simulate_read<R: Rng>(
+    rng: &mut R,
+    id: usize,
+    copies: &[RepeatCopy],
+    len: u16,
+) -> EncodedRead {
+    let read_len = rng.gen_range(50, 150);
+    let mut position = rng.gen_range(0, len) as i32;
+    let mut step = 1;
+    let mut units = vec![];
+    while units.len() < read_len && 0 <= position && position < len as i32 {
+        let unit = position as u16;
+        units.push(Encode::sketch(0, unit, step == 1));
+        // Jump into the other copy if the read is entering a copy.
+        let entering = copies
+            .iter()
+            .find(|&&(s, e, _, _)| (step == 1 && unit + 1 == s) || (step == -1 && unit == e + 1));
+        match entering {
+            Some(&(_, _, partner, is_inverted)) if rng.gen_bool(RECOMBINATION) => {
+                let (s, e, _, _) = copies[partner];
+                let (next, next_step) = match (step == 1, is_inverted) {
+                    (true, false) => (s, 1),
+                    (true, true) => (e, -1),
+                    (false, false) => (e, -1),
+                    (false, true) => (s, 1),
+                };
+                position = next as i32;
+                step = next_step;
+            }
+            _ => position += step,
+        }
+    }
+    if rng.gen_bool(0.5) {
+        units.reverse();
+        units.iter_mut().for_each(|e| e.is_forward = !e.is_forward);
+    }
+    EncodedRead {
+        id: format!("read{}", id),
+        seq: units.into_iter().map(ChunkedUnit::En).collect(),
+        desc: None,
+    }
+}
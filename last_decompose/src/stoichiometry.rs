@@ -0,0 +1,170 @@
+//! Relative abundance(stoichiometry) of the reconstructed structures and of the
+//! repeat-mediated configurations.
+//! The abundance of a structure is its mean depth along the path spelling its contig.
+//! As each read contributes by the bases it covers on the path, long reads and
+//! structures spanned easily are not over-represented as in read counts.
+//! The abundance of a configuration(a `ContigPair`) is the fraction of reads supporting
+//! the junction among the reads spanning either the junction or its parental positions.
+//! The confidence intervals are computed by bootstrapping reads.
+use super::assemble::{self, ChunkedRead};
+use super::find_breakpoint::{Cluster, ReadClassify};
+use super::ERead;
+use last_tiling::{Contigs, EncodedRead};
+use rand::{Rng, SeedableRng};
+use rand_xoshiro::Xoshiro256StarStar;
+
+/// The two-sided confidence level of the bootstrap intervals.
+pub const CONFIDENCE_LEVEL: f64 = 0.95;
+// The seed of bootstrapping, fixed so that the reports are reproducible.
+const SEED: u64 = 3_214;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Abundance {
+    /// `structure` or `configuration`.
+    pub kind: String,
+    pub name: String,
+    /// The number of reads supporting the structure/configuration.
+    pub reads: usize,
+    /// The mean depth along the path(structure), or the number of reads spanning the junction
+    /// or its parental positions(configuration).
+    pub depth: f64,
+    /// The molar fraction.
+    pub fraction: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Abundance {
+    pub const HEADER: &'static str = "kind\tname\treads\tdepth\tfraction\tlower\tupper";
+}
+
+impl std::fmt::Display for Abundance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{:.2}\t{:.4}\t{:.4}\t{:.4}",
+            self.kind, self.name, self.reads, self.depth, self.fraction, self.lower, self.upper
+        )
+    }
+}
+
+/// The abundances of the final clusters. `assignments` should be the result of assembly
+/// on `reads`, i.e., `DecomposedResult::assignments`.
+pub fn structure_abundances(
+    reads: &[ChunkedRead],
+    assignments: &[(String, Option<u8>)],
+    bootstrap: usize,
+) -> Vec<Abundance> {
+    let coverages = assemble::path_coverages(reads, assignments);
+    let lengths: Vec<_> = coverages.iter().map(|c| c.path_length as f64).collect();
+    // (Index of the structure, bases) for each read.
+    let bases: Vec<(usize, f64)> = coverages
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, c)| c.read_bases.iter().map(move |&(_, b)| (idx, b as f64)))
+        .collect();
+    let depths = |bases: &mut dyn Iterator<Item = &(usize, f64)>| -> Vec<f64> {
+        let mut depths = vec![0.; lengths.len()];
+        for &(idx, b) in bases {
+            depths[idx] += b / lengths[idx];
+        }
+        depths
+    };
+    let depth = depths(&mut bases.iter());
+    let fraction = normalize(&depth);
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(SEED);
+    let replicates: Vec<Vec<f64>> = (0..bootstrap)
+        .map(|_| {
+            let mut resampled = (0..bases.len()).map(|_| &bases[rng.gen_range(0, bases.len())]);
+            normalize(&depths(&mut resampled))
+        })
+        .collect();
+    coverages
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| {
+            let samples: Vec<_> = replicates.iter().map(|r| r[idx]).collect();
+            let (lower, upper) = confidence_interval(samples, fraction[idx]);
+            Abundance {
+                kind: "structure".to_string(),
+                name: format!("{}{:04}", assemble::CONTIG_PREFIX, c.cluster),
+                reads: c.read_bases.len(),
+                depth: depth[idx],
+                fraction: fraction[idx],
+                lower,
+                upper,
+            }
+        })
+        .collect()
+}
+
+/// The abundances of the configurations of each `ContigPair` in the initial clusters.
+/// The fraction is the ratio of the reads supporting the junction to the reads
+/// supporting the junction or spanning its parental positions.
+pub fn configuration_abundances(
+    reads: &[EncodedRead],
+    initial_clusters: &[Cluster],
+    contigs: &Contigs,
+    bootstrap: usize,
+) -> Vec<Abundance> {
+    let reads: Vec<_> = reads.iter().map(ERead::new_no_gapfill).collect();
+    let mut rng: Xoshiro256StarStar = SeedableRng::seed_from_u64(SEED);
+    initial_clusters
+        .iter()
+        .flat_map(|cl| cl.members.iter().map(move |m| (cl.id, &m.cr)))
+        .filter_map(|(id, cr)| cr.contig_pair().map(|cp| (id, cp)))
+        .map(|(id, cp)| {
+            let junction = cp.reads().len();
+            let parental = reads
+                .iter()
+                .filter(|r| !cp.has(r.id()) && cp.is_spanned_by(r))
+                .count();
+            let total = junction + parental;
+            let fraction = junction as f64 / total.max(1) as f64;
+            let samples: Vec<_> = (0..bootstrap)
+                .map(|_| {
+                    let count = (0..total).filter(|_| rng.gen_range(0, total) < junction);
+                    count.count() as f64 / total.max(1) as f64
+                })
+                .collect();
+            let (lower, upper) = confidence_interval(samples, fraction);
+            let format = |pos: &super::find_breakpoint::Position| {
+                let (start, end) = pos.range();
+                let name = contigs.get_name_by_id(pos.contig());
+                format!("{}:{}-{}", name, start, end)
+            };
+            let (c1, c2) = (format(cp.contig1()), format(cp.contig2()));
+            Abundance {
+                kind: "configuration".to_string(),
+                name: format!("cluster{}:{}/{}", id, c1, c2),
+                reads: junction,
+                depth: total as f64,
+                fraction,
+                lower,
+                upper,
+            }
+        })
+        .collect()
+}
+
+fn normalize(xs: &[f64]) -> Vec<f64> {
+    let sum = xs.iter().sum::<f64>();
+    if sum > 0. {
+        xs.iter().map(|x| x / sum).collect()
+    } else {
+        vec![0.; xs.len()]
+    }
+}
+
+// Percentile interval of bootstrap samples. If there is no sample, return (point, point).
+fn confidence_interval(mut samples: Vec<f64>, point: f64) -> (f64, f64) {
+    if samples.is_empty() {
+        return (point, point);
+    }
+    samples.sort_by(|a, b| a.total_cmp(b));
+    let alpha = (1. - CONFIDENCE_LEVEL) / 2.;
+    let last = samples.len() - 1;
+    let lower = samples[(alpha * last as f64).floor() as usize];
+    let upper = samples[((1. - alpha) * last as f64).ceil() as usize];
+    (lower, upper)
+}
@@ -0,0 +1,316 @@
+//! Export critical regions as structural variants in VCF 4.3(breakends) and BEDPE.
+//! Unit indices are converted into base coordinates by `last_tiling::UNIT_SIZE`.
+//! The range of units of a position is reported as the confidence interval(`CIPOS`) in VCF,
+//! and as the range of each side in BEDPE.
+//! Strands follow the convention of paired-end SV callers: `+` if the sequence upstream of
+//! the breakend is retained, and `-` if the sequence downstream of it is retained.
+//! A `ConfluentRegion` has no mate, thus it is written as a single breakend.
+//! If a `ContigPair` is refined to base-pair resolution, its breakends are at the refined
+//! positions, the inserted sequence is in the ALT alleles, and the microhomology gives
+//! the confidence interval. Otherwise, the breakends are marked as `IMPRECISE`.
+//! If a critical region is tested against the background of chimeric reads, QUAL is
+//! the phred-scaled p-value, and the q-value is reported as `QVALUE`.
+use super::find_breakpoint::{
+    Cluster, CriticalRegion, Direction, Junction, Position, Rearrangement, Significance,
+};
+use last_tiling::{Contigs, UNIT_SIZE};
+
+/// A side of a junction in base coordinates.
+#[derive(Debug, Clone)]
+struct Breakend {
+    contig: String,
+    // 1-based position of the breakend.
+    pos: usize,
+    // 0-based, half-open range of the breakend.
+    range: (usize, usize),
+    direction: Direction,
+    base: char,
+    // The inserted sequence between this breakend and its mate, as in the ALT allele.
+    insertion: String,
+    // The length of microhomology, if refined.
+    homology: Option<usize>,
+}
+
+impl Breakend {
+    fn new(pos: &Position, contigs: &Contigs) -> Self {
+        let seq = contigs.get_by_id(pos.contig()).unwrap_or(&[]);
+        let (start, end) = pos.range();
+        let start = (start.max(0) as usize * UNIT_SIZE).min(seq.len().max(1) - 1);
+        // The end unit is a part of the position, as in `Position::is_in`.
+        let end = ((end.max(0) as usize + 1) * UNIT_SIZE)
+            .min(seq.len())
+            .max(start + 1);
+        let (start, end, pos1) = match pos.refined() {
+            Some(x) => (x, x + 1, x + 1),
+            None => (start, end, (start + end) / 2 + 1),
+        };
+        let base = seq.get(pos1 - 1).map(|&b| b as char).unwrap_or('N');
+        Self {
+            contig: contigs.get_name_by_id(pos.contig()).to_string(),
+            pos: pos1,
+            range: (start, end),
+            direction: pos.direction(),
+            base: base.to_ascii_uppercase(),
+            insertion: String::new(),
+            homology: None,
+        }
+    }
+    // Set the refined junction. `is_first` should be true if this breakend is on contig1.
+    fn set_junction(&mut self, junction: &Junction, is_first: bool) {
+        let homology = junction.microhomology.len();
+        // Whether the junction sequence from contig1 to contig2 is on the forward strand.
+        if is_first == (self.direction == Direction::UpStream) {
+            self.insertion = junction.insertion.clone();
+            self.range.1 += homology;
+        } else {
+            self.insertion = revcmp(&junction.insertion);
+            self.range.0 = self.range.0.saturating_sub(homology);
+        }
+        self.homology = Some(homology);
+    }
+    fn strand(&self) -> char {
+        match self.direction {
+            Direction::UpStream => '+',
+            Direction::DownStream => '-',
+        }
+    }
+    fn cipos(&self) -> (i64, i64) {
+        let pos = self.pos as i64;
+        (self.range.0 as i64 + 1 - pos, self.range.1 as i64 - pos)
+    }
+    // ALT allele joined with `mate`. If there is no mate, it is a single breakend.
+    fn alt(&self, mate: Option<&Breakend>) -> String {
+        let mate = match mate {
+            Some(mate) => {
+                let bracket = match mate.direction {
+                    Direction::DownStream => '[',
+                    Direction::UpStream => ']',
+                };
+                format!("{}{}:{}{}", bracket, mate.contig, mate.pos, bracket)
+            }
+            None => ".".to_string(),
+        };
+        match self.direction {
+            Direction::UpStream => format!("{}{}{}", self.base, self.insertion, mate),
+            Direction::DownStream => format!("{}{}{}", mate, self.insertion, self.base),
+        }
+    }
+}
+
+// Phred-scaled probability, capped at 999.
+fn phred(p: f64) -> f64 {
+    (-10. * p.max(1e-100).log10()).min(999.)
+}
+
+fn revcmp(seq: &str) -> String {
+    seq.chars()
+        .rev()
+        .map(|b| match b {
+            'A' => 'T',
+            'C' => 'G',
+            'G' => 'C',
+            'T' => 'A',
+            _ => 'N',
+        })
+        .collect()
+}
+
+struct Record {
+    id: String,
+    cluster: usize,
+    // The number of supporting reads.
+    support: usize,
+    rearrangement: Rearrangement,
+    significance: Option<Significance>,
+    bnd: Breakend,
+    mate: Option<Breakend>,
+}
+
+fn junctions(clusters: &[Cluster], contigs: &Contigs) -> Vec<Record> {
+    clusters
+        .iter()
+        .flat_map(|cl| {
+            cl.members
+                .iter()
+                .enumerate()
+                .map(move |(idx, m)| (cl.id, idx, m))
+        })
+        .map(|(cluster, idx, m)| match &m.cr {
+            CriticalRegion::CP(cp) => {
+                let mut bnd1 = Breakend::new(cp.contig1(), contigs);
+                let mut bnd2 = Breakend::new(cp.contig2(), contigs);
+                if let Some(junction) = cp.junction() {
+                    bnd1.set_junction(junction, true);
+                    bnd2.set_junction(junction, false);
+                }
+                Record {
+                    id: format!("cp_{}_{}", cluster, idx),
+                    cluster,
+                    support: cp.reads().len(),
+                    rearrangement: m.rearrangement,
+                    significance: m.cr.significance().copied(),
+                    bnd: bnd1,
+                    mate: Some(bnd2),
+                }
+            }
+            CriticalRegion::CR(cr) => Record {
+                id: format!("cr_{}_{}", cluster, idx),
+                cluster,
+                support: cr.reads().len(),
+                rearrangement: m.rearrangement,
+                significance: m.cr.significance().copied(),
+                bnd: Breakend::new(cr.contig(), contigs),
+                mate: None,
+            },
+        })
+        .collect()
+}
+
+/// VCF 4.3 records of the critical regions in the initial clusters.
+/// Each `ContigPair` is written as a pair of mated breakends.
+pub fn to_vcf(clusters: &[Cluster], contigs: &Contigs) -> String {
+    let mut header = vec![
+        "##fileformat=VCFv4.3".to_string(),
+        "##source=last_decompose".to_string(),
+    ];
+    for (id, name) in contigs.names().iter().enumerate() {
+        let len = contigs.get_by_id(id as u16).map(|s| s.len()).unwrap_or(0);
+        header.push(format!("##contig=<ID={},length={}>", name, len));
+    }
+    header.extend(
+        vec![
+            r#"##INFO=<ID=SVTYPE,Number=1,Type=String,Description="Type of structural variant">"#,
+            r#"##INFO=<ID=MATEID,Number=.,Type=String,Description="ID of mate breakends">"#,
+            r#"##INFO=<ID=CIPOS,Number=2,Type=Integer,Description="Confidence interval around POS">"#,
+            r#"##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description="Imprecise structural variation">"#,
+            r#"##INFO=<ID=HOMLEN,Number=1,Type=Integer,Description="Length of microhomology at the breakend">"#,
+            r#"##INFO=<ID=STRANDS,Number=1,Type=String,Description="Strands of the breakend and its mate">"#,
+            r#"##INFO=<ID=SU,Number=1,Type=Integer,Description="Number of supporting reads">"#,
+            r#"##INFO=<ID=CLUSTER,Number=1,Type=Integer,Description="ID of the initial cluster">"#,
+            r#"##INFO=<ID=QVALUE,Number=1,Type=Float,Description="P-value adjusted by the Benjamini-Hochberg procedure">"#,
+            r#"##INFO=<ID=SVCLASS,Number=1,Type=String,Description="Class of the rearrangement(inversion, deletion, tandem_duplication, translocation, or confluence)">"#,
+            "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO",
+        ]
+        .into_iter()
+        .map(|x| x.to_string()),
+    );
+    let mut records = vec![];
+    for rec in junctions(clusters, contigs) {
+        let mut record = |bnd: &Breakend, mate: Option<&Breakend>, id: String, mate_id| {
+            let (ci_s, ci_e) = bnd.cipos();
+            let strands = match mate {
+                Some(mate) => format!("{}{}", bnd.strand(), mate.strand()),
+                None => format!("{}", bnd.strand()),
+            };
+            let mut info = format!("SVTYPE=BND;CIPOS={},{};STRANDS={}", ci_s, ci_e, strands);
+            match bnd.homology {
+                Some(homology) => info += &format!(";HOMLEN={}", homology),
+                None => info += ";IMPRECISE",
+            }
+            if let Some(mate_id) = mate_id {
+                info += &format!(";MATEID={}", mate_id);
+            }
+            info += &format!(";SU={};CLUSTER={}", rec.support, rec.cluster);
+            // Records never tested have neither QUAL nor FILTER.
+            let (qual, filter) = match rec.significance {
+                Some(ref sig) => {
+                    info += &format!(";QVALUE={:.3e}", sig.qvalue);
+                    (format!("{:.1}", phred(sig.pvalue)), "PASS")
+                }
+                None => (".".to_string(), "."),
+            };
+            info += &format!(";SVCLASS={}", rec.rearrangement);
+            let line = format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                bnd.contig,
+                bnd.pos,
+                id,
+                bnd.base,
+                bnd.alt(mate),
+                qual,
+                filter,
+                info
+            );
+            records.push((contigs.get_id(&bnd.contig), bnd.pos, line));
+        };
+        match rec.mate {
+            Some(ref mate) => {
+                let (id1, id2) = (format!("{}_1", rec.id), format!("{}_2", rec.id));
+                record(&rec.bnd, Some(mate), id1.clone(), Some(id2.clone()));
+                record(mate, Some(&rec.bnd), id2, Some(id1));
+            }
+            None => record(&rec.bnd, None, rec.id.clone(), None),
+        }
+    }
+    records.sort_by_key(|x| (x.0, x.1));
+    header.extend(records.into_iter().map(|x| x.2));
+    header.join("\n")
+}
+
+/// BEDPE lines of the critical regions in the initial clusters.
+/// The score is the phred-scaled p-value, or `.` if the critical region was not tested.
+/// The columns after the strands are the number of supporting reads, the ID of the cluster,
+/// the type of the critical region(`ContigPair` or `ConfluentRegion`), and the class of
+/// the rearrangement.
+pub fn to_bedpe(clusters: &[Cluster], contigs: &Contigs) -> String {
+    let mut lines = vec![
+        "#chrom1\tstart1\tend1\tchrom2\tstart2\tend2\tname\tscore\tstrand1\tstrand2\tsupport\tcluster\ttype\tclass".to_string(),
+    ];
+    for rec in junctions(clusters, contigs) {
+        let (second, strand2, kind) = match rec.mate {
+            Some(ref mate) => {
+                let (s, e) = mate.range;
+                let second = format!("{}\t{}\t{}", mate.contig, s, e);
+                (second, mate.strand(), "ContigPair")
+            }
+            None => (".\t-1\t-1".to_string(), '.', "ConfluentRegion"),
+        };
+        let score = match rec.significance {
+            Some(ref sig) => format!("{:.1}", phred(sig.pvalue)),
+            None => ".".to_string(),
+        };
+        let (s, e) = rec.bnd.range;
+        let first = format!("{}\t{}\t{}", rec.bnd.contig, s, e);
+        lines.push(format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            first,
+            second,
+            rec.id,
+            score,
+            rec.bnd.strand(),
+            strand2,
+            rec.support,
+            rec.cluster,
+            kind,
+            rec.rearrangement
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio_utils::fasta;
+    #[test]
+    fn breakend_test() {
+        let seq = vec![b'A'; 10 * UNIT_SIZE];
+        let contigs = Contigs::new(vec![fasta::Record::with_data("c", &None, &seq)]);
+        let position = |s, e| Position::new(0, s, e, Direction::DownStream, 9, 0);
+        // Units from 2 to 4, both inclusive.
+        let bnd = Breakend::new(&position(2, 4), &contigs);
+        assert_eq!(bnd.range, (2 * UNIT_SIZE, 5 * UNIT_SIZE));
+        assert_eq!(bnd.pos, 7 * UNIT_SIZE / 2 + 1);
+        let pos = bnd.pos as i64;
+        let (start, end) = (2 * UNIT_SIZE as i64, 5 * UNIT_SIZE as i64);
+        assert_eq!(bnd.cipos(), (start + 1 - pos, end - pos));
+        // A single unit.
+        let bnd = Breakend::new(&position(3, 3), &contigs);
+        assert_eq!(bnd.range, (3 * UNIT_SIZE, 4 * UNIT_SIZE));
+        // The last unit ends at the end of the contig, and units beyond it are clamped.
+        let bnd = Breakend::new(&position(8, 9), &contigs);
+        assert_eq!(bnd.range, (8 * UNIT_SIZE, seq.len()));
+        let bnd = Breakend::new(&position(8, 12), &contigs);
+        assert_eq!(bnd.range, (8 * UNIT_SIZE, seq.len()));
+    }
+}
@@ -0,0 +1,246 @@
+//! Layouts of the windows and the reconciliation of labels between overlapping windows.
+//! Reads are clustered in each window independently, thus the labels in neighbouring windows
+//! do not correspond to each other. If two neighbouring windows share units, the labels of
+//! the latter window are permuted so that the number of shared reads with the same label
+//! in both windows is maximized, by a maximum weight matching. Then, a shared read still
+//! having different labels is given the label maximizing the product of its posterior
+//! probabilities in the two windows.
+use super::bipartite_matching;
+use super::find_breakpoint::Cluster;
+use super::{DecomposeParams, ERead, Entry};
+use last_tiling::{Contigs, EncodedRead, UNIT_SIZE};
+use std::collections::HashMap;
+
+/// Split `ranges` at `breakpoints`.
+pub fn split_at(ranges: &[(usize, usize)], breakpoints: &[usize]) -> Vec<(usize, usize)> {
+    ranges
+        .iter()
+        .flat_map(|&(start, end)| {
+            let mut bounds = vec![start];
+            bounds.extend(breakpoints.iter().filter(|&&b| start < b && b < end));
+            bounds.push(end);
+            bounds.sort();
+            bounds.dedup();
+            bounds.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Tile `(start, end)` by windows of `params.window_size` units, sharing `params.overlap`
+/// units with the next one. A last window shorter than the half of `window_size` is merged
+/// into the previous one. Windows never go beyond `end`.
+pub fn tile((start, end): (usize, usize), params: &DecomposeParams) -> Vec<(usize, usize)> {
+    let size = params.window_size.max(1);
+    let step = size.saturating_sub(params.overlap).max(1);
+    let mut windows: Vec<(usize, usize)> = vec![];
+    let mut s = start;
+    loop {
+        let e = (s + size).min(end);
+        windows.push((s, e));
+        if e == end {
+            break;
+        }
+        s += step;
+    }
+    if let [.., (_, prev_end), (s, e)] = windows.as_mut_slice() {
+        if *e - *s < size / 2 {
+            *prev_end = end;
+            windows.pop();
+        }
+    }
+    windows
+}
+
+/// Windows on `(start, end)` each of which has about `params.window_size` times `mean`
+/// encoded units, with a half to twice `window_size` units. Consecutive windows share
+/// `params.overlap` units.
+pub fn by_coverage(
+    (start, end): (usize, usize),
+    covs: &[u32],
+    mean: u32,
+    params: &DecomposeParams,
+) -> Vec<(usize, usize)> {
+    let target = params.window_size as u64 * mean.max(1) as u64;
+    let (min_len, max_len) = (
+        (params.window_size / 2).max(1),
+        params.window_size.max(1) * 2,
+    );
+    let mut windows = vec![];
+    let mut s = start;
+    while s < end {
+        let (mut e, mut total) = (s, 0);
+        while e < end && (e - s < min_len || (total < target && e - s < max_len)) {
+            total += covs[e] as u64;
+            e += 1;
+        }
+        if end - e < min_len {
+            e = end;
+        }
+        windows.push((s, e));
+        s = e.saturating_sub(params.overlap).max(s + 1);
+        if e == end {
+            break;
+        }
+    }
+    windows
+}
+
+/// The units of the critical regions of `clusters` on `contig`.
+pub fn breakpoints(clusters: &[Cluster], contig: u16) -> Vec<usize> {
+    let mut breakpoints: Vec<_> = clusters
+        .iter()
+        .flat_map(|cl| cl.ranges())
+        .filter(|&(c, _)| c == contig)
+        .map(|(_, (start, end))| (start.max(0) + end.max(0)) as usize / 2)
+        .collect();
+    breakpoints.sort();
+    breakpoints.dedup();
+    breakpoints
+}
+
+/// The windows on all the contigs. `coverages` should be the ones from `get_coverages`.
+pub fn layout(
+    contigs: &Contigs,
+    coverages: &[Vec<u32>],
+    initial_clusters: &[Cluster],
+    params: &DecomposeParams,
+) -> Vec<(u16, u16, u16)> {
+    (0..contigs.get_num_of_contigs())
+        .zip(coverages.iter())
+        .flat_map(|(idx, cov)| {
+            let len = contigs.get_last_unit(idx as u16).unwrap() as usize;
+            let breakpoints = breakpoints(initial_clusters, idx as u16);
+            super::create_windows_with_breakpoints(idx, len, cov, &breakpoints, params)
+        })
+        .collect()
+}
+
+/// The windows used in `cluster_reads`.
+pub fn layout_of_reads(
+    encoded_reads: &[EncodedRead],
+    initial_clusters: &[Cluster],
+    contigs: &Contigs,
+    params: &DecomposeParams,
+) -> Vec<(u16, u16, u16)> {
+    let ereads: Vec<_> = encoded_reads.iter().map(ERead::new_no_gapfill).collect();
+    let coverages = super::get_coverages(contigs, &ereads);
+    layout(contigs, &coverages, initial_clusters, params)
+}
+
+/// The layout of windows in TSV. The ranges are in units and in base pairs(half-open),
+/// and `overlap` is the number of units shared with the next window.
+pub fn to_tsv(windows: &[(u16, u16, u16)], contigs: &Contigs) -> String {
+    let mut lines = vec!["window\tcontig\tstart\tend\tstart_bp\tend_bp\toverlap".to_string()];
+    for (idx, &(contig, start, end)) in windows.iter().enumerate() {
+        let overlap = match windows.get(idx + 1) {
+            Some(&(c, s, _)) if c == contig && s < end => end - s.max(start),
+            _ => 0,
+        };
+        lines.push(format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            idx,
+            contigs.get_name_by_id(contig),
+            start,
+            end,
+            start as usize * UNIT_SIZE,
+            end as usize * UNIT_SIZE,
+            overlap
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Reconcile the labels of the reads shared by neighbouring windows.
+/// `pileups[i]` should be the entries of `windows[i]`.
+pub fn stitch(pileups: &mut [Vec<Entry>], windows: &[(u16, u16, u16)]) {
+    for i in 0..windows.len().saturating_sub(1) {
+        let ((c1, s1, e1), (c2, s2, _)) = (windows[i], windows[i + 1]);
+        if c1 != c2 || e1 <= s2 || s2 < s1 {
+            continue;
+        }
+        let (head, tail) = pileups.split_at_mut(i + 1);
+        let (shared, agreed, reconciled) = stitch_pair(&mut head[i], &mut tail[0], (s2, e1));
+        debug!(
+            "Stitch {}-{}: {} shared, {} agreed, {} reconciled",
+            i,
+            i + 1,
+            shared,
+            agreed,
+            reconciled
+        );
+    }
+}
+
+// Stitch two windows sharing the units in `(start, end)`. Return the number of shared reads,
+// the number of them with the same label after permuting the labels, and the number of
+// reconciled ones.
+fn stitch_pair(
+    prev: &mut [Entry],
+    next: &mut [Entry],
+    (start, end): (u16, u16),
+) -> (usize, usize, usize) {
+    let in_shared = |e: &Entry| {
+        e.seq.iter().any(|&(pos, _)| {
+            let unit = e.window_range.1 as usize + pos;
+            start as usize <= unit && unit < end as usize
+        })
+    };
+    let prev_index: HashMap<&str, usize> = prev
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| in_shared(e))
+        .map(|(i, e)| (e.id, i))
+        .collect();
+    let shared: Vec<(usize, usize)> = next
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| in_shared(e))
+        .filter_map(|(j, e)| prev_index.get(e.id).map(|&i| (i, j)))
+        .collect();
+    if shared.is_empty() {
+        return (0, 0, 0);
+    }
+    let cluster_num = |entries: &[Entry]| {
+        entries
+            .iter()
+            .map(|e| (e.assignment as usize + 1).max(e.posterior.len()))
+            .max()
+            .unwrap_or(0)
+    };
+    let (k_prev, k_next) = (cluster_num(prev), cluster_num(next));
+    let mut counts = vec![vec![0; k_next]; k_prev];
+    for &(i, j) in shared.iter() {
+        counts[prev[i].assignment as usize][next[j].assignment as usize] += 1;
+    }
+    let to = bipartite_matching::relabel(&counts, k_next);
+    let n = k_prev.max(k_next);
+    for e in next.iter_mut() {
+        e.assignment = to[e.assignment as usize] as u8;
+        if !e.posterior.is_empty() {
+            let mut posterior = vec![0.; n];
+            for (b, &p) in e.posterior.iter().enumerate() {
+                posterior[to[b]] = p;
+            }
+            e.posterior = posterior;
+        }
+    }
+    let (mut agreed, mut reconciled) = (0, 0);
+    for &(i, j) in shared.iter() {
+        if prev[i].assignment == next[j].assignment {
+            agreed += 1;
+            continue;
+        } else if prev[i].label.is_some() || next[j].label.is_some() {
+            continue;
+        }
+        let (p, q) = (&prev[i].posterior, &next[j].posterior);
+        let best = (0..p.len().min(q.len()))
+            .max_by(|&x, &y| (p[x] * q[x]).total_cmp(&(p[y] * q[y])))
+            .filter(|&l| p[l] * q[l] > 0.);
+        if let Some(l) = best {
+            prev[i].assignment = l as u8;
+            next[j].assignment = l as u8;
+            reconciled += 1;
+        }
+    }
+    (shared.len(), agreed, reconciled)
+}
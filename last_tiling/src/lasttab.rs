@@ -1,4 +1,6 @@
 //! LastTAB is a struct to represent an alignment record produced by `last` program.
+//! Alignments in PAF or SAM can be converted into LastTAB as well.
+use std::collections::HashMap;
 
 /// The direction of the alignment.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -79,6 +81,21 @@ impl AlignInfo {
             seqlen,
         })
     }
+    fn new(
+        seqname: &str,
+        seqstart: usize,
+        matchlen: usize,
+        direction: Strand,
+        seqlen: usize,
+    ) -> Self {
+        Self {
+            seqname: seqname.to_string(),
+            seqstart,
+            matchlen,
+            direction,
+            seqlen,
+        }
+    }
     fn seqstart_from_forward(&self) -> usize {
         match self.direction {
             Strand::Forward => self.seqstart,
@@ -175,6 +192,180 @@ impl LastTAB {
             eg2,
         })
     }
+    /// Convert a PAF record into a LastTAB record. The target sequence
+    /// would be the seq1, and the query would be the seq2, as `lastal` does.
+    /// The record should have the CIGAR string in its `cg:Z:` tag.
+    /// The score is taken from the `AS:i:` tag if exists, otherwise the number of matching bases.
+    pub fn from_paf_line(line: &str) -> Option<Self> {
+        let line: Vec<&str> = line.split('\t').collect();
+        if line.len() < 12 {
+            return None;
+        }
+        let (qname, qlen): (_, usize) = (line[0], line[1].parse().ok()?);
+        let (qstart, qend): (usize, usize) = (line[2].parse().ok()?, line[3].parse().ok()?);
+        let direction = match line[4] {
+            "+" => Strand::Forward,
+            "-" => Strand::Reverse,
+            _ => return None,
+        };
+        let (tname, tlen): (_, usize) = (line[5], line[6].parse().ok()?);
+        let (tstart, tend): (usize, usize) = (line[7].parse().ok()?, line[8].parse().ok()?);
+        let matches: u64 = line[9].parse().ok()?;
+        let tag = |name: &str| {
+            line[12..]
+                .iter()
+                .find(|t| t.starts_with(name))
+                .map(|t| &t[5..])
+        };
+        let ops = Op::from_cigar(tag("cg:Z:")?)?;
+        let score = tag("AS:i:")
+            .and_then(|x| x.parse::<i64>().ok())
+            .map(|x| x.max(0) as u64)
+            .unwrap_or(matches);
+        Self::from_ops(
+            (tname, tstart, tend, tlen),
+            (qname, qstart, qend, qlen),
+            direction,
+            &ops,
+            score,
+        )
+    }
+    /// Convert an alignment in SAM's manner into a LastTAB record.
+    /// `pos` is the 0-based leftmost position in the reference, and `cigar` should
+    /// contain the clippings so that the length of the query can be recovered.
+    /// If `score` is None, the number of aligned bases would be used instead.
+    pub fn from_sam_alignment(
+        qname: &str,
+        (rname, rlen): (&str, usize),
+        pos: usize,
+        is_reverse: bool,
+        cigar: &str,
+        score: Option<u64>,
+    ) -> Option<Self> {
+        let (leading_clip, qlen) = clip_and_query_length(&parse_cigar(cigar)?);
+        let ops = Op::from_cigar(cigar)?;
+        let (refr_consumed, query_consumed, matches) =
+            ops.iter().fold((0, 0, 0), |(r, q, m), op| match *op {
+                Op::Match(l) => (r + l, q + l, m + l),
+                Op::Seq1In(l) => (r, q + l, m),
+                Op::Seq2In(l) => (r + l, q, m),
+            });
+        // The CIGAR string is along with the reverse complement if `is_reverse`.
+        let (qstart, qend, direction) = if is_reverse {
+            let qend = qlen.checked_sub(leading_clip)?;
+            (qend.checked_sub(query_consumed)?, qend, Strand::Reverse)
+        } else {
+            let qstart = leading_clip;
+            (qstart, qstart + query_consumed, Strand::Forward)
+        };
+        let score = score.unwrap_or(matches as u64);
+        let refr = (rname, pos, pos + refr_consumed, rlen);
+        Self::from_ops(refr, (qname, qstart, qend, qlen), direction, &ops, score)
+    }
+    /// Convert the `SA:Z:` tag of a SAM record into LastTAB records.
+    /// Each supplementary alignment would be a separate record.
+    /// Alignments to references not in `ref_lengths` are discarded.
+    pub fn from_sa_tag(qname: &str, sa: &str, ref_lengths: &HashMap<String, usize>) -> Vec<Self> {
+        sa.split(';')
+            .filter(|e| !e.is_empty())
+            .filter_map(|supplementary| {
+                // rname,pos,strand,CIGAR,mapQ,NM
+                let fields: Vec<_> = supplementary.split(',').collect();
+                if fields.len() < 4 {
+                    return None;
+                }
+                let rname = fields[0];
+                let rlen = *ref_lengths.get(rname)?;
+                // 1-based to 0-based.
+                let pos = fields[1].parse::<usize>().ok()?.checked_sub(1)?;
+                let is_reverse = fields[2] == "-";
+                let refr = (rname, rlen);
+                Self::from_sam_alignment(qname, refr, pos, is_reverse, fields[3], None)
+            })
+            .collect()
+    }
+    /// Convert a line of SAM into LastTAB records. `ref_lengths` is the
+    /// length of each reference, which should be taken from the `@SQ` header lines.
+    /// Unmapped and secondary alignments are discarded,
+    /// while supplementary alignments in the `SA:Z:` tag are converted into separate records.
+    /// Thus, the same alignment can appear twice if the SAM file contains both
+    /// the supplementary records and the `SA:Z:` tags.
+    pub fn from_sam_line(line: &str, ref_lengths: &HashMap<String, usize>) -> Vec<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if line.starts_with('@') || fields.len() < 11 {
+            return vec![];
+        }
+        let flag: u16 = match fields[1].parse() {
+            Ok(res) => res,
+            Err(_) => return vec![],
+        };
+        let (qname, rname, cigar) = (fields[0], fields[2], fields[5]);
+        let is_unmapped = flag & 0x4 != 0 || cigar == "*";
+        let is_secondary = flag & 0x100 != 0;
+        if is_unmapped || is_secondary {
+            return vec![];
+        }
+        let tag = |name: &str| {
+            fields[11..]
+                .iter()
+                .find(|t| t.starts_with(name))
+                .map(|t| &t[5..])
+        };
+        let mut result = vec![];
+        let rlen = ref_lengths.get(rname).copied();
+        let pos = fields[3]
+            .parse::<usize>()
+            .ok()
+            .and_then(|p| p.checked_sub(1));
+        if let (Some(rlen), Some(pos)) = (rlen, pos) {
+            let score = tag("AS:i:").and_then(|x| x.parse::<i64>().ok());
+            let score = score.map(|x| x.max(0) as u64);
+            let is_reverse = flag & 0x10 != 0;
+            let refr = (rname, rlen);
+            let aln = Self::from_sam_alignment(qname, refr, pos, is_reverse, cigar, score);
+            result.extend(aln);
+        }
+        if let Some(sa) = tag("SA:Z:") {
+            result.extend(Self::from_sa_tag(qname, sa, ref_lengths));
+        }
+        result
+    }
+    /// Construct a LastTAB from the coordinates of the reference(seq1) and the query(seq2).
+    /// Both coordinates should be in the forward strand of each sequence, and
+    /// `ops` should be ordered along the forward strand of the reference.
+    /// If the consumed lengths of `ops` do not match the coordinates, return None.
+    pub fn from_ops(
+        (rname, rstart, rend, rlen): (&str, usize, usize, usize),
+        (qname, qstart, qend, qlen): (&str, usize, usize, usize),
+        direction: Strand,
+        ops: &[Op],
+        score: u64,
+    ) -> Option<Self> {
+        let (refr_consumed, query_consumed) = ops.iter().fold((0, 0), |(r, q), op| match *op {
+            Op::Match(l) => (r + l, q + l),
+            Op::Seq1In(l) => (r, q + l),
+            Op::Seq2In(l) => (r + l, q),
+        });
+        if refr_consumed != rend - rstart || query_consumed != qend - qstart {
+            return None;
+        }
+        let seq1_information = AlignInfo::new(rname, rstart, rend - rstart, Strand::Forward, rlen);
+        // In the reverse strand, the start position is the one in the reverse complement.
+        let seq2_start = match direction {
+            Strand::Forward => qstart,
+            Strand::Reverse => qlen - qend,
+        };
+        let seq2_information = AlignInfo::new(qname, seq2_start, qend - qstart, direction, qlen);
+        let alignment = Op::to_alignment_string(ops);
+        Some(Self {
+            seq1_information,
+            seq2_information,
+            score,
+            alignment,
+            eg2: 2.,
+            e: 3.,
+        })
+    }
     pub fn score(&self) -> u64 {
         self.score
     }
@@ -271,6 +462,53 @@ impl std::fmt::Display for Op {
 }
 
 impl Op {
+    /// Parse a CIGAR string. `M`, `=`, and `X` are converted into matches,
+    /// `I` into `Seq1In`(insertion to the reference), and `D` and `N` into `Seq2In`.
+    /// Clippings and paddings are skipped.
+    pub fn from_cigar(cigar: &str) -> Option<Vec<Op>> {
+        let mut ops: Vec<Op> = vec![];
+        for (c, num) in parse_cigar(cigar)? {
+            let op = match c {
+                'M' | '=' | 'X' => Op::Match(num),
+                'I' => Op::Seq1In(num),
+                'D' | 'N' => Op::Seq2In(num),
+                'S' | 'H' | 'P' => continue,
+                _ => return None,
+            };
+            // Merge consecutive operations of the same kind, such as 10=1X10=.
+            match (ops.last_mut(), op) {
+                (Some(Op::Match(l)), Op::Match(m)) => *l += m,
+                (Some(Op::Seq1In(l)), Op::Seq1In(m)) => *l += m,
+                (Some(Op::Seq2In(l)), Op::Seq2In(m)) => *l += m,
+                _ => ops.push(op),
+            }
+        }
+        Some(ops)
+    }
+    /// Convert operations into the alignment column of LAST's TAB format,
+    /// such as `10,1:0,5`. This is the inverse of `LastTAB::alignment()`.
+    pub fn to_alignment_string(ops: &[Op]) -> String {
+        let mut blocks: Vec<String> = vec![];
+        let (mut seq1_gap, mut seq2_gap) = (0, 0);
+        for op in ops {
+            match *op {
+                Op::Match(l) => {
+                    if seq1_gap != 0 || seq2_gap != 0 {
+                        blocks.push(format!("{}:{}", seq1_gap, seq2_gap));
+                        seq1_gap = 0;
+                        seq2_gap = 0;
+                    }
+                    blocks.push(format!("{}", l));
+                }
+                Op::Seq2In(l) => seq1_gap += l,
+                Op::Seq1In(l) => seq2_gap += l,
+            }
+        }
+        if seq1_gap != 0 || seq2_gap != 0 {
+            blocks.push(format!("{}:{}", seq1_gap, seq2_gap));
+        }
+        blocks.join(",")
+    }
     fn from_string(res: &mut Vec<Op>, input: &str) {
         if input.contains(':') {
             let mut input = input.split(':');
@@ -288,6 +526,40 @@ impl Op {
     }
 }
 
+// Split a CIGAR string into (operation, length) pairs.
+fn parse_cigar(cigar: &str) -> Option<Vec<(char, usize)>> {
+    let mut result = vec![];
+    let mut num = None;
+    for c in cigar.chars() {
+        match c.to_digit(10) {
+            Some(d) => num = Some(num.unwrap_or(0) * 10 + d as usize),
+            None => {
+                result.push((c, num?));
+                num = None;
+            }
+        }
+    }
+    match num {
+        Some(_) => None,
+        None => Some(result),
+    }
+}
+
+// Return the length of the leading clip and the length of the query(including hard clips).
+fn clip_and_query_length(cigar: &[(char, usize)]) -> (usize, usize) {
+    let leading_clip = cigar
+        .iter()
+        .take_while(|&&(c, _)| c == 'S' || c == 'H')
+        .map(|x| x.1)
+        .sum::<usize>();
+    let query_length = cigar
+        .iter()
+        .filter(|&&(c, _)| matches!(c, 'M' | 'I' | 'S' | '=' | 'X' | 'H'))
+        .map(|x| x.1)
+        .sum::<usize>();
+    (leading_clip, query_length)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -329,4 +601,66 @@ mod tests {
         assert_eq!(aln.seq2_start_from_forward(), 1125 - 527 - 4);
         assert_eq!(aln.seq2_end_from_forward(), 1125 - 4);
     }
+    const PAF_INPUT:&str = "read1\t100\t5\t33\t-\ttig00000001\t1000\t200\t226\t22\t28\t60\tNM:i:4\tAS:i:30\tcg:Z:10M1D5M5I3M2D5M";
+    #[test]
+    fn paf_parse_test() {
+        let aln = LastTAB::from_paf_line(PAF_INPUT).unwrap();
+        assert_eq!(aln.score(), 30);
+        assert_eq!(aln.seq1_name(), "tig00000001");
+        assert_eq!(aln.seq1_start(), 200);
+        assert_eq!(aln.seq1_matchlen(), 26);
+        assert_eq!(aln.seq1_direction(), Strand::Forward);
+        assert_eq!(aln.seq2_name(), "read1");
+        assert_eq!(aln.seq2_start(), 100 - 33);
+        assert_eq!(aln.seq2_matchlen(), 28);
+        assert_eq!(aln.seq2_direction(), Strand::Reverse);
+        assert_eq!(aln.seq2_start_from_forward(), 5);
+        assert_eq!(aln.seq2_end_from_forward(), 33);
+        use Op::*;
+        assert_eq!(
+            aln.alignment(),
+            vec![
+                Match(10),
+                Seq2In(1),
+                Match(5),
+                Seq1In(5),
+                Match(3),
+                Seq2In(2),
+                Match(5)
+            ]
+        );
+    }
+    #[test]
+    fn sam_parse_test() {
+        let mut ref_lengths = HashMap::new();
+        ref_lengths.insert("tig00000001".to_string(), 1000);
+        let sam = "read1\t16\ttig00000001\t201\t60\t5H10M1D5M5I3M2D5M62S\t*\t0\t0\t*\t*\tAS:i:30\tSA:Z:tig00000001,501,+,60S30M10S,60,0;";
+        let alns = LastTAB::from_sam_line(sam, &ref_lengths);
+        assert_eq!(alns.len(), 2);
+        let aln = &alns[0];
+        assert_eq!(aln.score(), 30);
+        assert_eq!(aln.seq1_start(), 200);
+        assert_eq!(aln.seq1_matchlen(), 26);
+        assert_eq!(aln.seq2_len(), 95);
+        assert_eq!(aln.seq2_direction(), Strand::Reverse);
+        assert_eq!(aln.seq2_start(), 5);
+        assert_eq!(aln.seq2_matchlen(), 28);
+        assert_eq!(aln.seq2_start_from_forward(), 95 - 5 - 28);
+        let aln = &alns[1];
+        assert_eq!(aln.seq1_start(), 500);
+        assert_eq!(aln.seq2_len(), 100);
+        assert_eq!(aln.seq2_start(), 60);
+        assert_eq!(aln.seq2_direction(), Strand::Forward);
+        assert_eq!(aln.alignment(), vec![Op::Match(30)]);
+    }
+    #[test]
+    fn alignment_string_test() {
+        let aln = LastTAB::from_line(LAST_INPUT).unwrap();
+        let ops = aln.alignment();
+        assert_eq!(Op::to_alignment_string(&ops), "10,1:0,5,1:0,3,1:0,5,0:1,3");
+        use Op::*;
+        let ops = Op::from_cigar("3S4=1X2=2I1D3M5H").unwrap();
+        assert_eq!(ops, vec![Match(7), Seq1In(2), Seq2In(1), Match(3)]);
+        assert_eq!(Op::to_alignment_string(&ops), "7,1:2,3");
+    }
 }
@@ -11,6 +11,7 @@ extern crate bio_utils;
 extern crate rayon;
 pub mod contig;
 pub mod lasttab;
+pub mod mapper;
 pub mod repeat;
 pub mod unit;
 use bio_utils::fasta;
@@ -76,6 +77,125 @@ pub fn parse_tab_file<P: AsRef<Path>>(tab_file: P) -> std::io::Result<Vec<LastTA
         .collect())
 }
 
+/// The function to parse PAF format with CIGAR strings(`cg:Z:` tag) into LastTAB.
+/// Records without CIGAR strings are discarded.
+pub fn parse_paf_file<P: AsRef<Path>>(paf_file: P) -> std::io::Result<Vec<LastTAB>> {
+    let lines = std::fs::read_to_string(paf_file)?;
+    Ok(lines.lines().filter_map(LastTAB::from_paf_line).collect())
+}
+
+/// The function to parse SAM format into LastTAB. The lengths of the references
+/// are taken from the `@SQ` header lines. Supplementary alignments are
+/// converted into separate records, whether they are in their own lines or in `SA:Z:` tags.
+pub fn parse_sam_file<P: AsRef<Path>>(sam_file: P) -> std::io::Result<Vec<LastTAB>> {
+    let lines = std::fs::read_to_string(sam_file)?;
+    let ref_lengths: HashMap<String, usize> = lines
+        .lines()
+        .take_while(|line| line.starts_with('@'))
+        .filter(|line| line.starts_with("@SQ"))
+        .filter_map(|line| {
+            let name = line.split('\t').find(|e| e.starts_with("SN:"))?;
+            let len = line.split('\t').find(|e| e.starts_with("LN:"))?;
+            Some((name[3..].to_string(), len[3..].parse().ok()?))
+        })
+        .collect();
+    let alignments: Vec<_> = lines
+        .lines()
+        .flat_map(|line| LastTAB::from_sam_line(line, &ref_lengths))
+        .collect();
+    Ok(dedup_alignments(alignments))
+}
+
+/// The function to parse BAM format into LastTAB. See `parse_sam_file` for details.
+/// Available with the `bam` feature.
+#[cfg(feature = "bam")]
+pub fn parse_bam_file<P: AsRef<Path>>(bam_file: P) -> std::io::Result<Vec<LastTAB>> {
+    use rust_htslib::bam::{self, record::Aux, Read};
+    let to_io_error = |e: rust_htslib::errors::Error| {
+        std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+    };
+    let mut reader = bam::Reader::from_path(bam_file).map_err(to_io_error)?;
+    let header = reader.header().clone();
+    let references: Vec<(String, usize)> = (0..header.target_count())
+        .map(|tid| {
+            let name = String::from_utf8_lossy(header.tid2name(tid)).to_string();
+            let len = header.target_len(tid).unwrap_or(0) as usize;
+            (name, len)
+        })
+        .collect();
+    let ref_lengths: HashMap<String, usize> = references.iter().cloned().collect();
+    let mut alignments = vec![];
+    for record in reader.records() {
+        let record = record.map_err(to_io_error)?;
+        if record.is_unmapped() || record.is_secondary() || record.tid() < 0 {
+            continue;
+        }
+        let qname = String::from_utf8_lossy(record.qname()).to_string();
+        let (rname, rlen) = &references[record.tid() as usize];
+        let cigar = format!("{}", record.cigar());
+        let score = match record.aux(b"AS") {
+            Ok(Aux::I8(x)) => Some(x.max(0) as u64),
+            Ok(Aux::U8(x)) => Some(x as u64),
+            Ok(Aux::I16(x)) => Some(x.max(0) as u64),
+            Ok(Aux::U16(x)) => Some(x as u64),
+            Ok(Aux::I32(x)) => Some(x.max(0) as u64),
+            Ok(Aux::U32(x)) => Some(x as u64),
+            _ => None,
+        };
+        let (pos, is_reverse) = (record.pos() as usize, record.is_reverse());
+        let refr = (rname.as_str(), *rlen);
+        alignments.extend(LastTAB::from_sam_alignment(
+            &qname, refr, pos, is_reverse, &cigar, score,
+        ));
+        if let Ok(Aux::String(sa)) = record.aux(b"SA") {
+            alignments.extend(LastTAB::from_sa_tag(&qname, sa, &ref_lengths));
+        }
+    }
+    Ok(dedup_alignments(alignments))
+}
+
+#[cfg(not(feature = "bam"))]
+pub fn parse_bam_file<P: AsRef<Path>>(_bam_file: P) -> std::io::Result<Vec<LastTAB>> {
+    let msg = "BAM files are not supported. Build last_tiling with the `bam` feature.";
+    Err(std::io::Error::new(std::io::ErrorKind::Other, msg))
+}
+
+// Remove alignments appearing twice, both as a supplementary record and in a `SA:Z:` tag.
+fn dedup_alignments(alignments: Vec<LastTAB>) -> Vec<LastTAB> {
+    let mut registered = std::collections::HashSet::new();
+    alignments
+        .into_iter()
+        .filter(|aln| {
+            let key = (
+                aln.seq1_name().to_string(),
+                aln.seq1_start_from_forward(),
+                aln.seq1_end_from_forward(),
+                aln.seq2_name().to_string(),
+                aln.seq2_start_from_forward(),
+                aln.seq2_end_from_forward(),
+            );
+            registered.insert(key)
+        })
+        .collect()
+}
+
+/// Parse an alignment file. The format is determined by its extension:
+/// `.paf` is treated as PAF, `.sam` as SAM, `.bam` as BAM,
+/// and others as LAST's TAB format.
+pub fn parse_alignment_file<P: AsRef<Path>>(file: P) -> std::io::Result<Vec<LastTAB>> {
+    let extension = file
+        .as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| e.as_str()) {
+        Some("paf") => parse_paf_file(file),
+        Some("sam") => parse_sam_file(file),
+        Some("bam") => parse_bam_file(file),
+        _ => parse_tab_file(file),
+    }
+}
+
 pub fn remove_repeats(alns: Vec<LastTAB>, defs: &Contigs, rep: &[RepeatPairs]) -> Vec<LastTAB> {
     let repeats: HashMap<u16, Vec<(usize, usize)>> =
         rep.iter().fold(HashMap::new(), |mut map, reps| {
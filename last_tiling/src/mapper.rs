@@ -0,0 +1,654 @@
+//! A tiny seed-chain-extend aligner producing LastTAB records.
+//! It is intended to replace `lastal | last-split | maf-convert` when LAST is not available.
+//! The procedure is as follows:
+//! 1. Index the minimizers of the references(forward strand only).
+//! 2. Collect the anchors of a read and its reverse complement, and chain them colinearly.
+//! 3. Fill the gaps between anchors by banded affine-gap alignment, and
+//!    extend the both ends of the chain.
+//! 4. If `split` is true, select a set of alignments covering the read with little overlaps,
+//!    as `last-split` does. Otherwise, all the alignments are reported, as `lastal` does.
+use super::lasttab::{LastTAB, Op, Strand};
+use bio_utils::fasta;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Parameters of the aligner.
+#[derive(Debug, Clone)]
+pub struct MapperConfig {
+    /// K-mer size of minimizers. Should be less than 32.
+    pub k: usize,
+    /// Window size of minimizers.
+    pub w: usize,
+    /// Minimizers occurring more than this are discarded.
+    pub max_occ: usize,
+    /// Maximum distance between two adjacent anchors in a chain.
+    pub max_gap: usize,
+    /// Maximum difference of diagonals between two adjacent anchors in a chain.
+    pub chain_band: usize,
+    /// The number of anchors looked back in chaining.
+    pub lookback: usize,
+    /// Minimum chaining score to be aligned.
+    pub min_chain_score: f64,
+    /// Band width of the gap filling and extension.
+    pub band: usize,
+    /// Maximum length of the extension at each end.
+    pub max_extension: usize,
+    pub match_score: i32,
+    pub mismatch: i32,
+    pub gap_open: i32,
+    pub gap_extend: i32,
+    /// Minimum length of the alignment in the reference.
+    pub min_aln_len: usize,
+    /// If true, select alignments covering the read with little overlaps.
+    pub split: bool,
+}
+
+impl std::default::Default for MapperConfig {
+    fn default() -> Self {
+        Self {
+            k: 15,
+            w: 10,
+            max_occ: 100,
+            max_gap: 5_000,
+            chain_band: 500,
+            lookback: 50,
+            min_chain_score: 40.,
+            band: 50,
+            max_extension: 1_000,
+            match_score: 2,
+            mismatch: -4,
+            gap_open: 4,
+            gap_extend: 2,
+            min_aln_len: 500,
+            split: true,
+        }
+    }
+}
+
+impl MapperConfig {
+    /// The parameters for self-vs-self alignments of references. All the
+    /// alignments are reported, so that repeats can be detected.
+    pub fn self_alignment() -> Self {
+        Self {
+            split: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// An index of the references.
+#[derive(Debug, Clone)]
+pub struct Mapper {
+    config: MapperConfig,
+    references: Vec<(String, Vec<u8>)>,
+    // Minimizer -> (Reference ID, Position)
+    index: HashMap<u64, Vec<(u16, u32)>>,
+}
+
+// An exact k-mer match. (reference id, position at the reference, position at the query).
+type Anchor = (u16, usize, usize);
+
+impl Mapper {
+    pub fn new(references: &[fasta::Record], config: MapperConfig) -> Self {
+        let references: Vec<_> = references
+            .iter()
+            .map(|r| (r.id().to_string(), r.seq().to_ascii_uppercase()))
+            .collect();
+        let mut index: HashMap<u64, Vec<(u16, u32)>> = HashMap::new();
+        for (id, (_, seq)) in references.iter().enumerate() {
+            for (hash, pos) in minimizers(seq, config.k, config.w) {
+                index.entry(hash).or_default().push((id as u16, pos as u32));
+            }
+        }
+        index.retain(|_, occs| occs.len() <= config.max_occ);
+        debug!("Indexed {} minimizers.", index.len());
+        Self {
+            config,
+            references,
+            index,
+        }
+    }
+    /// Map reads in parallel.
+    pub fn map_reads(&self, reads: &[fasta::Record]) -> Vec<LastTAB> {
+        reads.par_iter().flat_map(|read| self.map(read)).collect()
+    }
+    /// Map a read to the references.
+    pub fn map(&self, read: &fasta::Record) -> Vec<LastTAB> {
+        let forward = read.seq().to_ascii_uppercase();
+        let reverse = revcmp(&forward);
+        let alignments: Vec<_> = vec![(Strand::Forward, &forward), (Strand::Reverse, &reverse)]
+            .into_iter()
+            .flat_map(|(strand, query)| {
+                let anchors = self.collect_anchors(query);
+                chain_anchors(anchors, &self.config)
+                    .into_iter()
+                    .filter_map(|chain| self.align_chain(&chain, query))
+                    .map(|aln| (strand, aln))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|(_, aln)| aln.refr_end - aln.refr_start >= self.config.min_aln_len)
+            .collect();
+        let qlen = forward.len();
+        let alignments = if self.config.split {
+            select_split(alignments, qlen)
+        } else {
+            alignments
+        };
+        alignments
+            .into_iter()
+            .filter_map(|(strand, aln)| {
+                let (rname, rseq) = &self.references[aln.ref_id as usize];
+                let refr = (rname.as_str(), aln.refr_start, aln.refr_end, rseq.len());
+                let (qstart, qend) = forward_coordinate(strand, &aln, qlen);
+                let query = (read.id(), qstart, qend, qlen);
+                let score = aln.score.max(0) as u64;
+                LastTAB::from_ops(refr, query, strand, &aln.ops, score)
+            })
+            .collect()
+    }
+    fn collect_anchors(&self, query: &[u8]) -> Vec<Anchor> {
+        let mut anchors: Vec<Anchor> = minimizers(query, self.config.k, self.config.w)
+            .into_iter()
+            .filter_map(|(hash, qpos)| self.index.get(&hash).map(|occs| (occs, qpos)))
+            .flat_map(|(occs, qpos)| {
+                occs.iter()
+                    .map(move |&(id, rpos)| (id, rpos as usize, qpos))
+            })
+            .collect();
+        anchors.sort();
+        anchors
+    }
+    fn align_chain(&self, chain: &[Anchor], query: &[u8]) -> Option<ChainAlignment> {
+        let c = &self.config;
+        let &(ref_id, r0, q0) = chain.first()?;
+        let refr = &self.references[ref_id as usize].1;
+        // Extend to the upstream.
+        let ext_q = q0.min(c.max_extension);
+        let ext_r = r0.min(ext_q + ext_q / 4 + c.band);
+        let rev_r: Vec<_> = refr[r0 - ext_r..r0].iter().rev().copied().collect();
+        let rev_q: Vec<_> = query[q0 - ext_q..q0].iter().rev().copied().collect();
+        let head = banded_alignment(&rev_r, &rev_q, c, Mode::Extension);
+        let (mut ops, mut score) = (head.ops, head.score);
+        ops.reverse();
+        let (refr_start, query_start) = (r0 - head.refr_len, q0 - head.query_len);
+        // Fill the gaps between anchors.
+        for w in chain.windows(2) {
+            let (_, r1, q1) = w[0];
+            let (_, r2, q2) = w[1];
+            let gap = banded_alignment(&refr[r1..r2], &query[q1..q2], c, Mode::Global);
+            score = score.saturating_add(gap.score);
+            push_ops(&mut ops, &gap.ops);
+        }
+        // The last anchor is an exact match.
+        let &(_, rl, ql) = chain.last()?;
+        push_ops(&mut ops, &[Op::Match(c.k)]);
+        score = score.saturating_add(c.match_score * c.k as i32);
+        let (rl, ql) = (rl + c.k, ql + c.k);
+        // Extend to the downstream.
+        let ext_q = (query.len() - ql).min(c.max_extension);
+        let ext_r = (refr.len() - rl).min(ext_q + ext_q / 4 + c.band);
+        let tail = banded_alignment(
+            &refr[rl..rl + ext_r],
+            &query[ql..ql + ext_q],
+            c,
+            Mode::Extension,
+        );
+        score = score.saturating_add(tail.score);
+        push_ops(&mut ops, &tail.ops);
+        Some(ChainAlignment {
+            ref_id,
+            refr_start,
+            refr_end: rl + tail.refr_len,
+            query_start,
+            query_end: ql + tail.query_len,
+            score,
+            ops,
+        })
+    }
+}
+
+// An alignment of a chain. The query coordinate is the one of the aligned strand.
+#[derive(Debug, Clone)]
+struct ChainAlignment {
+    ref_id: u16,
+    refr_start: usize,
+    refr_end: usize,
+    query_start: usize,
+    query_end: usize,
+    score: i32,
+    ops: Vec<Op>,
+}
+
+fn forward_coordinate(strand: Strand, aln: &ChainAlignment, qlen: usize) -> (usize, usize) {
+    match strand {
+        Strand::Forward => (aln.query_start, aln.query_end),
+        Strand::Reverse => (qlen - aln.query_end, qlen - aln.query_start),
+    }
+}
+
+// Select alignments as `last-split` does. Alignments are taken greedily
+// from the highest score, unless more than half of them is already covered.
+fn select_split(
+    mut alignments: Vec<(Strand, ChainAlignment)>,
+    qlen: usize,
+) -> Vec<(Strand, ChainAlignment)> {
+    alignments.sort_by_key(|(_, aln)| std::cmp::Reverse(aln.score));
+    let mut selected: Vec<(Strand, ChainAlignment)> = vec![];
+    for (strand, aln) in alignments {
+        let (start, end) = forward_coordinate(strand, &aln, qlen);
+        let overlap = selected
+            .iter()
+            .map(|(s, a)| {
+                let (s, e) = forward_coordinate(*s, a, qlen);
+                end.min(e).saturating_sub(start.max(s))
+            })
+            .sum::<usize>();
+        if 2 * overlap < end - start {
+            selected.push((strand, aln));
+        }
+    }
+    selected.sort_by_key(|(s, a)| forward_coordinate(*s, a, qlen).0);
+    selected
+}
+
+// Colinear chaining of anchors sorted by (reference, reference position, query position).
+// Return chains, each of which is sorted and strictly increasing in both coordinates.
+fn chain_anchors(anchors: Vec<Anchor>, c: &MapperConfig) -> Vec<Vec<Anchor>> {
+    let k = c.k as f64;
+    let mut scores: Vec<f64> = vec![k; anchors.len()];
+    let mut parents: Vec<Option<usize>> = vec![None; anchors.len()];
+    for i in 0..anchors.len() {
+        let (id_i, r_i, q_i) = anchors[i];
+        let start = i.saturating_sub(c.lookback);
+        for j in (start..i).rev() {
+            let (id_j, r_j, q_j) = anchors[j];
+            if id_i != id_j || r_i - r_j > c.max_gap {
+                break;
+            }
+            if r_i <= r_j || q_i <= q_j || q_i - q_j > c.max_gap {
+                continue;
+            }
+            let (dr, dq) = (r_i - r_j, q_i - q_j);
+            let diff = dr.max(dq) - dr.min(dq);
+            if diff > c.chain_band {
+                continue;
+            }
+            let gain = dr.min(dq).min(c.k) as f64;
+            let penalty = if diff == 0 {
+                0.
+            } else {
+                0.01 * k * diff as f64 + 0.5 * (diff as f64).log2()
+            };
+            let score = scores[j] + gain - penalty;
+            if scores[i] < score {
+                scores[i] = score;
+                parents[i] = Some(j);
+            }
+        }
+    }
+    // Backtrack from the heighest score.
+    let mut order: Vec<_> = (0..anchors.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    let mut used = vec![false; anchors.len()];
+    let mut chains = vec![];
+    for end in order {
+        if used[end] || scores[end] < c.min_chain_score {
+            continue;
+        }
+        let mut chain = vec![];
+        let mut current = Some(end);
+        while let Some(idx) = current {
+            if used[idx] {
+                break;
+            }
+            used[idx] = true;
+            chain.push(anchors[idx]);
+            current = parents[idx];
+        }
+        chain.reverse();
+        if chain.len() >= 3 {
+            chains.push(chain);
+        }
+    }
+    chains
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    // Align the both sequences entirely.
+    Global,
+    // Align from the start of the both sequences, and stop at the best scoring position.
+    Extension,
+}
+
+#[derive(Debug, Clone)]
+struct SegmentAlignment {
+    score: i32,
+    ops: Vec<Op>,
+    refr_len: usize,
+    query_len: usize,
+}
+
+const NEG: i32 = i32::MIN / 2;
+// Traceback flags.
+const H_FROM_E: u8 = 0b0001;
+const H_FROM_F: u8 = 0b0010;
+const E_FROM_H: u8 = 0b0100;
+const F_FROM_H: u8 = 0b1000;
+
+// Banded affine-gap alignment. The band is centered at the line from (0,0) to (refr.len(), query.len()).
+// In the global mode, the band is widened by the difference of the lengths, as the gap between
+// two anchors can be far from the diagonal if it has a long indel.
+// E is the state consuming the query(insertion), and F is the one consuming the reference(deletion).
+fn banded_alignment(refr: &[u8], query: &[u8], c: &MapperConfig, mode: Mode) -> SegmentAlignment {
+    let (n, m) = (refr.len(), query.len());
+    if n == 0 || m == 0 {
+        return match mode {
+            Mode::Extension => SegmentAlignment {
+                score: 0,
+                ops: vec![],
+                refr_len: 0,
+                query_len: 0,
+            },
+            Mode::Global => {
+                let gap = |l: usize| -(c.gap_open + c.gap_extend * l as i32);
+                let (mut ops, mut score) = (vec![], 0);
+                if n > 0 {
+                    ops.push(Op::Seq2In(n));
+                    score += gap(n);
+                }
+                if m > 0 {
+                    ops.push(Op::Seq1In(m));
+                    score += gap(m);
+                }
+                SegmentAlignment {
+                    score,
+                    ops,
+                    refr_len: n,
+                    query_len: m,
+                }
+            }
+        };
+    }
+    let half = match mode {
+        Mode::Global => c.band + n.max(m) - n.min(m),
+        Mode::Extension => c.band,
+    };
+    let band = |i: usize| {
+        let center = i * m / n;
+        (center.saturating_sub(half), (center + half).min(m))
+    };
+    let (oe, e) = (c.gap_open + c.gap_extend, c.gap_extend);
+    let score = |x: u8, y: u8| if x == y { c.match_score } else { c.mismatch };
+    let mut traceback: Vec<(usize, Vec<u8>)> = Vec::with_capacity(n + 1);
+    let (mut prev_lo, mut prev_h, mut prev_f): (usize, Vec<i32>, Vec<i32>) = (0, vec![], vec![]);
+    let mut best = (0, 0, 0);
+    for i in 0..=n {
+        let (lo, hi) = band(i);
+        let width = hi - lo + 1;
+        let (mut hs, mut es, mut fs) = (vec![NEG; width], vec![NEG; width], vec![NEG; width]);
+        let mut tb = vec![0u8; width];
+        let prev_hi = prev_lo + prev_h.len();
+        let in_prev = |j: usize| i > 0 && prev_lo <= j && j < prev_hi;
+        for j in lo..=hi {
+            let idx = j - lo;
+            if i == 0 && j == 0 {
+                hs[idx] = 0;
+                continue;
+            }
+            let mut flag = 0;
+            let e_score = if j > lo {
+                let (h, e_prev) = (hs[idx - 1] - oe, es[idx - 1] - e);
+                if h >= e_prev {
+                    flag |= E_FROM_H;
+                    h
+                } else {
+                    e_prev
+                }
+            } else {
+                NEG
+            };
+            let f_score = if in_prev(j) {
+                let (h, f_prev) = (prev_h[j - prev_lo] - oe, prev_f[j - prev_lo] - e);
+                if h >= f_prev {
+                    flag |= F_FROM_H;
+                    h
+                } else {
+                    f_prev
+                }
+            } else {
+                NEG
+            };
+            let d_score = if j > 0 && in_prev(j - 1) {
+                prev_h[j - 1 - prev_lo] + score(refr[i - 1], query[j - 1])
+            } else {
+                NEG
+            };
+            let h_score = if d_score >= e_score && d_score >= f_score {
+                d_score
+            } else if e_score >= f_score {
+                flag |= H_FROM_E;
+                e_score
+            } else {
+                flag |= H_FROM_F;
+                f_score
+            };
+            hs[idx] = h_score;
+            es[idx] = e_score;
+            fs[idx] = f_score;
+            tb[idx] = flag;
+            if mode == Mode::Extension && best.0 < h_score {
+                best = (h_score, i, j);
+            }
+        }
+        traceback.push((lo, tb));
+        prev_lo = lo;
+        prev_h = hs;
+        prev_f = fs;
+    }
+    let (score, end_i, end_j) = match mode {
+        Mode::Global => (prev_h[m - prev_lo], n, m),
+        Mode::Extension => best,
+    };
+    // Traceback. 0:H, 1:E, 2:F.
+    let (mut i, mut j, mut state) = (end_i, end_j, 0);
+    let mut ops = vec![];
+    while i > 0 && j > 0 {
+        let (lo, ref tb) = traceback[i];
+        let flag = tb[j - lo];
+        match state {
+            0 if flag & H_FROM_E != 0 => state = 1,
+            0 if flag & H_FROM_F != 0 => state = 2,
+            0 => {
+                ops.push(Op::Match(1));
+                i -= 1;
+                j -= 1;
+            }
+            1 => {
+                ops.push(Op::Seq1In(1));
+                state = if flag & E_FROM_H != 0 { 0 } else { 1 };
+                j -= 1;
+            }
+            _ => {
+                ops.push(Op::Seq2In(1));
+                state = if flag & F_FROM_H != 0 { 0 } else { 2 };
+                i -= 1;
+            }
+        }
+    }
+    if i > 0 {
+        ops.push(Op::Seq2In(i));
+    }
+    if j > 0 {
+        ops.push(Op::Seq1In(j));
+    }
+    ops.reverse();
+    let mut merged = vec![];
+    push_ops(&mut merged, &ops);
+    SegmentAlignment {
+        score,
+        ops: merged,
+        refr_len: end_i,
+        query_len: end_j,
+    }
+}
+
+// Append operations, merging the same kind of operations.
+fn push_ops(ops: &mut Vec<Op>, new_ops: &[Op]) {
+    for &op in new_ops {
+        match (ops.last_mut(), op) {
+            (Some(Op::Match(l)), Op::Match(m)) => *l += m,
+            (Some(Op::Seq1In(l)), Op::Seq1In(m)) => *l += m,
+            (Some(Op::Seq2In(l)), Op::Seq2In(m)) => *l += m,
+            (_, Op::Match(0)) | (_, Op::Seq1In(0)) | (_, Op::Seq2In(0)) => {}
+            _ => ops.push(op),
+        }
+    }
+}
+
+// Invertible hash function of k-mers, which is the same as minimap2.
+fn hash64(key: u64, mask: u64) -> u64 {
+    let mut key = (!key).wrapping_add(key << 21) & mask;
+    key ^= key >> 24;
+    key = (key.wrapping_add(key << 3)).wrapping_add(key << 8) & mask;
+    key ^= key >> 14;
+    key = (key.wrapping_add(key << 2)).wrapping_add(key << 4) & mask;
+    key ^= key >> 28;
+    key.wrapping_add(key << 31) & mask
+}
+
+// (hash, start position) of (w,k)-minimizers. K-mers containing non-ACGT bases are skipped.
+fn minimizers(seq: &[u8], k: usize, w: usize) -> Vec<(u64, usize)> {
+    if seq.len() < k {
+        return vec![];
+    }
+    let mask = (1u64 << (2 * k)) - 1;
+    let mut kmers: Vec<Option<u64>> = vec![None; seq.len() - k + 1];
+    let (mut code, mut valid) = (0u64, 0);
+    for (i, base) in seq.iter().enumerate() {
+        let bits = match base {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => {
+                valid = 0;
+                continue;
+            }
+        };
+        code = ((code << 2) | bits) & mask;
+        valid += 1;
+        if valid >= k {
+            kmers[i + 1 - k] = Some(hash64(code, mask));
+        }
+    }
+    let mut result: Vec<(u64, usize)> = vec![];
+    for start in 0..kmers.len().saturating_sub(w - 1) {
+        let min = kmers[start..start + w]
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, h)| h.map(|h| (h, start + idx)))
+            .min();
+        if let Some(min) = min {
+            if result.last() != Some(&min) {
+                result.push(min);
+            }
+        }
+    }
+    result
+}
+
+fn revcmp(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|&e| match e {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // A linear congruential generator, to avoid dependency on rand.
+    fn gen_seq(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                b"ACGT"[(x >> 33) as usize % 4]
+            })
+            .collect()
+    }
+    fn consumed(ops: &[Op]) -> (usize, usize) {
+        ops.iter().fold((0, 0), |(r, q), op| match *op {
+            Op::Match(l) => (r + l, q + l),
+            Op::Seq1In(l) => (r, q + l),
+            Op::Seq2In(l) => (r + l, q),
+        })
+    }
+    #[test]
+    fn banded_alignment_test() {
+        let c = MapperConfig::default();
+        let refr = gen_seq(300, 1);
+        let mut query = refr.clone();
+        query.remove(100);
+        query.insert(200, b'A');
+        query.insert(200, b'A');
+        let aln = banded_alignment(&refr, &query, &c, Mode::Global);
+        assert_eq!(consumed(&aln.ops), (300, 301));
+        assert_eq!((aln.refr_len, aln.query_len), (300, 301));
+        let aln = banded_alignment(&refr[..150], &query[..200], &c, Mode::Extension);
+        assert_eq!(consumed(&aln.ops), (aln.refr_len, aln.query_len));
+        assert!(aln.refr_len >= 140);
+    }
+    #[test]
+    fn long_indel_test() {
+        let c = MapperConfig::default();
+        let refr = gen_seq(400, 2);
+        // A deletion longer than the band.
+        let mut query = refr[..200].to_vec();
+        query.extend_from_slice(&refr[320..]);
+        let aln = banded_alignment(&refr, &query, &c, Mode::Global);
+        assert_eq!(consumed(&aln.ops), (400, 280));
+        assert_eq!(
+            aln.ops,
+            vec![Op::Match(200), Op::Seq2In(120), Op::Match(80)]
+        );
+        // An insertion so long that the bands of adjacent rows would not overlap.
+        let refr = gen_seq(10, 3);
+        let mut query = refr[..5].to_vec();
+        query.extend(gen_seq(190, 4));
+        query.extend_from_slice(&refr[5..]);
+        let aln = banded_alignment(&refr, &query, &c, Mode::Global);
+        assert_eq!(consumed(&aln.ops), (10, 200));
+        let gap = c.gap_open + c.gap_extend * 190;
+        assert!(aln.score >= 10 * c.match_score - gap);
+    }
+    #[test]
+    fn mapping_test() {
+        let refr = gen_seq(20_000, 10);
+        let reference = vec![fasta::Record::with_data("ref", &None, &refr)];
+        let mapper = Mapper::new(&reference, MapperConfig::default());
+        // A chimeric read, joining 2000..5000 and the reverse complement of 12000..15000.
+        let mut read = refr[2_000..5_000].to_vec();
+        read.extend(revcmp(&refr[12_000..15_000]));
+        let read = fasta::Record::with_data("read", &None, &read);
+        let alns = mapper.map(&read);
+        // Extensions may go over the junction by a few bases.
+        let near = |x: usize, y: usize| (x as i64 - y as i64).abs() < 10;
+        assert_eq!(alns.len(), 2);
+        assert!(near(alns[0].seq1_start(), 2_000));
+        assert!(near(alns[0].seq1_end_from_forward(), 5_000));
+        assert_eq!(alns[0].seq2_direction(), Strand::Forward);
+        assert!(near(alns[0].seq2_start_from_forward(), 0));
+        assert!(near(alns[1].seq1_start(), 12_000));
+        assert!(near(alns[1].seq1_end_from_forward(), 15_000));
+        assert_eq!(alns[1].seq2_direction(), Strand::Reverse);
+        assert!(near(alns[1].seq2_start_from_forward(), 3_000));
+    }
+}
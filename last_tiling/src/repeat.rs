@@ -3,6 +3,9 @@
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RepeatPairs {
     reps: [Repeat; 2],
+    // The fraction of identical bases in the alignment between the copies.
+    #[serde(default)]
+    identity: f64,
 }
 
 /// A repeat. Note that the id should be consistent with other data such as contigs.
@@ -39,11 +42,15 @@ impl RepeatPairs {
                 is_forward: aln.seq2_direction().is_forward(),
             },
         ];
-        Some(Self { reps })
+        let identity = identity(aln, contigs).unwrap_or(0.);
+        Some(Self { reps, identity })
     }
     pub fn inner(&self) -> &[Repeat] {
         &self.reps
     }
+    pub fn identity(&self) -> f64 {
+        self.identity
+    }
     pub fn len(&self) -> usize {
         self.reps.len()
     }
@@ -52,6 +59,45 @@ impl RepeatPairs {
     }
 }
 
+// The fraction of identical bases in the alignment. None if the alignment is out of the contigs.
+fn identity(aln: &super::LastTAB, contigs: &super::Contigs) -> Option<f64> {
+    let get = |name, is_forward: bool| {
+        if is_forward {
+            contigs.get(name)
+        } else {
+            contigs.get_revcmp(name)
+        }
+    };
+    let seq1 = get(aln.seq1_name(), aln.seq1_direction().is_forward())?;
+    let seq2 = get(aln.seq2_name(), aln.seq2_direction().is_forward())?;
+    let (mut i, mut j) = (aln.seq1_start(), aln.seq2_start());
+    let (mut matches, mut total) = (0, 0);
+    for op in aln.alignment() {
+        match op {
+            super::lasttab::Op::Match(l) => {
+                let (xs, ys) = (seq1.get(i..i + l)?, seq2.get(j..j + l)?);
+                matches += xs
+                    .iter()
+                    .zip(ys)
+                    .filter(|(x, y)| x.eq_ignore_ascii_case(y))
+                    .count();
+                i += l;
+                j += l;
+                total += l;
+            }
+            super::lasttab::Op::Seq1In(l) => {
+                i += l;
+                total += l;
+            }
+            super::lasttab::Op::Seq2In(l) => {
+                j += l;
+                total += l;
+            }
+        }
+    }
+    Some(matches as f64 / total.max(1) as f64)
+}
+
 impl Repeat {
     pub fn id(&self) -> u16 {
         self.id
@@ -65,6 +111,9 @@ impl Repeat {
     pub fn end(&self) -> usize {
         self.end
     }
+    pub fn is_forward(&self) -> bool {
+        self.is_forward
+    }
     pub fn start_in_unit(&self) -> u16 {
         (self.start / super::UNIT_SIZE) as u16
     }
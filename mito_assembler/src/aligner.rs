@@ -0,0 +1,238 @@
+//! Alignment backends. Each backend aligns a set of query sequences to references,
+//! and returns the alignments as `LastTAB` records, where the seq1 is the reference
+//! and the seq2 is the query.
+use bio_utils::fasta;
+use last_tiling::mapper::{Mapper, MapperConfig};
+use last_tiling::LastTAB;
+use log::debug;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+pub trait Aligner {
+    /// Align `query`<FASTA> to `reference`<FASTA>.
+    fn align(&self, query: &Path, reference: &Path) -> std::io::Result<Vec<LastTAB>>;
+}
+
+fn other_error(msg: String) -> Error {
+    Error::new(ErrorKind::Other, msg)
+}
+
+fn check_status(name: &str, output: &std::process::Output) -> std::io::Result<()> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(other_error(format!("{} failed:{}", name, stderr)))
+    }
+}
+
+fn to_str(path: &Path) -> std::io::Result<&str> {
+    path.to_str()
+        .ok_or_else(|| other_error(format!("{:?} is not a valid UTF-8 path", path)))
+}
+
+/// Alignment by LAST. The database and the trained parameters are
+/// put in a scratch directory created under `tmpdir`, which is removed afterwards.
+#[derive(Debug, Clone)]
+pub struct Last {
+    threads: usize,
+    train: bool,
+    tmpdir: PathBuf,
+}
+
+impl Last {
+    pub fn new<P: AsRef<Path>>(threads: usize, tmpdir: P) -> Self {
+        Self {
+            threads,
+            train: false,
+            tmpdir: tmpdir.as_ref().to_path_buf(),
+        }
+    }
+    /// If `train` is true, train the score parameters by `last-train`, then
+    /// split the alignments by `last-split`.
+    pub fn with_training(mut self, train: bool) -> Self {
+        self.train = train;
+        self
+    }
+    fn align_with_training(
+        &self,
+        db_name: &str,
+        dir: &Path,
+        reads: &str,
+    ) -> std::io::Result<Vec<LastTAB>> {
+        let p = format!("{}", self.threads);
+        let last_train = Command::new("last-train")
+            .args(&["-P", &p, "-Q", "0", db_name, reads])
+            .output()?;
+        check_status("last-train", &last_train)?;
+        let param = dir.join("param.par");
+        std::fs::write(&param, &last_train.stdout)?;
+        let param = to_str(&param)?;
+        let mut lastal = Command::new("lastal")
+            .args(&[
+                "-f", "maf", "-P", &p, "-R", "00", "-Q", "0", "-p", param, db_name, reads,
+            ])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let raw_alignment = lastal
+            .stdout
+            .take()
+            .ok_or_else(|| other_error("failed to catch lastal output".to_string()))?;
+        let mut last_split = Command::new("last-split")
+            .stdin(Stdio::from(raw_alignment))
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let raw_alignment = last_split
+            .stdout
+            .take()
+            .ok_or_else(|| other_error("failed to catch last-split output".to_string()))?;
+        let maf_convert = Command::new("maf-convert")
+            .args(&["tab", "--join", "1000"])
+            .stdin(Stdio::from(raw_alignment))
+            .output()?;
+        if !lastal.wait()?.success() {
+            return Err(other_error("lastal failed".to_string()));
+        }
+        if !last_split.wait()?.success() {
+            return Err(other_error("last-split failed".to_string()));
+        }
+        check_status("maf-convert", &maf_convert)?;
+        Ok(String::from_utf8_lossy(&maf_convert.stdout)
+            .lines()
+            .filter(|e| !e.starts_with('#'))
+            .filter_map(LastTAB::from_line)
+            .collect())
+    }
+    fn align_without_training(&self, db_name: &str, reads: &str) -> std::io::Result<Vec<LastTAB>> {
+        let p = format!("{}", self.threads);
+        let lastal = Command::new("lastal")
+            .args(&["-f", "tab", "-P", &p, "-R", "00", "-Q", "0", db_name, reads])
+            .output()?;
+        check_status("lastal", &lastal)?;
+        Ok(String::from_utf8_lossy(&lastal.stdout)
+            .lines()
+            .filter(|e| !e.starts_with('#'))
+            .filter_map(LastTAB::from_line)
+            .collect())
+    }
+}
+
+impl Aligner for Last {
+    fn align(&self, query: &Path, reference: &Path) -> std::io::Result<Vec<LastTAB>> {
+        std::fs::create_dir_all(&self.tmpdir)?;
+        // The directory would be removed when `dir` is dropped.
+        let dir = tempfile::Builder::new()
+            .prefix("mmmm_last")
+            .tempdir_in(&self.tmpdir)?;
+        debug!("Creating {:?}.", dir.path());
+        let (reference, reads) = (to_str(reference)?, to_str(query)?);
+        let db_name = dir.path().join("reference");
+        let db_name = to_str(&db_name)?;
+        let lastdb = Command::new("lastdb")
+            .args(&["-R", "00", "-Q", "0", db_name, reference])
+            .output()?;
+        check_status("lastdb", &lastdb)?;
+        let alignments = if self.train {
+            self.align_with_training(db_name, dir.path(), reads)
+        } else {
+            self.align_without_training(db_name, reads)
+        };
+        debug!("Removing {:?}", dir.path());
+        dir.close()?;
+        alignments
+    }
+}
+
+/// Alignment by an external command writing PAF with CIGAR strings(`cg:Z:` tag) to the stdout,
+/// such as `minimap2 -c -x map-pb`. The reference and the query are appended to the arguments.
+#[derive(Debug, Clone)]
+pub struct PafCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl PafCommand {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+    /// Construct from a command line such as "minimap2 -c -x map-pb -t 24".
+    pub fn from_command_line(command: &str) -> Option<Self> {
+        let mut command = command.split_whitespace();
+        let program = command.next()?;
+        let args: Vec<_> = command.collect();
+        Some(Self::new(program, &args))
+    }
+}
+
+impl Aligner for PafCommand {
+    fn align(&self, query: &Path, reference: &Path) -> std::io::Result<Vec<LastTAB>> {
+        debug!("Invoking {} {:?}", self.program, self.args);
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(reference)
+            .arg(query)
+            .output()?;
+        check_status(&self.program, &output)?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(LastTAB::from_paf_line)
+            .collect())
+    }
+}
+
+/// Alignments computed beforehand. The query and the reference are ignored.
+/// The format is determined by the extension of the file.
+#[derive(Debug, Clone)]
+pub struct Precomputed {
+    path: PathBuf,
+}
+
+impl Precomputed {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Aligner for Precomputed {
+    fn align(&self, _query: &Path, _reference: &Path) -> std::io::Result<Vec<LastTAB>> {
+        debug!("Loading alignments from {:?}", self.path);
+        last_tiling::parse_alignment_file(&self.path)
+    }
+}
+
+/// Alignment by the built-in aligner(`last_tiling::mapper`). No external program is needed.
+/// If `split` is true, the alignments are split as `last-split` does.
+#[derive(Debug, Clone)]
+pub struct Builtin {
+    config: MapperConfig,
+}
+
+impl Builtin {
+    pub fn new(split: bool) -> Self {
+        let config = if split {
+            MapperConfig::default()
+        } else {
+            MapperConfig::self_alignment()
+        };
+        Self { config }
+    }
+    pub fn with_config(config: MapperConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Aligner for Builtin {
+    fn align(&self, query: &Path, reference: &Path) -> std::io::Result<Vec<LastTAB>> {
+        let reference = fasta::parse_into_vec(reference)?;
+        let query = fasta::parse_into_vec(query)?;
+        debug!("Indexing {} references", reference.len());
+        let mapper = Mapper::new(&reference, self.config.clone());
+        Ok(mapper.map_reads(&query))
+    }
+}
@@ -21,7 +21,10 @@ fn main() {
     let (k, thr) = (5, 15);
     let mut labels: Vec<_> = labels.into_iter().collect();
     labels.sort_by_key(|x| x.0);
-    let result = last_decompose::assemble::assemble_reads(&chunked_reads, k, thr);
+    let contacts = last_decompose::contacts::Contacts::default();
+    let contact_thr = last_decompose::DecomposeParams::default().contact_thr;
+    let result =
+        last_decompose::assemble::assemble_reads(&chunked_reads, &contacts, k, thr, contact_thr);
     for (id, asn) in result.0.iter() {
         match (id2desc[id].as_ref(), asn) {
             (Some(desc), Some(asn)) => println!("{}\t{}", asn, desc),
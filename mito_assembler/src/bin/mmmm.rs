@@ -9,6 +9,15 @@ extern crate env_logger;
 extern crate mito_assembler;
 use bio_utils::fasta;
 use clap::{App, Arg, SubCommand};
+use last_decompose::assemble::ChunkedRead;
+use last_decompose::consensus::WindowStability;
+use last_decompose::constraints::{Constraints, Violation};
+use last_decompose::gibbs_trace::{Convergence, WindowTrace};
+use last_decompose::recombination_graph::RecombinationGraph;
+use last_decompose::{recombination, stoichiometry, sv_export, windows, Cluster, DecomposeParams};
+use last_tiling::{EncodedRead, LastTAB};
+use mito_assembler::aligner::{self, Aligner};
+use mito_assembler::checkpoint::{Checkpoints, Key};
 use mito_assembler::dump_viewer;
 use std::collections::HashMap;
 use std::io::{BufRead, BufReader, BufWriter, Write};
@@ -50,7 +59,7 @@ fn subcommand_create_viewer() -> App<'static, 'static> {
                 .short("a")
                 .long("read_aln")
                 .value_name("ALIGNMENT(Read->C)")
-                .help("Alignment from reads to contigs<LastTAB>")
+                .help("Alignment from reads to contigs<LastTAB|PAF|SAM|BAM>")
                 .takes_value(true),
         )
         .arg(
@@ -59,7 +68,7 @@ fn subcommand_create_viewer() -> App<'static, 'static> {
                 .short("l")
                 .long("contig_aln")
                 .value_name("ALIGNMENT(C->Ref)")
-                .help("Alignments from contigs to the reference<LastTAB>")
+                .help("Alignments from contigs to the reference<LastTAB|PAF|SAM|BAM>")
                 .takes_value(true),
         )
         .arg(
@@ -169,16 +178,233 @@ fn subcommand_decompose() -> App<'static, 'static> {
                 .required(false)
                 .help("Do not exec decompose. Just detects SVs."),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .required(false)
+                .value_name("CONFIG")
+                .help("Parameters of the decomposition<TOML|JSON>. The effective parameters are written to OUT DIR/params.toml")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("param")
+                .long("param")
+                .required(false)
+                .value_name("KEY=VALUE")
+                .help("Overwrite a parameter of the decomposition, e.g., --param window_size=30")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("model_selection")
+                .long("model-selection")
+                .required(false)
+                .value_name("CRITERION")
+                .help("Select the number of clusters in each window by a criterion. Takes precedence over --config and --param.")
+                .possible_values(&["fixed", "bic", "aic"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("window_layout")
+                .long("window-layout")
+                .required(false)
+                .value_name("LAYOUT")
+                .help("Lay out windows by a fixed number of units, by coverage, or split at critical regions. Takes precedence over --config and --param.")
+                .possible_values(&["fixed", "coverage", "breakpoint"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("phasing")
+                .long("phasing")
+                .required(false)
+                .value_name("DECODING")
+                .help("Decode each read through the clusters of the windows by a hidden Markov model before the assembly. Takes precedence over --config and --param.")
+                .possible_values(&["none", "viterbi", "forward-backward"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("merge_strategy")
+                .long("merge-strategy")
+                .required(false)
+                .value_name("STRATEGY")
+                .help("Merge the clusters of the windows by the de Bruijn graph or by bipartite matchings. Takes precedence over --config and --param.")
+                .possible_values(&["dbg", "bipartite"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fdr")
+                .long("fdr")
+                .required(false)
+                .value_name("FDR")
+                .help("Test critical regions against the background of chimeric reads and keep the ones passing this false discovery rate, instead of the coverage threshold. Takes precedence over --config and --param.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("consensus_seeds")
+                .long("consensus-seeds")
+                .required(false)
+                .value_name("SEEDS")
+                .help("Run the clustering of each window with this many seeds, and report the stability of the consensus. Takes precedence over --config and --param.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace_dir")
+                .long("trace-dir")
+                .required(false)
+                .value_name("DIR")
+                .help("Write the trace of the Gibbs sampler on each window and a summary of the windows not converged into DIR.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hic")
+                .long("hic")
+                .required(false)
+                .value_name("DATASET(JSON)")
+                .help("DataSet with Hi-C pairs or edges, used to favour putting linked reads in the same cluster.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("constraints")
+                .long("constraints")
+                .required(false)
+                .value_name("CONSTRAINTS(TSV)")
+                .help("Must-link, cannot-link, and label constraints on reads. Each line is one of \"must-link<TAB>read<TAB>read\", \"cannot-link<TAB>read<TAB>read\", or \"label<TAB>read<TAB>structure\".")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("read_alignments")
+                .long("read-alignments")
+                .required(false)
+                .value_name("ALIGNMENT(Read->Ref)")
+                .help("Precomputed alignments from reads to the reference<LastTAB|PAF|SAM|BAM>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("self_alignments")
+                .long("self-alignments")
+                .required(false)
+                .value_name("ALIGNMENT(Ref->Ref)")
+                .help("Precomputed self-vs-self alignments of the reference<LastTAB|PAF|SAM|BAM>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("paf_command")
+                .long("paf-command")
+                .required(false)
+                .value_name("COMMAND")
+                .help("Aligner writing PAF with cg:Z tags, used instead of LAST. e.g., \"minimap2 -c -x map-pb\"")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("builtin_aligner")
+                .long("builtin-aligner")
+                .required(false)
+                .help("Use the built-in aligner instead of LAST."),
+        )
+        .arg(
+            Arg::with_name("tmpdir")
+                .long("tmpdir")
+                .required(false)
+                .value_name("TEMP DIR")
+                .help("Directory where scratch files are created. [default: system's temp dir]")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("resume")
                 .long("resume")
                 .required(false)
-                .value_name("LOG FILE")
-                .help("Log files <JSON>")
-                .takes_value(true),
+                .help("Resume from the checkpoints in OUT DIR/checkpoints. Stages whose inputs or parameters have changed are recomputed."),
         )
 }
 
+// Select the alignment backend. If `precomputed` is supplied, the alignments are loaded from it.
+// Otherwise, use the PAF command if supplied, or LAST.
+fn get_aligner(
+    matches: &clap::ArgMatches,
+    precomputed: &str,
+    threads: usize,
+    train: bool,
+) -> std::io::Result<Box<dyn Aligner>> {
+    if let Some(file) = matches.value_of(precomputed) {
+        return Ok(Box::new(aligner::Precomputed::new(file)));
+    }
+    if let Some(command) = matches.value_of("paf_command") {
+        return match aligner::PafCommand::from_command_line(command) {
+            Some(res) => Ok(Box::new(res)),
+            None => {
+                let msg = format!("Invalid command:{}", command);
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg))
+            }
+        };
+    }
+    if matches.is_present("builtin_aligner") {
+        return Ok(Box::new(aligner::Builtin::new(train)));
+    }
+    let tmpdir = matches
+        .value_of("tmpdir")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    Ok(Box::new(
+        aligner::Last::new(threads, tmpdir).with_training(train),
+    ))
+}
+
+// Load the parameters from the configuration file, then overwrite them by `--param`s.
+fn get_params(matches: &clap::ArgMatches) -> std::io::Result<DecomposeParams> {
+    let mut params = match matches.value_of("config") {
+        Some(file) => DecomposeParams::from_file(file)?,
+        None => DecomposeParams::default(),
+    };
+    for param in matches.values_of("param").into_iter().flatten() {
+        let mut kv = param.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) => params.set(key.trim(), value.trim())?,
+            _ => {
+                let msg = format!("Invalid parameter:{}. It should be KEY=VALUE", param);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+            }
+        }
+    }
+    if let Some(criterion) = matches.value_of("model_selection") {
+        params.model_selection = criterion.parse()?;
+    }
+    if let Some(layout) = matches.value_of("window_layout") {
+        params.window_layout = layout.parse()?;
+    }
+    if let Some(phasing) = matches.value_of("phasing") {
+        params.phasing = phasing.parse()?;
+    }
+    if let Some(strategy) = matches.value_of("merge_strategy") {
+        params.merge_strategy = strategy.parse()?;
+    }
+    if let Some(fdr) = matches.value_of("fdr") {
+        params.set("fdr", fdr)?;
+        params.breakpoint_test = last_decompose::BreakpointTest::Poisson;
+    }
+    if let Some(seeds) = matches.value_of("consensus_seeds") {
+        params.set("consensus_seeds", seeds)?;
+    }
+    if matches.is_present("trace_dir") {
+        params.gibbs_trace = true;
+    }
+    Ok(params)
+}
+
+// Describe the alignment backend selected by `get_aligner`, to be used as a part of a checkpoint key.
+fn aligner_key(matches: &clap::ArgMatches, precomputed: &str) -> std::io::Result<String> {
+    if let Some(file) = matches.value_of(precomputed) {
+        return Ok(Key::new("precomputed").consume_file(file)?.finish());
+    }
+    if let Some(command) = matches.value_of("paf_command") {
+        return Ok(format!("paf:{}", command));
+    }
+    if matches.is_present("builtin_aligner") {
+        return Ok("builtin".to_string());
+    }
+    Ok("last".to_string())
+}
+
 fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
     let level = match matches.occurrences_of("verbose") {
         0 => "warn",
@@ -207,18 +433,36 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
         .value_of("threads")
         .and_then(|num| num.parse().ok())
         .unwrap();
-    let alignments = {
-        let reads = matches.value_of("reads").unwrap();
-        let refr = matches.value_of("reference").unwrap();
-        mito_assembler::last_alignment_train(&reads, &refr, threads).unwrap()
-    };
-    let self_aln = {
-        let refr = matches.value_of("reference").unwrap();
-        mito_assembler::last_alignment(&refr, &refr, threads).unwrap()
-    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .unwrap();
     let output_dir = matches
         .value_of("outdir")
         .expect("please specify output directry.");
+    let params = get_params(matches)?;
+    std::fs::create_dir_all(output_dir)?;
+    std::fs::write(format!("{}/params.toml", output_dir), params.to_toml())?;
+    let checkpoints = Checkpoints::new(
+        format!("{}/checkpoints", output_dir),
+        matches.is_present("resume"),
+    )?;
+    let reads_path = std::path::Path::new(matches.value_of("reads").unwrap());
+    let refr_path = std::path::Path::new(matches.value_of("reference").unwrap());
+    let alignment_key = Key::new("alignment")
+        .consume_file(reads_path)?
+        .consume_file(refr_path)?
+        .consume(aligner_key(matches, "read_alignments")?)
+        .consume(aligner_key(matches, "self_alignments")?)
+        .finish();
+    let (alignments, self_aln): (Vec<LastTAB>, Vec<LastTAB>) =
+        checkpoints.load_or_run("alignment", &alignment_key, || {
+            let aligner = get_aligner(matches, "read_alignments", threads, true)?;
+            let alignments = aligner.align(reads_path, refr_path)?;
+            let aligner = get_aligner(matches, "self_alignments", threads, false)?;
+            let self_aln = aligner.align(refr_path, refr_path)?;
+            Ok((alignments, self_aln))
+        })?;
     debug!("All files opened.");
     let config = last_decompose::error_profile::summarize_tab(&alignments, &reads, &reference);
     let cluster_num: usize = matches
@@ -229,14 +473,27 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
         .value_of("limit")
         .and_then(|num| num.parse().ok())
         .unwrap();
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(threads)
-        .build_global()
-        .unwrap();
     let no_merge = matches.is_present("no_merge");
     let contigs = last_tiling::contig::Contigs::new(reference);
-    let encoded_reads = last_tiling::encoding(&reads, &contigs, &alignments);
-    let initial_clusters = last_decompose::initial_clusters(&encoded_reads, &contigs);
+    let encoding_key = Key::new("encoding").consume(&alignment_key).finish();
+    let encoded_reads: Vec<EncodedRead> =
+        checkpoints.load_or_run("encoding", &encoding_key, || {
+            Ok(last_tiling::encoding(&reads, &contigs, &alignments))
+        })?;
+    let initial_clusters_key = Key::new("initial_clusters")
+        .consume(&encoding_key)
+        .consume(params.key_of(DecomposeParams::INITIAL_CLUSTERS))
+        .finish();
+    let repeats = last_tiling::into_repeats(&self_aln, &contigs);
+    let initial_clusters: Vec<Cluster> =
+        checkpoints.load_or_run("initial_clusters", &initial_clusters_key, || {
+            Ok(last_decompose::initial_clusters(
+                &encoded_reads,
+                &contigs,
+                &repeats,
+                &params,
+            ))
+        })?;
     debug!("Initial clusters constructed");
     {
         if let Err(why) = std::fs::create_dir_all(output_dir) {
@@ -261,9 +518,37 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
                 let count = cp.reads().len();
                 let (s1, e1) = cp.contig1().range();
                 let (s2, e2) = cp.contig2().range();
-                writeln!(&mut writer, "{}\t{}\t{}\t{}\t{}", s1, e1, s2, e2, count)?;
+                let refined = |x: Option<usize>| match x {
+                    Some(x) => x.to_string(),
+                    None => "NA".to_string(),
+                };
+                let (r1, r2) = (
+                    refined(cp.contig1().refined()),
+                    refined(cp.contig2().refined()),
+                );
+                let line = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}", s1, e1, s2, e2, count, r1, r2);
+                writeln!(&mut writer, "{}\t{}", line, cp.rearrangement())?;
             }
         }
+        let file = format!("{}/critical_regions.vcf", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(
+            &mut writer,
+            "{}",
+            sv_export::to_vcf(&initial_clusters, &contigs)
+        )?;
+        let file = format!("{}/critical_regions.bedpe", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(
+            &mut writer,
+            "{}",
+            sv_export::to_bedpe(&initial_clusters, &contigs)
+        )?;
+        let windows =
+            windows::layout_of_reads(&encoded_reads, &initial_clusters, &contigs, &params);
+        let file = format!("{}/windows.tsv", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(&mut writer, "{}", windows::to_tsv(&windows, &contigs))?;
         let dir = format!("{}/viewer", output_dir);
         if let Err(why) = std::fs::create_dir_all(&dir) {
             error!("Error Occured while outputing reads.");
@@ -272,11 +557,40 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
             error!("Shutting down...");
             std::process::exit(1);
         }
-        let repeats = last_tiling::into_repeats(&self_aln, &contigs);
         let file = format!("{}/repeats.json", dir);
         let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        let recombinations =
+            recombination::recombination_frequencies(&encoded_reads, &repeats, &params);
+        let graph = RecombinationGraph::new(&initial_clusters, &encoded_reads, &contigs, &repeats);
         let repeats = serde_json::ser::to_string(&repeats).unwrap();
         writeln!(&mut writer, "{}", repeats)?;
+        let file = format!("{}/recombination.json", dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        let json = serde_json::ser::to_string(&recombinations).unwrap();
+        writeln!(&mut writer, "{}", json)?;
+        let file = format!("{}/recombination.tsv", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(
+            &mut writer,
+            "{}",
+            recombination::RepeatRecombination::HEADER
+        )?;
+        for recomb in recombinations.iter() {
+            writeln!(&mut writer, "{}", recomb)?;
+        }
+        let circles = graph.circles();
+        debug!("{} candidate circles", circles.len());
+        let file = format!("{}/circles.tsv", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(&mut writer, "{}", graph.to_tsv(&circles, &contigs))?;
+        let file = format!("{}/circles.gfa", output_dir);
+        let mut writer = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(&mut writer, "{}", graph.to_gfa(&circles, &contigs))?;
+        let file = format!("{}/circles.fasta", output_dir);
+        let mut wtr = fasta::Writer::new(std::fs::File::create(&file)?);
+        for record in graph.to_fasta(&circles, &contigs) {
+            wtr.write_record(&record)?;
+        }
         let file = format!("{}/circos.html", dir);
         let mut writer = BufWriter::new(std::fs::File::create(&file)?);
         writeln!(&mut writer, "{}", mito_assembler::template::TEMPLATE)?;
@@ -291,52 +605,135 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
     }
     let cl = cluster_num;
     debug!("Profiled Error Rates:{}", config);
-    let results: HashMap<String, u8> = if !no_merge {
-        let settings = last_decompose::DecomposeConfig::new(5, 15);
-        let result = if matches.is_present("resume") {
-            let chunked_reads: Vec<last_decompose::assemble::ChunkedRead> = matches
-                .value_of("resume")
-                .and_then(|e| std::fs::File::open(e).map(BufReader::new).ok())
-                .and_then(|e| serde_json::de::from_reader(e).ok())
-                .unwrap();
-            use last_decompose::*;
-            let (assignments, gfa, contigs) = assemble::assemble_reads(&chunked_reads, 5, 15);
-            DecomposedResult {
-                assignments,
-                gfa,
-                contigs,
-                reads: chunked_reads,
+    let mut abundances = vec![];
+    let constraints = match matches.value_of("constraints") {
+        Some(file) => Constraints::from_file(file)?,
+        None => Constraints::default(),
+    };
+    let (results, posteriors): (HashMap<String, u8>, HashMap<String, Vec<f64>>) = if !no_merge {
+        let mut clustering_key = Key::new("clustering")
+            .consume(&initial_clusters_key)
+            .consume(params.key_of(DecomposeParams::CLUSTERING))
+            .consume(cluster_num)
+            .consume(limit);
+        if let Some(file) = matches.value_of("hic") {
+            clustering_key = clustering_key.consume_file(file)?;
+        }
+        if let Some(file) = matches.value_of("constraints") {
+            clustering_key = clustering_key.consume_file(file)?;
+        }
+        let clustering_key = clustering_key.finish();
+        // The contacts are used in the clustering and in the assembly.
+        let contacts = match matches.value_of("hic") {
+            Some(file) => {
+                let rdr = BufReader::new(std::fs::File::open(file)?);
+                let dataset: definitions::DataSet = serde_json::de::from_reader(rdr)?;
+                last_decompose::contacts::from_dataset(&dataset, &encoded_reads, &contigs)
             }
-        } else {
-            let result = last_decompose::decompose(
-                encoded_reads,
-                &initial_clusters,
-                &contigs,
-                &config,
-                cl,
-                limit,
-                &settings,
+            None => last_decompose::contacts::Contacts::default(),
+        };
+        debug!("{} contacts between reads", contacts.len());
+        type Clustering = (Vec<ChunkedRead>, Vec<WindowStability>, Vec<WindowTrace>);
+        let (chunked_reads, mut stability, traces): Clustering =
+            checkpoints.load_or_run("clustering", &clustering_key, || {
+                Ok(last_decompose::cluster_reads(
+                    &encoded_reads,
+                    &initial_clusters,
+                    &contigs,
+                    &contacts,
+                    &constraints,
+                    &config,
+                    cl,
+                    limit,
+                    &params,
+                ))
+            })?;
+        let filename = format!("{}/encoded_reads.json", output_dir);
+        if let Ok(mut wtr) = std::fs::File::create(&filename).map(std::io::BufWriter::new) {
+            if let Err(w) = serde_json::ser::to_writer_pretty(&mut wtr, &chunked_reads) {
+                debug!("{:?}", w);
+            }
+        }
+        // The threshold is not a part of the key.
+        stability
+            .iter_mut()
+            .for_each(|s| s.judge(params.stability_thr));
+        if !stability.is_empty() {
+            let unstable = stability.iter().filter(|s| s.unstable).count();
+            info!(
+                "{} out of {} windows are unstable",
+                unstable,
+                stability.len()
             );
-            let filename = format!("{}/encoded_reads.json", output_dir);
-            if let Ok(mut wtr) = std::fs::File::create(&filename).map(std::io::BufWriter::new) {
-                if let Err(w) = serde_json::ser::to_writer_pretty(&mut wtr, &result.reads) {
-                    debug!("{:?}", w);
-                }
+            let file = format!("{}/stability.tsv", output_dir);
+            let mut wtr = BufWriter::new(std::fs::File::create(&file)?);
+            writeln!(&mut wtr, "{}", WindowStability::HEADER)?;
+            for window in stability.iter() {
+                writeln!(&mut wtr, "{}", window)?;
             }
-            result
-        };
+        }
+        if let Some(dir) = matches.value_of("trace_dir") {
+            std::fs::create_dir_all(dir)?;
+            for trace in traces.iter() {
+                let file = format!("{}/window_{}.tsv", dir, trace.window);
+                let mut wtr = BufWriter::new(std::fs::File::create(&file)?);
+                trace.write_tsv(&mut wtr)?;
+            }
+            let summary: Vec<Convergence> = traces.iter().map(|t| t.summary()).collect();
+            let unconverged = summary.iter().filter(|s| s.unconverged > 0).count();
+            info!(
+                "{} out of {} windows hit the iteration limit without stabilising",
+                unconverged,
+                summary.len()
+            );
+            let file = format!("{}/summary.tsv", dir);
+            let mut wtr = BufWriter::new(std::fs::File::create(&file)?);
+            writeln!(&mut wtr, "{}", Convergence::HEADER)?;
+            for window in summary.iter() {
+                writeln!(&mut wtr, "{}", window)?;
+            }
+        }
+        let result = last_decompose::merge_chunked_reads(
+            chunked_reads,
+            &encoded_reads,
+            &initial_clusters,
+            &contacts,
+            &params,
+        );
         // Output gfa
         let filename = format!("{}/scaffolds.gfa", output_dir);
         let mut wtr = std::fs::File::create(&filename).map(std::io::BufWriter::new)?;
         writeln!(&mut wtr, "{}", result.gfa)?;
-        result
+        if !result.phases.is_empty() {
+            let filename = format!("{}/phasing.tsv", output_dir);
+            let mut wtr = std::fs::File::create(&filename).map(std::io::BufWriter::new)?;
+            writeln!(&mut wtr, "{}", last_decompose::phasing::Phase::HEADER)?;
+            for phase in result.phases.iter() {
+                writeln!(&mut wtr, "{}", phase)?;
+            }
+        }
+        // Output contigs
+        mito_assembler::dump_contigs(output_dir, &result.contigs, &result.assignments)?;
+        abundances = stoichiometry::structure_abundances(
+            &result.reads,
+            &result.assignments,
+            params.bootstrap_num,
+        );
+        let posteriors: HashMap<_, _> = result
+            .assignments
+            .iter()
+            .zip(result.posteriors)
+            .map(|((id, _), posterior)| (id.clone(), posterior))
+            .collect();
+        let results = result
             .assignments
             .into_iter()
             .filter_map(|(id, asn)| asn.map(|x| (id, x)))
-            .collect()
+            .collect();
+        (results, posteriors)
     } else {
         use last_decompose::find_breakpoint::ReadClassify;
-        encoded_reads
+        let results = encoded_reads
             .iter()
             .filter_map(|r| {
                 initial_clusters
@@ -345,12 +742,75 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
                     .nth(0)
                     .map(|cl| (r.id().to_string(), cl.id as u8))
             })
-            .collect()
+            .collect();
+        (results, HashMap::new())
+    };
+    if !constraints.is_empty() {
+        let violations = constraints.violations(&results);
+        info!(
+            "{} out of {} constraints are violated",
+            violations.len(),
+            constraints.len()
+        );
+        let file = format!("{}/constraint_violations.tsv", output_dir);
+        let mut wtr = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(&mut wtr, "{}", Violation::HEADER)?;
+        for violation in violations.iter() {
+            writeln!(&mut wtr, "{}", violation)?;
+        }
+    }
+    abundances.extend(stoichiometry::configuration_abundances(
+        &encoded_reads,
+        &initial_clusters,
+        &contigs,
+        params.bootstrap_num,
+    ));
+    {
+        let file = format!("{}/stoichiometry.tsv", output_dir);
+        let mut wtr = BufWriter::new(std::fs::File::create(&file)?);
+        writeln!(&mut wtr, "{}", stoichiometry::Abundance::HEADER)?;
+        for abundance in abundances.iter() {
+            writeln!(&mut wtr, "{}", abundance)?;
+        }
+    }
+    // The posterior probability on the assigned cluster.
+    // Reads without posterior probabilities(i.e., --no_merge) are assigned with confidence 1.
+    let confidence = |id: &str, cluster: u8| -> f64 {
+        match posteriors.get(id) {
+            Some(posterior) => posterior.get(cluster as usize).copied().unwrap_or(0.),
+            None => 1.,
+        }
+    };
+    let (results, ambiguous): (HashMap<_, _>, HashMap<_, _>) = results
+        .into_iter()
+        .partition(|(id, cluster)| confidence(id, *cluster) >= params.min_confidence);
+    info!(
+        "{} reads are ambiguous(confidence < {})",
+        ambiguous.len(),
+        params.min_confidence
+    );
+    let format_posterior = |id: &str| -> String {
+        match posteriors.get(id) {
+            Some(posterior) if !posterior.is_empty() => {
+                let posterior: Vec<_> = posterior.iter().map(|p| format!("{:.3}", p)).collect();
+                posterior.join(",")
+            }
+            _ => "NA".to_string(),
+        }
     };
     let mut decomposed: HashMap<u8, Vec<&fasta::Record>> = HashMap::new();
-    let unassigned = results.values().copied().max().unwrap_or(0) + 1;
+    let mut ambiguous_reads: Vec<&fasta::Record> = vec![];
+    let unassigned = results
+        .values()
+        .chain(ambiguous.values())
+        .copied()
+        .max()
+        .unwrap_or(0)
+        + 1;
     for read in &reads {
-        if let Some(cluster) = results.get(read.id()) {
+        if ambiguous.contains_key(read.id()) {
+            ambiguous_reads.push(read);
+        } else if let Some(cluster) = results.get(read.id()) {
             let cls = decomposed.entry(*cluster).or_insert(vec![]);
             cls.push(read);
         } else {
@@ -360,6 +820,7 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
     }
     let readlist = format!("{}/readlist.tsv", output_dir);
     let mut readlist = BufWriter::new(std::fs::File::create(readlist)?);
+    writeln!(&mut readlist, "#cluster\tid\tdesc\tconfidence\tposterior")?;
     let decomposed: HashMap<u8, Vec<_>> = decomposed.into_iter().collect();
     {
         for (&cluster_id, reads) in decomposed.iter() {
@@ -373,16 +834,34 @@ fn decompose(matches: &clap::ArgMatches) -> std::io::Result<()> {
             };
             let mut wtr = fasta::Writer::new(wtr);
             for read in reads {
-                let line = match read.desc() {
-                    Some(d) => format!("{}\t{}\t{}", cluster_id, read.id(), d),
-                    None => format!("{}\t{}\tNoDesc", cluster_id, read.id()),
+                let conf = match results.get(read.id()) {
+                    Some(&cluster) => confidence(read.id(), cluster),
+                    None => 0.,
                 };
+                let desc = read.desc().map(|d| d.to_string());
+                let desc = desc.unwrap_or_else(|| "NoDesc".to_string());
+                let posterior = format_posterior(read.id());
+                let (id, cl) = (read.id(), cluster_id);
+                let line = format!("{}\t{}\t{}\t{:.3}\t{}", cl, id, desc, conf, posterior);
+                writeln!(&mut readlist, "{}", line)?;
+                wtr.write_record(read)?;
+            }
+        }
+        if !ambiguous_reads.is_empty() {
+            let outpath = format!("{}/ambiguous.fasta", output_dir);
+            let mut wtr = fasta::Writer::new(std::fs::File::create(&outpath)?);
+            for read in ambiguous_reads {
+                let conf = confidence(read.id(), ambiguous[read.id()]);
+                let desc = read.desc().map(|d| d.to_string());
+                let desc = desc.unwrap_or_else(|| "NoDesc".to_string());
+                let posterior = format_posterior(read.id());
+                let id = read.id();
+                let line = format!("ambiguous\t{}\t{}\t{:.3}\t{}", id, desc, conf, posterior);
                 writeln!(&mut readlist, "{}", line)?;
                 wtr.write_record(read)?;
             }
         }
     }
-    let encoded_reads = last_tiling::encoding(&reads, &contigs, &alignments);
     let dir = format!("{}/viewer", output_dir);
     let file = format!("{}/data.json", dir);
     let mut writer = BufWriter::new(std::fs::File::create(&file)?);
@@ -422,14 +901,14 @@ fn create_viewer(matches: &clap::ArgMatches) -> std::io::Result<()> {
         .unwrap();
     let read_aln = matches
         .value_of("read_alignments")
-        .map(|file| match last_tiling::parse_tab_file(file) {
+        .map(|file| match last_tiling::parse_alignment_file(file) {
             Ok(res) => res,
             Err(why) => panic!("{}:{}", why, file),
         })
         .unwrap();
     let contig_aln = matches
         .value_of("contig_alignments")
-        .map(|file| match last_tiling::parse_tab_file(file) {
+        .map(|file| match last_tiling::parse_alignment_file(file) {
             Ok(res) => res,
             Err(why) => panic!("{}:{}", why, file),
         })
@@ -0,0 +1,137 @@
+//! Stage-level checkpoints of the decompose pipeline.
+//! Each stage is identified by its name and a key, the MD5 digest of its inputs and parameters.
+//! The result of a stage is written to `{dir}/{stage}.json`, then the key is written to
+//! `{dir}/{stage}.md5`. Thus, a checkpoint is valid only if the stage has completed and
+//! its inputs have not been changed since then.
+use log::{debug, info};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The version of the checkpoint format. Increment this when the
+/// serialized types or the semantics of the stages change.
+pub const VERSION: u32 = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Checkpoint<T> {
+    version: u32,
+    stage: String,
+    key: String,
+    data: T,
+}
+
+/// An accumulating MD5 digest. The key of a stage should be derived from
+/// the key of the previous stage, so that changes of inputs propagate downstream.
+pub struct Key {
+    context: md5::Context,
+}
+
+impl Key {
+    pub fn new(stage: &str) -> Self {
+        let mut context = md5::Context::new();
+        context.consume(format!("{}:{}", VERSION, stage));
+        Self { context }
+    }
+    /// Feed a string, such as a parameter or the key of the previous stage.
+    pub fn consume<T: std::fmt::Display>(mut self, value: T) -> Self {
+        self.context.consume(format!("{}\t", value));
+        self
+    }
+    /// Feed the content of a file.
+    pub fn consume_file<P: AsRef<Path>>(mut self, path: P) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            let len = reader.read(&mut buffer)?;
+            if len == 0 {
+                break;
+            }
+            self.context.consume(&buffer[..len]);
+        }
+        Ok(self)
+    }
+    pub fn finish(self) -> String {
+        format!("{:x}", self.context.compute())
+    }
+}
+
+/// Checkpoints in a directory. If `resume` is false, existing checkpoints are ignored and overwritten.
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    dir: PathBuf,
+    resume: bool,
+}
+
+impl Checkpoints {
+    pub fn new<P: AsRef<Path>>(dir: P, resume: bool) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            resume,
+        })
+    }
+    fn data_path(&self, stage: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", stage))
+    }
+    fn key_path(&self, stage: &str) -> PathBuf {
+        self.dir.join(format!("{}.md5", stage))
+    }
+    /// Load the result of `stage` if there is a valid checkpoint. Otherwise, run `f`
+    /// and write the result as a new checkpoint.
+    pub fn load_or_run<T, F>(&self, stage: &str, key: &str, f: F) -> std::io::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> std::io::Result<T>,
+    {
+        if self.resume {
+            if let Some(data) = self.load(stage, key) {
+                info!("Resumed {} from the checkpoint.", stage);
+                return Ok(data);
+            }
+            info!("No valid checkpoint for {}. Computing.", stage);
+        }
+        let data = f()?;
+        self.save(stage, key, data)
+    }
+    fn load<T: DeserializeOwned>(&self, stage: &str, key: &str) -> Option<T> {
+        let saved_key = std::fs::read_to_string(self.key_path(stage)).ok()?;
+        if saved_key.trim() != key {
+            debug!("The checkpoint of {} is outdated.", stage);
+            return None;
+        }
+        let reader = std::fs::File::open(self.data_path(stage))
+            .map(BufReader::new)
+            .ok()?;
+        let checkpoint: Checkpoint<T> = match serde_json::de::from_reader(reader) {
+            Ok(res) => res,
+            Err(why) => {
+                debug!("Failed to load the checkpoint of {}:{:?}", stage, why);
+                return None;
+            }
+        };
+        if checkpoint.version != VERSION || checkpoint.stage != stage || checkpoint.key != key {
+            return None;
+        }
+        Some(checkpoint.data)
+    }
+    fn save<T: Serialize>(&self, stage: &str, key: &str, data: T) -> std::io::Result<T> {
+        // Remove the old key first, so that a crash during writing never leaves a valid checkpoint.
+        let key_path = self.key_path(stage);
+        if key_path.exists() {
+            std::fs::remove_file(&key_path)?;
+        }
+        let checkpoint = Checkpoint {
+            version: VERSION,
+            stage: stage.to_string(),
+            key: key.to_string(),
+            data,
+        };
+        let mut wtr = std::fs::File::create(self.data_path(stage)).map(BufWriter::new)?;
+        serde_json::ser::to_writer(&mut wtr, &checkpoint)?;
+        wtr.flush()?;
+        std::fs::write(&key_path, key)?;
+        debug!("Wrote the checkpoint of {}.", stage);
+        Ok(checkpoint.data)
+    }
+}
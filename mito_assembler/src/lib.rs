@@ -3,124 +3,87 @@ use last_decompose::find_breakpoint::Cluster;
 use last_tiling::{Contigs, EncodedRead};
 use log::debug;
 use std::collections::HashMap;
+pub mod aligner;
+pub mod checkpoint;
 pub mod template;
+use aligner::Aligner;
 
+/// Align `query` to `refr` by LAST with parameter training and `last-split`.
+/// The scratch directory is created under the system's temporary directory.
 pub fn last_alignment_train<P: AsRef<std::path::Path>>(
     query: &P,
     refr: &P,
     thread: usize,
-) -> Option<Vec<last_tiling::LastTAB>> {
-    use rand::{thread_rng, Rng};
-    let mut rng = thread_rng();
-    let id: u64 = rng.gen::<u64>() % 10_000;
-    let mut c_dir = std::env::current_dir().ok()?;
-    use std::io::{BufWriter, Write};
-    c_dir.push(format!("{}", id));
-    debug!("Creating {:?}.", c_dir);
-    std::fs::create_dir(&c_dir).ok()?;
-    let reference = refr.as_ref().to_str()?;
-    let reads = query.as_ref().to_str()?;
-    let db_name = {
-        let mut temp = c_dir.clone();
-        temp.push("reference");
-        temp.into_os_string().into_string().unwrap()
-    };
-    // Create database - train - align
-    let lastdb = std::process::Command::new("lastdb")
-        .args(&["-R", "00", "-Q", "0", &db_name, reference])
-        .output()
-        .ok()?;
-    if !lastdb.status.success() {
-        eprintln!("{:?} or {:?} might be invalid.", db_name, reference);
-        panic!("lastdb-{}", String::from_utf8_lossy(&lastdb.stderr));
-    }
-    let p = format!("{}", thread);
-    let last_train = std::process::Command::new("last-train")
-        .args(&["-P", &p, "-Q", "0", &db_name, &reads])
-        .output()
-        .unwrap();
-    if !last_train.status.success() {
-        panic!("last-train-{}", String::from_utf8_lossy(&last_train.stderr));
-    }
-    let param = {
-        let mut param = c_dir.clone();
-        param.push("param.par");
-        let mut wtr = BufWriter::new(std::fs::File::create(&param).unwrap());
-        wtr.write_all(&last_train.stdout).unwrap();
-        wtr.flush().unwrap();
-        param.into_os_string().into_string().unwrap()
-    };
-    let lastal = std::process::Command::new("lastal")
-        .args(&[
-            "-f", "maf", "-P", &p, "-R", "00", "-Q", "0", "-p", &param, &db_name, &reads,
-        ])
-        .stdout(std::process::Stdio::piped())
-        .spawn()
-        .expect("failed to invoke lastal");
-    let raw_alignment = lastal.stdout.expect("failed to chatch lastal output");
-    let last_split = std::process::Command::new("last-split")
-        .stdin(std::process::Stdio::from(raw_alignment))
-        .stdout(std::process::Stdio::piped())
-        .spawn()
-        .expect("failed to invoke last-split");
-    let raw_alignment = last_split.stdout.expect("failed to exec last-split");
-    let maf_convert = std::process::Command::new("maf-convert")
-        .args(&["tab", "--join", "1000"])
-        .stdin(std::process::Stdio::from(raw_alignment))
-        .output()
-        .expect("failed to exec maf-convert");
-    let alignments: Vec<_> = String::from_utf8_lossy(&maf_convert.stdout)
-        .lines()
-        .filter(|e| !e.starts_with("#"))
-        .filter_map(|e| last_tiling::LastTAB::from_line(&e))
-        .collect();
-    debug!("Removing {:?}", c_dir);
-    std::fs::remove_dir_all(c_dir).ok()?;
-    Some(alignments)
+) -> std::io::Result<Vec<last_tiling::LastTAB>> {
+    let aligner = aligner::Last::new(thread, std::env::temp_dir()).with_training(true);
+    aligner.align(query.as_ref(), refr.as_ref())
 }
 
+/// Align `query` to `refr` by LAST with the default parameters.
+/// The scratch directory is created under the system's temporary directory.
 pub fn last_alignment<P: AsRef<std::path::Path>>(
     query: &P,
     refr: &P,
     threads: usize,
-) -> Option<Vec<last_tiling::LastTAB>> {
-    use rand::{thread_rng, Rng};
-    let mut rng = thread_rng();
-    let id: u64 = rng.gen::<u64>() % 10_000;
-    let mut c_dir = std::env::current_dir().ok()?;
-    c_dir.push(format!("{}", id));
-    debug!("Creating {:?}.", c_dir);
-    std::fs::create_dir(&c_dir).ok()?;
-    // Create reference and reads.
-    let (reference, reads) = (refr.as_ref().to_str()?, query.as_ref().to_str()?);
-    let db_name = {
-        let mut temp = c_dir.clone();
-        temp.push("reference");
-        temp.into_os_string().into_string().unwrap()
-    };
-    // Create database - train - align
-    let lastdb = std::process::Command::new("lastdb")
-        .args(&["-R", "00", "-Q", "0", &db_name, &reference])
-        .output()
-        .ok()?;
-    if !lastdb.status.success() {
-        panic!("lastdb-{}", String::from_utf8_lossy(&lastdb.stderr));
+) -> std::io::Result<Vec<last_tiling::LastTAB>> {
+    let aligner = aligner::Last::new(threads, std::env::temp_dir());
+    aligner.align(query.as_ref(), refr.as_ref())
+}
+
+/// Write the assembled contigs into `output_dir`.
+/// For each cluster, `{cluster}.contigs.fasta` would be created.
+/// All the contigs are also merged into `multipartite.fasta`, and
+/// `contigs.tsv` links each contig to its cluster, the number of reads,
+/// its length and whether it is circular.
+pub fn dump_contigs(
+    output_dir: &str,
+    contigs: &[bio_utils::fasta::Record],
+    assignments: &[(String, Option<u8>)],
+) -> std::io::Result<()> {
+    use bio_utils::fasta;
+    use last_decompose::assemble::{cluster_of_contig, is_circular_contig};
+    use std::io::{BufWriter, Write};
+    let read_counts: HashMap<u8, usize> =
+        assignments
+            .iter()
+            .filter_map(|x| x.1)
+            .fold(HashMap::new(), |mut x, asn| {
+                *x.entry(asn).or_default() += 1;
+                x
+            });
+    let file = format!("{}/multipartite.fasta", output_dir);
+    let mut multipartite = fasta::Writer::new(std::fs::File::create(&file)?);
+    let file = format!("{}/contigs.tsv", output_dir);
+    let mut summary = BufWriter::new(std::fs::File::create(&file)?);
+    writeln!(&mut summary, "contig\tcluster\treads\tlength\tis_circular")?;
+    let mut per_cluster: HashMap<u8, Vec<&fasta::Record>> = HashMap::new();
+    for contig in contigs {
+        let cluster = match cluster_of_contig(contig.id()) {
+            Some(res) => res,
+            None => {
+                debug!("Can not determine the cluster of {}", contig.id());
+                continue;
+            }
+        };
+        let count = read_counts.get(&cluster).copied().unwrap_or(0);
+        let (len, is_circular) = (contig.seq().len(), is_circular_contig(contig));
+        let id = contig.id();
+        writeln!(
+            &mut summary,
+            "{}\t{}\t{}\t{}\t{}",
+            id, cluster, count, len, is_circular
+        )?;
+        multipartite.write_record(contig)?;
+        per_cluster.entry(cluster).or_default().push(contig);
     }
-    let p = format!("{}", threads);
-    let lastal = std::process::Command::new("lastal")
-        .args(&[
-            "-f", "tab", "-P", &p, "-R", "00", "-Q", "0", &db_name, &reads,
-        ])
-        .output()
-        .expect("failed to invoke lastal");
-    let alignments: Vec<_> = String::from_utf8_lossy(&lastal.stdout)
-        .lines()
-        .filter(|e| !e.starts_with("#"))
-        .filter_map(|e| last_tiling::LastTAB::from_line(&e))
-        .collect();
-    debug!("Removing {:?}", c_dir);
-    std::fs::remove_dir_all(c_dir).ok()?;
-    Some(alignments)
+    for (cluster, contigs) in per_cluster {
+        let file = format!("{}/{}.contigs.fasta", output_dir, cluster);
+        let mut wtr = fasta::Writer::new(std::fs::File::create(&file)?);
+        for contig in contigs {
+            wtr.write_record(contig)?;
+        }
+    }
+    Ok(())
 }
 
 pub fn dump_viewer(
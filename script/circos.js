@@ -385,28 +385,28 @@ const contigToHTML = (contig) => {
 </div>`;
 };
 
-const criticalpairToHTML = (cp, idx) => {
-  const header = `<div class = critical-region><div>CP:${idx}</div>`;
+const criticalpairToHTML = (cp, idx, rearrangement) => {
+  const header = `<div class = critical-region><div>CP:${idx} (${rearrangement})</div>`;
   const contig1 = contigToHTML(cp["contig1"]);
   const contig2 = contigToHTML(cp["contig2"]);
   return header + contig1 + contig2 + "</div>";
 };
 
-const confluentregionToHTML = (cr, idx) => {
-  const header = `<div class = critical-region><div>CR:${idx}</div>`;
+const confluentregionToHTML = (cr, idx, rearrangement) => {
+  const header = `<div class = critical-region><div>CR:${idx} (${rearrangement})</div>`;
   const contig = contigToHTML(cr["pos"]);
   return header + contig + "</div>";
 };
 
-const crToHTML = (cr, cluster) => {
-  // Input: JSON object, Array
+const crToHTML = (cr, cluster, rearrangement) => {
+  // Input: JSON object, Array, String
   // Output: String
-  // Requirements: Critical region object
+  // Requirements: Critical region object and the class of its rearrangement
   // Return the HTML contents corresponds to the given cr.
   if (cr.hasOwnProperty("CP")) {
-    return criticalpairToHTML(cr["CP"], cluster);
+    return criticalpairToHTML(cr["CP"], cluster, rearrangement);
   } else if (cr.hasOwnProperty("CR")) {
-    return confluentregionToHTML(cr["CR"], cluster);
+    return confluentregionToHTML(cr["CR"], cluster, rearrangement);
   } else {
     console.log(`Error ${cr}`);
     return "Error";
@@ -746,7 +746,7 @@ const plotData = (dataset, repeats, unit_length) =>
             console.log(argmax, max, total_coverage);
             let contents = critical_regions.reduce((acc, member, idx) => {
               if (member.cluster == cluster) {
-                const html = crToHTML(member.cr, idx);
+                const html = crToHTML(member.cr, idx, member.rearrangement);
                 acc += html;
               }
               return acc;